@@ -1,6 +1,5 @@
-use looper::{Child, Core, ObjectId};
-use std::io::Read;
-use std::process::Command;
+use looper::{Child, Core, ObjectId, Stdout};
+use std::process::{Command, ExitStatus};
 
 // Tests running commands in sequence.
 
@@ -10,44 +9,46 @@ struct Sequence {
 }
 
 impl Sequence {
-    fn read(&mut self, _core: &mut Core) {
-        let mut s = String::new();
-        match self.child.stdout.read_to_string(&mut s) {
-            Ok(_) => {
-                if !s.is_empty() {
-                    eprintln!("output: {}", s)
-                }
-            }
-            Err(e) => eprintln!("Error reading from child: {}", e),
-        }
+    fn output(&mut self, line: String, _core: &mut Core) {
+        eprintln!("output: {}", line);
     }
 
-    fn handle_death_1(&mut self, core: &mut Core) {
-        eprintln!("handling death 1");
-        let echo = core
-            .spawn(Command::new("echo").arg("papapapapapap"))
-            .expect("echo executable must exist.")
-            .close_stdin();
-        core.register_reaper(&echo, self.id, Sequence::handle_death_2);
-        core.register_reader(&echo.stdout, self.id, Sequence::read);
-        self.child = echo;
+    fn handle_death_1(&mut self, exit_status: Option<ExitStatus>, core: &mut Core) {
+        eprintln!("handling death 1, exit status: {:?}", exit_status);
+        core.spawn_with_handlers(
+            Command::new("echo").arg("papapapapapap"),
+            self.id,
+            Sequence::output,
+            Sequence::output,
+            Sequence::handle_death_2,
+        )
+        .expect("echo executable must exist.");
     }
 
-    fn handle_death_2(&mut self, core: &mut Core) {
+    fn handle_death_2(&mut self, _exit_status: Option<ExitStatus>, core: &mut Core) {
         core.exit();
         eprintln!("called exit.");
     }
 }
 
+fn stdout(sequence: &mut Sequence) -> &mut Stdout {
+    &mut sequence.child.stdout
+}
+
 fn main() {
     let mut core = Core::new();
-    let e1 = core
-        .spawn(Command::new("echo").arg("mamamamamam"))
-        .expect("echo executable must exist.")
-        .close_stdin();
-    let id = core.next_id();
-    core.register_reaper(&e1, id, Sequence::handle_death_1);
-    core.register_reader(&e1.stdout, id, Sequence::read);
-    core.add(Sequence { child: e1, id });
-    core.run();
+    core.supervise(
+        Command::new("echo").arg("mamamamamam"),
+        |child, id, core| {
+            core.read_lines(&child.stdout, id, stdout, Sequence::output)
+                .expect("Failed to register reader.");
+            Sequence {
+                child: child.close_stdin(),
+                id,
+            }
+        },
+        Sequence::handle_death_1,
+    )
+    .expect("echo executable must exist.");
+    core.run().expect("Failed to run the event loop.");
 }
@@ -0,0 +1,75 @@
+use crate::{Core, ObjectId};
+use log::error;
+use mio::{Ready, Registration, SetReadiness};
+use std::any::Any;
+use std::io;
+use std::rc::Rc;
+use std::sync::mpsc;
+
+/// The sending end of a channel made with `channel`.
+///
+/// Senders can be cloned and, as long as the messages can, sent to other threads.
+pub struct Sender<T> {
+    sender: mpsc::Sender<T>,
+    set_readiness: SetReadiness,
+}
+
+impl<T> Sender<T> {
+    /// Sends a message to the receiving object, waking the loop up if it is waiting
+    /// for events.
+    ///
+    /// Fails if the receiving object has been removed.
+    pub fn send(&self, message: T) -> io::Result<()> {
+        self.sender.send(message).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The receiving object has been removed.",
+            )
+        })?;
+        self.set_readiness.set_readiness(Ready::readable())
+    }
+}
+
+// implemented by hand, as deriving would require `T` to be `Clone`.
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            sender: self.sender.clone(),
+            set_readiness: self.set_readiness.clone(),
+        }
+    }
+}
+
+/// Creates a channel for sending messages of type `T` to the object with the given id.
+///
+/// `f` is called on the object with every message sent, in order, from the loop's
+/// thread. This lets objects, or other threads, talk to an object without knowing its
+/// type or borrowing it. The channel is a registration of the receiving object, so it
+/// keeps `run` from returning until the object is removed, after which sending fails.
+pub fn channel<T, O, F>(object_id: ObjectId, mut f: F, core: &mut Core) -> io::Result<Sender<T>>
+where
+    T: 'static,
+    O: Any,
+    F: 'static + FnMut(&mut O, T, &mut Core),
+{
+    let (registration, set_readiness) = Registration::new2();
+    let (sender, receiver) = mpsc::channel();
+    // the callback keeps the registration alive, as dropping it stops the wakeups.
+    let registration = Rc::new(registration);
+    let kept = registration.clone();
+    let own_readiness = set_readiness.clone();
+    core.register_reader(&*registration, object_id, move |object: &mut O, core| {
+        let _ = &kept;
+        // clear the readiness first, so messages sent from now on wake the loop again.
+        if let Err(err) = own_readiness.set_readiness(Ready::empty()) {
+            error!("Failed to reset readiness for a channel: {}", err);
+        }
+        while let Ok(message) = receiver.try_recv() {
+            f(object, message, core);
+        }
+    })?;
+    Ok(Sender {
+        sender,
+        set_readiness,
+    })
+}
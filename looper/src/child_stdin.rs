@@ -0,0 +1,142 @@
+use crate::{Core, EventKind, ObjectId, Stdin, WriteBuffer};
+use log::error;
+use mio::{Ready, Token};
+use std::any::Any;
+use std::io::{self, ErrorKind};
+
+/// Writes to the stdin of a child as the pipe takes the data, and closes it once
+/// everything has been written.
+///
+/// Writing more than the pipe holds to a non-blocking stdin fails with `WouldBlock`,
+/// so the writer queues what the pipe can't take yet and writes it as the pipe becomes
+/// writable. After `finish` the pipe is closed as soon as the queue has drained, so the
+/// child reads the end of its input.
+pub struct ChildStdinWriter {
+    // taken once the pipe is closed.
+    stdin: Option<Stdin>,
+    buffer: WriteBuffer,
+    token: Token,
+    finishing: bool,
+}
+
+impl ChildStdinWriter {
+    /// Registers `stdin` for writing, for a writer that is kept in the object with the
+    /// given id.
+    ///
+    /// `access` is how the registration gets at the writer, e.g.
+    /// `|job: &mut Job| &mut job.stdin`. `on_done` is called once the pipe has been
+    /// closed after `finish`, or with the error if writing fails or the child closes
+    /// its end first. Errors from `write` and `finish` themselves are returned instead.
+    pub fn new<A, F, T>(
+        stdin: Stdin,
+        object_id: ObjectId,
+        access: A,
+        on_done: F,
+        core: &mut Core,
+    ) -> io::Result<ChildStdinWriter>
+    where
+        A: 'static + Fn(&mut T) -> &mut ChildStdinWriter,
+        F: 'static + FnOnce(&mut T, io::Result<()>, &mut Core),
+        T: Any,
+    {
+        let mut on_done = Some(on_done);
+        let token = core.register_with_events(
+            &stdin,
+            Ready::writable(),
+            object_id,
+            move |object: &mut T, kind, core: &mut Core| {
+                let writer = access(object);
+                let result = match kind {
+                    EventKind::Writable | EventKind::Error => match writer.flush(core) {
+                        Ok(false) => return,
+                        Ok(true) => Ok(()),
+                        Err(err) => Err(err),
+                    },
+                    EventKind::WriteClosed => Err(io::Error::new(
+                        ErrorKind::BrokenPipe,
+                        "The child closed its stdin.",
+                    )),
+                    _ => return,
+                };
+                writer.close(core);
+                if let Some(on_done) = on_done.take() {
+                    on_done(object, result, core);
+                }
+            },
+        )?;
+        Ok(ChildStdinWriter {
+            stdin: Some(stdin),
+            buffer: WriteBuffer::new(token, usize::MAX),
+            token,
+            finishing: false,
+        })
+    }
+
+    /// Queues `bytes` and writes as much as the pipe takes right away.
+    ///
+    /// Fails if the writer has been finished or closed, or on any error but the pipe
+    /// being full, after which the pipe is closed.
+    pub fn write(&mut self, bytes: &[u8], core: &mut Core) -> io::Result<()> {
+        let stdin = match &mut self.stdin {
+            Some(stdin) if !self.finishing => stdin,
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::BrokenPipe,
+                    "Writing to a finished stdin.",
+                ))
+            }
+        };
+        if let Err(err) = self.buffer.write(stdin, bytes, core) {
+            self.close(core);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Closes the pipe once everything queued has been written, after which `on_done`
+    /// is called.
+    pub fn finish(&mut self, core: &mut Core) -> io::Result<()> {
+        if self.finishing {
+            return Ok(());
+        }
+        self.finishing = true;
+        if let Some(stdin) = &self.stdin {
+            // the pipe is closed from the registration, which only runs while there
+            // is interest in writability.
+            core.set_write_interest(stdin, self.token, true)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of bytes waiting to be written.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Returns true once the pipe has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.stdin.is_none()
+    }
+
+    // writes what is queued. Returns true once the pipe should be closed.
+    fn flush(&mut self, core: &mut Core) -> io::Result<bool> {
+        let stdin = match &mut self.stdin {
+            Some(stdin) => stdin,
+            None => return Ok(false),
+        };
+        self.buffer.flush(stdin, core)?;
+        Ok(self.finishing && self.buffer.is_empty())
+    }
+
+    fn close(&mut self, core: &mut Core) {
+        if let Some(stdin) = self.stdin.take() {
+            if let Err(err) = core.deregister(&stdin, self.token) {
+                error!("Failed to deregister a child's stdin: {}", err);
+            }
+        }
+    }
+}
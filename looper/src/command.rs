@@ -0,0 +1,124 @@
+use crate::{Core, ObjectId, Stderr, Stdout};
+use std::io::{self, ErrorKind, Read};
+use std::mem;
+use std::process::{Command, ExitStatus, Output, Stdio};
+
+type Deliver = Box<dyn FnOnce(io::Result<Output>, &mut Core)>;
+
+// collects the output of a command run with `Core::run_command`, living in the core
+// as an object of its own until the command has exited and closed its output.
+struct CommandRun {
+    object_id: ObjectId,
+    stdout: Option<Stdout>,
+    stderr: Option<Stderr>,
+    stdout_data: Vec<u8>,
+    stderr_data: Vec<u8>,
+    // set once the child has been reaped.
+    reaped: bool,
+    status: Option<ExitStatus>,
+    error: Option<io::Error>,
+    deliver: Option<Deliver>,
+}
+
+pub fn run(core: &mut Core, cmd: &mut Command, deliver: Deliver) -> io::Result<()> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = core.spawn_with_stdio(cmd)?;
+    let object_id = core.next_id();
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let mut result = Ok(());
+    if let Some(stdout) = &stdout {
+        result = core
+            .register_reader(stdout, object_id, CommandRun::read_stdout)
+            .map(drop);
+    }
+    if let (Ok(()), Some(stderr)) = (&result, &stderr) {
+        result = core
+            .register_reader(stderr, object_id, CommandRun::read_stderr)
+            .map(drop);
+    }
+    if let Err(err) = result {
+        // the command can't be followed, so it is stopped instead of left running.
+        core.unregister_object(object_id);
+        core.abandon_child(child, object_id);
+        return Err(err);
+    }
+    core.register_reaper_with_status(&child, object_id, CommandRun::reaped);
+    core.add(CommandRun {
+        object_id,
+        stdout,
+        stderr,
+        stdout_data: Vec::new(),
+        stderr_data: Vec::new(),
+        reaped: false,
+        status: None,
+        error: None,
+        deliver: Some(deliver),
+    });
+    Ok(())
+}
+
+impl CommandRun {
+    fn read_stdout(&mut self, core: &mut Core) {
+        drain(&mut self.stdout, &mut self.stdout_data, &mut self.error);
+        self.finish_if_done(core);
+    }
+
+    fn read_stderr(&mut self, core: &mut Core) {
+        drain(&mut self.stderr, &mut self.stderr_data, &mut self.error);
+        self.finish_if_done(core);
+    }
+
+    fn reaped(&mut self, status: Option<ExitStatus>, core: &mut Core) {
+        self.reaped = true;
+        self.status = status;
+        self.finish_if_done(core);
+    }
+
+    fn finish_if_done(&mut self, core: &mut Core) {
+        if !self.reaped || self.stdout.is_some() || self.stderr.is_some() {
+            return;
+        }
+        let deliver = match self.deliver.take() {
+            Some(deliver) => deliver,
+            None => return,
+        };
+        core.remove(self.object_id);
+        let result = match (self.error.take(), self.status) {
+            (Some(err), _) => Err(err),
+            (None, Some(status)) => Ok(Output {
+                status,
+                stdout: mem::take(&mut self.stdout_data),
+                stderr: mem::take(&mut self.stderr_data),
+            }),
+            (None, None) => Err(io::Error::other("Failed to get the exit status.")),
+        };
+        deliver(result, core);
+    }
+}
+
+// reads everything available, dropping the stream once it has ended.
+fn drain<R: Read>(stream: &mut Option<R>, data: &mut Vec<u8>, error: &mut Option<io::Error>) {
+    let reader = match stream {
+        Some(reader) => reader,
+        None => return,
+    };
+    let mut chunk = [0; 4096];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => data.extend_from_slice(&chunk[..n]),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+            // this is how the end of a pipe is reported on Windows.
+            Err(ref e) if e.kind() == ErrorKind::BrokenPipe => break,
+            Err(e) => {
+                error.get_or_insert(e);
+                break;
+            }
+        }
+    }
+    *stream = None;
+}
@@ -0,0 +1,285 @@
+use std::borrow::{Borrow, BorrowMut};
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+#[cfg(unix)]
+use std::ffi::CString;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+#[cfg(unix)]
+use std::ptr;
+
+/// Sets up a command to be started with `Core::spawn` and friends.
+///
+/// This wraps a `Command` with the setup commonly needed for children of a long
+/// running process, such as a clean environment or a session of their own. A builder
+/// can be passed to `Core::spawn` directly, and `build` returns the underlying
+/// `Command` for everything else.
+#[derive(Debug)]
+pub struct CommandBuilder {
+    cmd: Command,
+    // whether `groups` has been called, so `uid` leaves the groups alone.
+    #[cfg(unix)]
+    groups_set: bool,
+}
+
+impl CommandBuilder {
+    pub fn new(program: impl AsRef<OsStr>) -> CommandBuilder {
+        CommandBuilder {
+            cmd: Command::new(program),
+            #[cfg(unix)]
+            groups_set: false,
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> CommandBuilder {
+        self.cmd.arg(arg);
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> CommandBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.cmd.args(args);
+        self
+    }
+
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> CommandBuilder {
+        self.cmd.env(key, value);
+        self
+    }
+
+    /// Sets all the variables in the given map, e.g. a `HashMap<String, String>`.
+    pub fn envs<I, K, V>(mut self, vars: I) -> CommandBuilder
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.cmd.envs(vars);
+        self
+    }
+
+    pub fn env_remove(mut self, key: impl AsRef<OsStr>) -> CommandBuilder {
+        self.cmd.env_remove(key);
+        self
+    }
+
+    /// Starts the child with an empty environment, rather than a copy of ours.
+    ///
+    /// Variables set before this are dropped as well, so it usually comes first.
+    pub fn env_clear(mut self) -> CommandBuilder {
+        self.cmd.env_clear();
+        self
+    }
+
+    /// Passes on the given variables from our environment, those that are set.
+    ///
+    /// Meant to follow `env_clear`, to keep e.g. `PATH` and `HOME` but nothing else.
+    pub fn env_inherit<I, K>(mut self, keys: I) -> CommandBuilder
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<OsStr>,
+    {
+        for key in keys {
+            if let Some(value) = env::var_os(&key) {
+                self.cmd.env(key, value);
+            }
+        }
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl AsRef<Path>) -> CommandBuilder {
+        self.cmd.current_dir(dir);
+        self
+    }
+
+    /// Runs the child as the given user, which requires the privileges to do so.
+    ///
+    /// Like the other settings applied in the child, see `pre_exec`, this takes
+    /// effect in the order it is called. The privileges dropped here are the ones
+    /// `chroot`, `groups` and `gid` need, so this usually comes last. Unless `groups`
+    /// came first, a child started by root also loses our supplementary groups.
+    #[cfg(unix)]
+    pub fn uid(self, uid: u32) -> CommandBuilder {
+        let clear_groups = !self.groups_set;
+        self.in_child(move || unsafe {
+            if clear_groups && libc::getuid() == 0 {
+                check(libc::setgroups(0, ptr::null()))?;
+            }
+            check(libc::setuid(uid as libc::uid_t))
+        })
+    }
+
+    /// Runs the child with the given group, which requires the privileges to do so.
+    #[cfg(unix)]
+    pub fn gid(self, gid: u32) -> CommandBuilder {
+        self.in_child(move || unsafe { check(libc::setgid(gid as libc::gid_t)) })
+    }
+
+    /// Sets the supplementary groups of the child, which requires the privileges to
+    /// do so.
+    #[cfg(unix)]
+    pub fn groups(mut self, groups: &[u32]) -> CommandBuilder {
+        self.groups_set = true;
+        let groups: Vec<libc::gid_t> = groups.iter().map(|g| *g as libc::gid_t).collect();
+        self.in_child(move || unsafe { check(libc::setgroups(groups.len() as _, groups.as_ptr())) })
+    }
+
+    /// Makes `path` the root directory of the child, and its working directory.
+    ///
+    /// The program has to be found inside the new root. Requires the privileges to
+    /// do so, see `uid`.
+    #[cfg(unix)]
+    pub fn chroot(self, path: impl AsRef<Path>) -> CommandBuilder {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes());
+        self.in_child(move || unsafe {
+            match &path {
+                Ok(path) => {
+                    check(libc::chroot(path.as_ptr()))?;
+                    check(libc::chdir(b"/\0".as_ptr() as *const libc::c_char))
+                }
+                // the path contains a nul byte.
+                Err(_) => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+            }
+        })
+    }
+
+    /// Sets the file mode creation mask of the child, e.g. `0o077` to keep the files
+    /// it creates private.
+    #[cfg(unix)]
+    pub fn umask(self, mask: u32) -> CommandBuilder {
+        self.in_child(move || unsafe {
+            libc::umask(mask as libc::mode_t);
+            Ok(())
+        })
+    }
+
+    /// Keeps the child from inheriting any file descriptors other than its stdio.
+    ///
+    /// Descriptors opened by Rust are closed on exec anyway, but ones opened by C
+    /// libraries or inherited from our own parent may not be.
+    #[cfg(unix)]
+    pub fn close_inherited_fds(self) -> CommandBuilder {
+        self.in_child(|| {
+            // marked to be closed on exec rather than closed right away, as a failing
+            // exec is reported through one of them.
+            set_cloexec_from(3);
+            Ok(())
+        })
+    }
+
+    /// Calls `f` in the child after it has been forked and before the program is
+    /// executed, failing the spawn if it returns an error.
+    ///
+    /// This is for setup that isn't covered by the other methods. The settings made
+    /// with this, `uid`, `gid`, `groups`, `chroot`, `umask` and `close_inherited_fds`
+    /// take effect in the order they are called, after those that `Command` makes
+    /// itself, such as the working directory.
+    ///
+    /// # Safety
+    ///
+    /// `f` runs in a copy of our process with only the one thread, so it may only make
+    /// async-signal-safe calls, and must not allocate or take locks. See
+    /// `CommandExt::pre_exec`.
+    #[cfg(unix)]
+    pub unsafe fn pre_exec<F>(mut self, f: F) -> CommandBuilder
+    where
+        F: 'static + FnMut() -> io::Result<()> + Send + Sync,
+    {
+        self.cmd.pre_exec(f);
+        self
+    }
+
+    /// Puts the child in a new process group of its own, so that signals sent to our
+    /// group, e.g. by pressing ^C in a terminal, don't reach it.
+    #[cfg(unix)]
+    pub fn new_process_group(mut self) -> CommandBuilder {
+        self.cmd.process_group(0);
+        self
+    }
+
+    /// Starts the child in a new session, detached from our controlling terminal.
+    ///
+    /// This puts it in a new process group as well, so it can't be combined with
+    /// `new_process_group`.
+    #[cfg(unix)]
+    pub fn new_session(self) -> CommandBuilder {
+        self.in_child(|| unsafe { check(libc::setsid()) })
+    }
+
+    /// Sets the flags passed to `CreateProcess`, e.g. `CREATE_NEW_PROCESS_GROUP`.
+    #[cfg(windows)]
+    pub fn creation_flags(mut self, flags: u32) -> CommandBuilder {
+        self.cmd.creation_flags(flags);
+        self
+    }
+
+    pub fn build(self) -> Command {
+        self.cmd
+    }
+
+    // only used with closures that make async-signal-safe calls, and allocate nothing.
+    #[cfg(unix)]
+    fn in_child<F>(self, f: F) -> CommandBuilder
+    where
+        F: 'static + FnMut() -> io::Result<()> + Send + Sync,
+    {
+        unsafe { self.pre_exec(f) }
+    }
+}
+
+#[cfg(unix)]
+fn check(rc: libc::c_int) -> io::Result<()> {
+    if rc == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// makes every file descriptor from `first` on be closed on exec.
+#[cfg(unix)]
+fn set_cloexec_from(first: libc::c_int) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let flags = libc::CLOSE_RANGE_CLOEXEC;
+        if libc::syscall(libc::SYS_close_range, first, libc::c_uint::MAX, flags) == 0 {
+            return;
+        }
+    }
+    // without close_range, go through every descriptor there may be.
+    let max = match unsafe { libc::sysconf(libc::_SC_OPEN_MAX) } {
+        n if n > 0 => n as libc::c_int,
+        _ => 1024,
+    };
+    for fd in first..max {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+}
+
+impl From<CommandBuilder> for Command {
+    fn from(builder: CommandBuilder) -> Command {
+        builder.cmd
+    }
+}
+
+impl Borrow<Command> for CommandBuilder {
+    fn borrow(&self) -> &Command {
+        &self.cmd
+    }
+}
+
+impl BorrowMut<Command> for CommandBuilder {
+    fn borrow_mut(&mut self) -> &mut Command {
+        &mut self.cmd
+    }
+}
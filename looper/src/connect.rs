@@ -0,0 +1,79 @@
+use crate::{Core, ObjectId};
+use log::error;
+use mio::net::TcpStream;
+use mio::Token;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+type Deliver = Box<dyn FnOnce(io::Result<TcpStream>, &mut Core)>;
+
+// a connection being made with `Core::connect_tcp`, living in the core as an object of
+// its own until it is done.
+struct Connecting {
+    object_id: ObjectId,
+    // taken once the connection is done, either way.
+    stream: Option<TcpStream>,
+    token: Token,
+    deliver: Option<Deliver>,
+}
+
+pub fn connect(
+    core: &mut Core,
+    address: SocketAddr,
+    timeout: Option<Duration>,
+    deliver: Deliver,
+) -> io::Result<()> {
+    let stream = TcpStream::connect(&address)?;
+    let object_id = core.next_id();
+    let token =
+        core.register_writer_with_hangup(&stream, object_id, Connecting::check, Connecting::check)?;
+    core.add(Connecting {
+        object_id,
+        stream: Some(stream),
+        token,
+        deliver: Some(deliver),
+    });
+    if let Some(timeout) = timeout {
+        // dropped along with the object if the connection is done first.
+        core.set_timeout(timeout, object_id, |connecting: &mut Connecting, core| {
+            let err = io::Error::new(ErrorKind::TimedOut, "Timed out while connecting.");
+            connecting.finish(Err(err), core);
+        });
+    }
+    Ok(())
+}
+
+impl Connecting {
+    fn check(&mut self, core: &mut Core) {
+        let stream = match &self.stream {
+            Some(stream) => stream,
+            None => return,
+        };
+        let result = match stream.take_error() {
+            Ok(Some(err)) | Err(err) => Err(err),
+            // a connected socket has a peer.
+            Ok(None) => match stream.peer_addr() {
+                Ok(_) => Ok(()),
+                Err(ref e) if e.kind() == ErrorKind::NotConnected => return,
+                Err(e) => Err(e),
+            },
+        };
+        self.finish(result, core);
+    }
+
+    fn finish(&mut self, result: io::Result<()>, core: &mut Core) {
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => return,
+        };
+        // the stream is registered again by whoever it is handed to.
+        if let Err(err) = core.deregister(&stream, self.token) {
+            error!("Failed to deregister a connected socket: {}", err);
+        }
+        core.remove(self.object_id);
+        if let Some(deliver) = self.deliver.take() {
+            deliver(result.map(|()| stream), core);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{proc_imp, Core};
+use std::io;
+
+/// What happens when a callback panics, see `CoreBuilder::panic_policy`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PanicPolicy {
+    /// The panic unwinds out of `run`, like out of any other function. The core is left
+    /// in an inconsistent state and shouldn't be used afterwards.
+    #[default]
+    Unwind,
+    /// The process is aborted once the panic message has been printed, so there is
+    /// no chance of running on in an inconsistent state.
+    Abort,
+    /// The panic is caught and the object whose callback panicked is removed, as with
+    /// `Core::remove`, so the rest of the loop keeps running. The panic is passed to
+    /// the hook set with `Core::on_panic`. For registrations made with
+    /// `Core::register_owned`, the registration and its object are dropped instead,
+    /// without calling the hook. Panics in callbacks that don't belong to an object,
+    /// such as the ones passed to `Core::defer`, still unwind.
+    RemoveObject,
+}
+
+/// Sets up a core with other options than the ones `Core::new` uses.
+///
+/// `build` returns an error if the core can't be set up, e.g. because the process is
+/// out of file descriptors, where `Core::new` panics.
+#[derive(Clone, Debug)]
+pub struct CoreBuilder {
+    pub(crate) events: usize,
+    pub(crate) objects: usize,
+    #[cfg(unix)]
+    pub(crate) reap_children: bool,
+    pub(crate) panic_policy: PanicPolicy,
+}
+
+impl Default for CoreBuilder {
+    fn default() -> Self {
+        CoreBuilder {
+            events: 32,
+            objects: 0,
+            #[cfg(unix)]
+            reap_children: true,
+            panic_policy: PanicPolicy::default(),
+        }
+    }
+}
+
+impl CoreBuilder {
+    pub fn new() -> CoreBuilder {
+        CoreBuilder::default()
+    }
+
+    /// Sets the number of events read per poll, 32 by default.
+    ///
+    /// A core handling many connections at once wakes up less often when busy with
+    /// a larger number.
+    pub fn events(mut self, events: usize) -> CoreBuilder {
+        self.events = events.max(1);
+        self
+    }
+
+    /// Makes room for the given number of objects up front, rather than growing the
+    /// storage as objects are added.
+    pub fn objects(mut self, objects: usize) -> CoreBuilder {
+        self.objects = objects;
+        self
+    }
+
+    /// Sets whether the core handles SIGCHLD to reap its children, which it does by
+    /// default.
+    ///
+    /// Applications that wait for their children themselves, or let another library
+    /// do it, can turn this off. The reapers registered with the core are then never
+    /// called, so `register_reaper` and friends shouldn't be used.
+    #[cfg(unix)]
+    pub fn reap_children(mut self, reap_children: bool) -> CoreBuilder {
+        self.reap_children = reap_children;
+        self
+    }
+
+    /// Sets what happens when a callback panics, `PanicPolicy::Unwind` by default.
+    pub fn panic_policy(mut self, panic_policy: PanicPolicy) -> CoreBuilder {
+        self.panic_policy = panic_policy;
+        self
+    }
+
+    pub fn build(self) -> io::Result<Core> {
+        let mut core = proc_imp::new_core(&self)?;
+        core.reserve_objects(self.objects);
+        Ok(core)
+    }
+}
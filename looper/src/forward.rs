@@ -0,0 +1,361 @@
+use crate::{Core, ObjectId};
+use mio::unix::EventedFd;
+use std::io::{self, ErrorKind};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use log::debug;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::fs::File;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::os::unix::io::FromRawFd;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+type Done = Box<dyn FnOnce(io::Result<u64>, &mut Core)>;
+
+// how data gets from the source to the sink.
+enum Method {
+    // the source is a regular file.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    SendFile,
+    // either end is a pipe, so data can be spliced from one to the other directly.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Splice,
+    // data is spliced into a pipe of our own and from there to the sink.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    SpliceThrough {
+        reader: File,
+        writer: File,
+        // bytes in the pipe not written to the sink yet.
+        buffered: usize,
+    },
+    Copy {
+        buffer: Vec<u8>,
+        // the part of the buffer that has been read but not written yet.
+        start: usize,
+        end: usize,
+    },
+}
+
+/// Copies everything read from a source file descriptor to a sink without passing
+/// the data through userspace where possible.
+///
+/// This is like `Proxy`, but meant for forwarding e.g. the output of a child to a
+/// socket, or a socket to a file. On Linux the data is moved with `sendfile` when
+/// the source is a regular file and with `splice` otherwise, falling back to reading
+/// into and writing from a buffer when the kernel can't move data between the two.
+/// On other systems the data is always copied through a buffer.
+///
+/// Sockets and pipes must be non-blocking, which those from mio and `Core::spawn`
+/// are. Regular files are always ready, so they are not registered with the core.
+/// When the source reaches its end and everything has been written, or either side
+/// fails, the forwarder removes itself from the core, closing both ends, and calls
+/// the completion callback with the number of bytes forwarded or the error.
+pub struct Forwarder<R, W> {
+    source: R,
+    sink: W,
+    method: Method,
+    eof: bool,
+    copied: u64,
+    object_id: ObjectId,
+    on_done: Option<Done>,
+}
+
+impl<R, W> Forwarder<R, W>
+where
+    R: 'static + AsRawFd,
+    W: 'static + AsRawFd,
+{
+    /// Adds a forwarder from `source` to `sink` to the core.
+    ///
+    /// Fails if either end can't be registered with the core. The source and sink
+    /// must be registered separately, so they can't be the same socket or pipe.
+    pub fn start<F>(source: R, sink: W, core: &mut Core, on_done: F) -> io::Result<ObjectId>
+    where
+        F: 'static + FnOnce(io::Result<u64>, &mut Core),
+    {
+        let method = Method::new(source.as_raw_fd(), sink.as_raw_fd())?;
+        let object_id = core.next_id();
+        let source_fd = source.as_raw_fd();
+        let sink_fd = sink.as_raw_fd();
+        ignore_unpollable(core.register_reader(
+            &EventedFd(&source_fd),
+            object_id,
+            Forwarder::<R, W>::pump,
+        ))?;
+        let result = ignore_unpollable(core.register_writer(
+            &EventedFd(&sink_fd),
+            object_id,
+            Forwarder::<R, W>::pump,
+        ));
+        if let Err(err) = result {
+            core.unregister_object(object_id);
+            return Err(err);
+        }
+        core.add(Forwarder {
+            source,
+            sink,
+            method,
+            eof: false,
+            copied: 0,
+            object_id,
+            on_done: Some(Box::new(on_done)),
+        });
+        // files never report being ready, so get going without waiting for them.
+        core.post(object_id, Forwarder::<R, W>::pump);
+        Ok(object_id)
+    }
+
+    /// Returns the number of bytes forwarded so far.
+    pub fn copied(&self) -> u64 {
+        self.copied
+    }
+
+    /// Returns true if data is moved by the kernel, rather than copied through a
+    /// buffer of ours.
+    pub fn is_zero_copy(&self) -> bool {
+        match self.method {
+            Method::Copy { .. } => false,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            _ => true,
+        }
+    }
+
+    fn pump(&mut self, core: &mut Core) {
+        let result = match self.transfer() {
+            Ok(false) => return,
+            Ok(true) => Ok(self.copied),
+            Err(err) => Err(err),
+        };
+        core.remove(self.object_id);
+        if let Some(on_done) = self.on_done.take() {
+            on_done(result, core);
+        }
+    }
+
+    // moves data until either side would block. Returns true once everything has been
+    // forwarded.
+    fn transfer(&mut self) -> io::Result<bool> {
+        let source = self.source.as_raw_fd();
+        let sink = self.sink.as_raw_fd();
+        loop {
+            match self.step(source, sink) {
+                Ok(true) => {}
+                Ok(false) => return Ok(false),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                Err(ref e) if is_unsupported(e) && !self.is_copy() => self.fall_back()?,
+                Err(e) => return Err(e),
+            }
+            if self.eof && self.is_drained() {
+                return Ok(true);
+            }
+        }
+    }
+
+    // moves one chunk of data. Returns false if either side would block.
+    fn step(&mut self, source: RawFd, sink: RawFd) -> io::Result<bool> {
+        match &mut self.method {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Method::SendFile => {
+                let n = would_block(cvt(unsafe {
+                    libc::sendfile(sink, source, std::ptr::null_mut(), CHUNK_SIZE)
+                }))?;
+                match n {
+                    None => return Ok(false),
+                    Some(0) => self.eof = true,
+                    Some(n) => self.copied += n as u64,
+                }
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Method::Splice => match would_block(splice(source, sink, CHUNK_SIZE))? {
+                None => return Ok(false),
+                Some(0) => self.eof = true,
+                Some(n) => self.copied += n as u64,
+            },
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Method::SpliceThrough {
+                reader,
+                writer,
+                buffered,
+            } => {
+                if *buffered > 0 {
+                    match would_block(splice(reader.as_raw_fd(), sink, *buffered))? {
+                        None => return Ok(false),
+                        Some(0) => return Err(ErrorKind::WriteZero.into()),
+                        Some(n) => {
+                            *buffered -= n;
+                            self.copied += n as u64;
+                        }
+                    }
+                } else if !self.eof {
+                    match would_block(splice(source, writer.as_raw_fd(), CHUNK_SIZE))? {
+                        None => return Ok(false),
+                        Some(0) => self.eof = true,
+                        Some(n) => *buffered = n,
+                    }
+                }
+            }
+            Method::Copy { buffer, start, end } => {
+                if *start < *end {
+                    match would_block(write(sink, &buffer[*start..*end]))? {
+                        None => return Ok(false),
+                        Some(0) => return Err(ErrorKind::WriteZero.into()),
+                        Some(n) => {
+                            *start += n;
+                            self.copied += n as u64;
+                        }
+                    }
+                } else if !self.eof {
+                    *start = 0;
+                    *end = 0;
+                    match would_block(read(source, buffer))? {
+                        None => return Ok(false),
+                        Some(0) => self.eof = true,
+                        Some(n) => *end = n,
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn is_copy(&self) -> bool {
+        !self.is_zero_copy()
+    }
+
+    fn is_drained(&self) -> bool {
+        match self.method {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Method::SpliceThrough { buffered, .. } => buffered == 0,
+            Method::Copy { start, end, .. } => start == end,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            _ => true,
+        }
+    }
+
+    // switches to copying through a buffer, taking along anything left in our pipe.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn fall_back(&mut self) -> io::Result<()> {
+        debug!(
+            "Can't forward data for {:?} in the kernel, copying it instead.",
+            self.object_id
+        );
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut end = 0;
+        if let Method::SpliceThrough {
+            reader, buffered, ..
+        } = &self.method
+        {
+            while end < *buffered {
+                match read(reader.as_raw_fd(), &mut buffer[end..*buffered]) {
+                    Ok(n) => end += n,
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        self.method = Method::Copy {
+            buffer,
+            start: 0,
+            end,
+        };
+        Ok(())
+    }
+}
+
+impl Method {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn new(source: RawFd, sink: RawFd) -> io::Result<Method> {
+        let source_type = file_type(source)?;
+        let sink_type = file_type(sink)?;
+        if source_type == libc::S_IFREG {
+            return Ok(Method::SendFile);
+        }
+        if source_type == libc::S_IFIFO || sink_type == libc::S_IFIFO {
+            return Ok(Method::Splice);
+        }
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Method::SpliceThrough {
+            reader: unsafe { File::from_raw_fd(fds[0]) },
+            writer: unsafe { File::from_raw_fd(fds[1]) },
+            buffered: 0,
+        })
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn new(_source: RawFd, _sink: RawFd) -> io::Result<Method> {
+        Ok(Method::Copy {
+            buffer: vec![0; CHUNK_SIZE],
+            start: 0,
+            end: 0,
+        })
+    }
+}
+
+// regular files can't be polled, and don't need to be as they are always ready.
+fn ignore_unpollable<T>(result: io::Result<T>) -> io::Result<()> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(ref e) if e.raw_os_error() == Some(libc::EPERM) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// turns running into a full or empty end into `None`.
+fn would_block(result: io::Result<usize>) -> io::Result<Option<usize>> {
+    match result {
+        Ok(n) => Ok(Some(n)),
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn cvt(n: libc::ssize_t) -> io::Result<usize> {
+    if n == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(n as usize)
+}
+
+fn read(fd: RawFd, bytes: &mut [u8]) -> io::Result<usize> {
+    cvt(unsafe { libc::read(fd, bytes.as_mut_ptr() as *mut _, bytes.len()) })
+}
+
+fn write(fd: RawFd, bytes: &[u8]) -> io::Result<usize> {
+    cvt(unsafe { libc::write(fd, bytes.as_ptr() as *const _, bytes.len()) })
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn splice(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+    let flags = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+    cvt(unsafe {
+        libc::splice(
+            from,
+            std::ptr::null_mut(),
+            to,
+            std::ptr::null_mut(),
+            len,
+            flags,
+        )
+    })
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn file_type(fd: RawFd) -> io::Result<libc::mode_t> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.st_mode & libc::S_IFMT)
+}
+
+// errors meaning the kernel can't move data between the two ends.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn is_unsupported(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EINVAL) | Some(libc::ENOSYS))
+}
@@ -0,0 +1,78 @@
+use crate::{Core, LoopObject, ObjectId, TimerId};
+use std::any::Any;
+
+/// A set of objects, and timers, that are torn down together.
+///
+/// This suits e.g. the objects making up a session with a client, which should all
+/// go once the client disconnects. Removing an object drops its registrations and
+/// reapers as well, so those don't need to be tracked. Children keep running unless
+/// an object kills its own, e.g. from `LoopObject::on_remove`.
+#[derive(Debug, Default)]
+pub struct ObjectGroup {
+    // the members along with their generations, as ids are reused once an object
+    // has been removed.
+    objects: Vec<(ObjectId, u32)>,
+    timers: Vec<TimerId>,
+}
+
+impl ObjectGroup {
+    pub fn new() -> ObjectGroup {
+        ObjectGroup::default()
+    }
+
+    /// Adds an object to the core as a member of the group.
+    pub fn add(&mut self, core: &mut Core, object: impl Any) -> ObjectId {
+        let object_id = core.add(object);
+        self.insert(object_id, core);
+        object_id
+    }
+
+    /// Like `add`, but for `Core::add_loop_object`.
+    pub fn add_loop_object<T: LoopObject>(&mut self, core: &mut Core, object: T) -> ObjectId {
+        let object_id = core.add_loop_object(object);
+        self.insert(object_id, core);
+        object_id
+    }
+
+    /// Makes an object that has been added already, or will be with the id from
+    /// `Core::next_id`, a member of the group.
+    pub fn insert(&mut self, object_id: ObjectId, core: &Core) {
+        // forget about members that have been removed in the meantime.
+        self.objects
+            .retain(|(id, generation)| core.expected_generation(*id) == *generation);
+        if !self.contains(object_id) {
+            self.objects
+                .push((object_id, core.expected_generation(object_id)));
+        }
+    }
+
+    /// Cancels the timer when the group is torn down.
+    ///
+    /// Timers for members are dropped along with them anyway, this is for timers set
+    /// for objects outside of the group.
+    pub fn track_timer(&mut self, timer_id: TimerId) {
+        self.timers.push(timer_id);
+    }
+
+    pub fn contains(&self, object_id: ObjectId) -> bool {
+        self.objects.iter().any(|(id, _)| *id == object_id)
+    }
+
+    /// Returns the ids of the members, including ones that may have been removed.
+    pub fn objects(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        self.objects.iter().map(|(id, _)| *id)
+    }
+
+    /// Removes all members from the core, in the order they were added, and cancels
+    /// the tracked timers.
+    pub fn teardown(self, core: &mut Core) {
+        for timer_id in self.timers {
+            core.cancel_timer(timer_id);
+        }
+        for (object_id, generation) in self.objects {
+            if core.expected_generation(object_id) == generation {
+                core.remove(object_id);
+            }
+        }
+    }
+}
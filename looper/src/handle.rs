@@ -0,0 +1,72 @@
+use crate::{Core, ObjectId};
+use std::any::Any;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+/// An `ObjectId` that also knows the type of its object.
+///
+/// Handles are returned by `Core::add_typed`, or checked with `Core::handle`. Looking
+/// up an object through its handle can't be done with the wrong type, so it only
+/// fails once the object has been removed or while its own callback runs. Use `id`
+/// where a plain id is needed, e.g. for registrations.
+pub struct Handle<T> {
+    id: ObjectId,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Any> Handle<T> {
+    pub(crate) fn new(id: ObjectId) -> Handle<T> {
+        Handle {
+            id,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn id(self) -> ObjectId {
+        self.id
+    }
+
+    pub fn get(self, core: &Core) -> Option<&T> {
+        core.get(self.id)
+    }
+
+    pub fn get_mut(self, core: &mut Core) -> Option<&mut T> {
+        core.get_mut(self.id)
+    }
+}
+
+// implemented by hand, as deriving would require `T` to implement these as well.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Handle").field(&self.id).finish()
+    }
+}
+
+impl<T> From<Handle<T>> for ObjectId {
+    fn from(handle: Handle<T>) -> Self {
+        handle.id
+    }
+}
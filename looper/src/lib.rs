@@ -1,11 +1,19 @@
-use log::trace;
-use mio::{Evented, Events as MioEvents, Poll, PollOpt, Ready, Token};
+use log::{error, trace, warn};
+use mio::net::TcpStream;
+use mio::{Event, Events as MioEvents, Poll, PollOpt, Ready, Token};
 use stash::Stash;
 use std::any::Any;
 use std::borrow::{Borrow, BorrowMut};
-use std::io;
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::io::{self, ErrorKind, IoSliceMut, Read};
 use std::marker::PhantomData;
-use std::process::{Child as ProcessChild, Command, Stdio};
+use std::mem;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::process::{self, Child as ProcessChild, Command, ExitStatus, Output, Stdio};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct ObjectId(usize);
@@ -15,20 +23,152 @@ impl From<usize> for ObjectId {
         ObjectId(idx)
     }
 }
-impl Into<usize> for ObjectId {
-    fn into(self) -> usize {
-        self.0
+impl From<ObjectId> for usize {
+    fn from(id: ObjectId) -> Self {
+        id.0
     }
 }
 
+/// When a callback runs compared to others that are due at the same time.
+///
+/// Of the callbacks for the events from one poll, and of the posted callbacks, the
+/// ones with a higher priority run first. Callbacks with the same priority run in the
+/// order they came in, like callbacks without a priority, which are `Normal`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+/// When the poll reports a source as ready, see `Core::register_reader_with_opts`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Trigger {
+    /// Reported once each time the source becomes ready, so the callback has to read
+    /// until it would block or it won't be called again. This is what the other
+    /// registration methods use.
+    #[default]
+    Edge,
+    /// Reported on every poll for as long as the source is ready, so the callback can
+    /// read as much as it likes and will be called again for the rest. Not supported
+    /// on Windows.
+    Level,
+    /// Reported once, after which the registration is disabled until `Core::rearm` is
+    /// called, and then reported again if the source is still ready.
+    Oneshot,
+}
+
+impl Trigger {
+    pub(crate) fn poll_opt(self) -> PollOpt {
+        match self {
+            Trigger::Edge => PollOpt::edge(),
+            Trigger::Level => PollOpt::level(),
+            // level triggering isn't implemented on Windows, where a rearmed source is
+            // only reported once it becomes ready again.
+            Trigger::Oneshot if cfg!(windows) => PollOpt::edge() | PollOpt::oneshot(),
+            Trigger::Oneshot => PollOpt::level() | PollOpt::oneshot(),
+        }
+    }
+}
+
+/// Identifies a callback registered with `Core::register_idle`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct IdleId(u64);
+
 trait Call {
-    fn make_call(&mut self, _: &mut Any, _: &mut Core);
+    fn make_call(&mut self, _: &mut dyn Any, _: &mut Core);
+}
+
+/// What happened to a source registered with `Core::register_with_events`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EventKind {
+    Readable,
+    Writable,
+    /// The other end has closed, so no more data will arrive after what is still
+    /// buffered. Only reported for sources registered for reading.
+    ReadClosed,
+    /// The other end has closed, so writes will fail. Only reported for sources
+    /// registered for writing.
+    WriteClosed,
+    /// The source is in an error state, which reading or writing will report.
+    Error,
+}
+
+trait EventCall {
+    fn make_call(&mut self, _: &mut dyn Any, _: EventKind, _: &mut Core);
+}
+
+impl<F, T> EventCall for Callback<F, T>
+where
+    F: FnMut(&mut T, EventKind, &mut Core),
+    T: Any,
+{
+    fn make_call(&mut self, object: &mut dyn Any, kind: EventKind, core: &mut Core) {
+        if let Some(t) = object.downcast_mut() {
+            (self.f)(t, kind, core);
+        }
+    }
+}
+
+trait ErrorCall {
+    fn make_call(&mut self, _: &mut dyn Any, _: ObjectId, _: &(dyn Error + 'static), _: &mut Core);
+}
+
+impl<F, T> ErrorCall for Callback<F, T>
+where
+    F: FnMut(&mut T, ObjectId, &(dyn Error + 'static), &mut Core),
+    T: Any,
+{
+    fn make_call(
+        &mut self,
+        object: &mut dyn Any,
+        source: ObjectId,
+        error: &(dyn Error + 'static),
+        core: &mut Core,
+    ) {
+        if let Some(t) = object.downcast_mut() {
+            (self.f)(t, source, error, core);
+        }
+    }
 }
 
 struct IoHandler {
-    object_id: ObjectId,
-    read_fn: Option<Box<Call>>,
-    write_fn: Option<Box<Call>>,
+    // `None` for registrations made with `register_owned`.
+    object_id: Option<ObjectId>,
+    // the generation the object is expected to have, see Core::generations.
+    generation: u32,
+    read_fn: Option<Box<dyn Call>>,
+    write_fn: Option<Box<dyn Call>>,
+    hangup_fn: Option<Box<dyn Call>>,
+    event_fn: Option<Box<dyn EventCall>>,
+    // the object and callback of a registration made with `register_owned`, which
+    // doesn't belong to an object in `objects`.
+    owned: Option<Box<dyn OwnedCall>>,
+}
+
+trait OwnedCall {
+    fn make_call(&mut self, _: Event, _: &mut Core);
+    fn object(&mut self) -> &mut dyn Any;
+}
+
+struct Owned<F, T> {
+    f: F,
+    object: T,
+}
+
+impl<F, T> OwnedCall for Owned<F, T>
+where
+    F: FnMut(&mut T, Event, &mut Core),
+    T: Any,
+{
+    fn make_call(&mut self, event: Event, core: &mut Core) {
+        (self.f)(&mut self.object, event, core)
+    }
+
+    fn object(&mut self) -> &mut dyn Any {
+        &mut self.object
+    }
 }
 
 struct Callback<F, T> {
@@ -50,17 +190,271 @@ where
     F: FnMut(&mut T, &mut Core),
     T: Any,
 {
-    fn make_call(&mut self, object: &mut Any, core: &mut Core) {
+    fn make_call(&mut self, object: &mut dyn Any, core: &mut Core) {
         if let Some(t) = object.downcast_mut() {
             (self.f)(t, core);
         }
     }
 }
 
+/// How many polls events for an object that has not been added yet are held back,
+/// before its registration is dropped. Exits of its children are held back as long.
+pub(crate) const PENDING_EVENT_POLLS: usize = 16;
+
+/// How long the loop has to go without IO events before the idle callbacks run.
+const IDLE_WINDOW: Duration = Duration::from_millis(10);
+
+/// The size of the chunks `read_chunks` passes on, and how many are read at once.
+const CHUNK_LEN: usize = 16 * 1024;
+const CHUNKS_PER_READ: usize = 4;
+
+type ChunkBuffers = Box<[[u8; CHUNK_LEN]; CHUNKS_PER_READ]>;
+
+struct PendingEvent {
+    token: Token,
+    readiness: Ready,
+    polls_left: usize,
+}
+
+// an output pipe of a child started with `spawn_with_handlers`, shared by its reader
+// and the child's reaper.
+struct LinePipe<R, F> {
+    pipe: R,
+    reader: Utf8LineReader,
+    f: F,
+    token: Option<Token>,
+}
+
+impl<R: Read + Source, F> LinePipe<R, F> {
+    fn new(pipe: R, f: F) -> Rc<RefCell<LinePipe<R, F>>> {
+        Rc::new(RefCell::new(LinePipe {
+            pipe,
+            reader: Utf8LineReader::new(),
+            f,
+            token: None,
+        }))
+    }
+
+    fn read<T>(&mut self, object: &mut T, core: &mut Core)
+    where
+        F: FnMut(&mut T, String, &mut Core),
+    {
+        // lines are collected first, as the reader borrows the pipe.
+        let mut lines = Vec::new();
+        if let Err(err) = self
+            .reader
+            .read_lines(&mut self.pipe, |line| lines.push(line))
+        {
+            error!("Failed to read lines: {}", err);
+        }
+        for line in lines {
+            (self.f)(object, line, core);
+        }
+    }
+
+    // passes on what is left once the child has exited and drops the registration.
+    fn finish<T>(&mut self, object: &mut T, core: &mut Core)
+    where
+        F: FnMut(&mut T, String, &mut Core),
+    {
+        self.read(object, core);
+        // the pipe may still be held open by a child of the child.
+        let mut rest = None;
+        self.reader.finish(|line| rest = Some(line));
+        if let Some(line) = rest {
+            (self.f)(object, line, core);
+        }
+        if let Some(token) = self.token.take() {
+            if let Err(err) = core.deregister(&self.pipe, token) {
+                error!("Failed to deregister the output of a child: {}", err);
+            }
+        }
+    }
+}
+
+type ObjectMap = Box<dyn FnOnce(Box<dyn Any>, &mut Core) -> Box<dyn Any>>;
+
+// a closure queued with `defer`.
+type Deferred = Box<dyn FnOnce(&mut Core)>;
+
+type PanicHook = Box<dyn FnMut(ObjectId, &str, &mut Core)>;
+
+// adapts a callback that is only called once to the `FnMut` that `Callback` takes.
+fn once<F, T>(f: F) -> impl FnMut(&mut T, &mut Core)
+where
+    F: FnOnce(&mut T, &mut Core),
+{
+    let mut f = Some(f);
+    move |object, core| {
+        if let Some(f) = f.take() {
+            f(object, core)
+        }
+    }
+}
+
+// the kinds of event to report for the given readiness, in the order to report them.
+fn event_kinds(readiness: Ready, interest: Ready) -> Vec<EventKind> {
+    let mut kinds = Vec::new();
+    if readiness.is_readable() && interest.is_readable() {
+        kinds.push(EventKind::Readable);
+    }
+    if readiness.is_writable() && interest.is_writable() {
+        kinds.push(EventKind::Writable);
+    }
+    if proc_imp::is_error(readiness) {
+        kinds.push(EventKind::Error);
+    } else if proc_imp::is_hangup(readiness) {
+        if interest.is_readable() {
+            kinds.push(EventKind::ReadClosed);
+        }
+        if interest.is_writable() {
+            kinds.push(EventKind::WriteClosed);
+        }
+    }
+    kinds
+}
+
+// the timer of a registration made with `register_reader_with_timeout`, shared by
+// its callbacks.
+struct IdleTimeout<F> {
+    object_id: ObjectId,
+    timeout: Duration,
+    token: Rc<Cell<Option<Token>>>,
+    // the pending timer, if it hasn't fired since the last event.
+    timer: Rc<Cell<Option<TimerId>>>,
+    on_timeout: Rc<RefCell<F>>,
+}
+
+impl<F> Clone for IdleTimeout<F> {
+    fn clone(&self) -> Self {
+        IdleTimeout {
+            object_id: self.object_id,
+            timeout: self.timeout,
+            token: self.token.clone(),
+            timer: self.timer.clone(),
+            on_timeout: self.on_timeout.clone(),
+        }
+    }
+}
+
+impl<F> IdleTimeout<F> {
+    fn restart<T>(&self, core: &mut Core)
+    where
+        F: 'static + FnMut(&mut T, &mut Core),
+        T: Any,
+    {
+        if let Some(timer_id) = self.timer.get() {
+            if core.reset_timer(timer_id, self.timeout) {
+                return;
+            }
+        }
+        let idle = self.clone();
+        let timer_id =
+            core.set_timeout(self.timeout, self.object_id, move |object: &mut T, core| {
+                idle.timer.set(None);
+                // the source may have been deregistered while the object lives on.
+                let registered = idle
+                    .token
+                    .get()
+                    .is_some_and(|token| core.is_registered(token, idle.object_id));
+                if registered {
+                    (RefCell::borrow_mut(&idle.on_timeout))(object, core);
+                }
+            });
+        self.timer.set(Some(timer_id));
+    }
+}
+
+// a callback that only needs its object to still exist, not the object itself.
+struct CoreCallback<F>(F);
+
+impl<F> Call for CoreCallback<F>
+where
+    F: FnMut(&mut Core),
+{
+    fn make_call(&mut self, _object: &mut dyn Any, core: &mut Core) {
+        (self.0)(core)
+    }
+}
+
+// a callback for an object, for hooks that aren't tied to IO.
+struct ObjectCallback {
+    object_id: ObjectId,
+    callback: Box<dyn Call>,
+}
+
+// the callback is taken out while it runs, so it can be unregistered meanwhile.
+struct IdleHandler {
+    id: IdleId,
+    object_id: ObjectId,
+    callback: Option<Box<dyn Call>>,
+}
+
 pub struct Core {
     io_handlers: Stash<Option<IoHandler>, Token>,
-    objects: Stash<Option<Box<Any>>, ObjectId>,
+    // the interest each registration in `io_handlers` was last registered with.
+    interests: Vec<Ready>,
+    // the object each registration in `io_handlers` was made for, if any.
+    owners: Vec<Option<ObjectId>>,
+    // the priority of each registration in `io_handlers`.
+    priorities: Vec<Priority>,
+    // how each registration in `io_handlers` is triggered.
+    triggers: Vec<Trigger>,
+    objects: Stash<Option<Box<dyn Any>>, ObjectId>,
+    // number of times an object has been put into each slot of `objects`, used to
+    // tell objects that were removed apart from objects that were never added.
+    generations: Vec<u32>,
+    // the registrations made for each object, which are dropped together with it.
+    object_tokens: Vec<Vec<Token>>,
+    // the `LoopObject::on_remove` of each object added with `add_loop_object`.
+    remove_hooks: Vec<Option<RemoveHook>>,
+    // hooks of objects removed while borrowed, to call once their callback returns.
+    deferred_remove_hooks: Vec<(ObjectId, RemoveHook)>,
+    pending_events: Vec<PendingEvent>,
+    tick_handlers: Vec<ObjectCallback>,
+    reload_handlers: Vec<ObjectCallback>,
+    shutdown_handlers: Vec<ObjectCallback>,
+    // when a shutdown started with `shutdown` gives up waiting, and exits anyway.
+    shutdown_deadline: Option<Instant>,
+    // ordered by id, as new handlers are added at the end.
+    idle_handlers: Vec<IdleHandler>,
+    next_idle_id: u64,
+    // whether there has been IO since the idle handlers last ran.
+    idle_due: bool,
+    // callbacks queued with `post`, run before the next poll.
+    posted: Vec<(Priority, ObjectCallback)>,
+    // closures queued with `defer`, run once the current batch of callbacks is done.
+    deferred: Vec<Deferred>,
+    // buffers for `read_chunks`, shared by all readers as they read one at a time.
+    chunk_buffers: Vec<ChunkBuffers>,
+    // whether SIGHUP is being watched for `on_reload`.
+    watching_reload: bool,
+    orphan_hook: Option<Box<dyn FnMut(Token, Ready)>>,
+    // the object errors are reported to, see `set_error_sink`.
+    error_sink: Option<(ObjectId, Box<dyn ErrorCall>)>,
+    timers: timer::Timers,
+    remote: Option<Remote>,
+    waker: Option<Waker>,
+    // maps to apply to objects once their current callback returns, see `map_object`.
+    object_maps: Vec<(ObjectId, ObjectMap)>,
+    // number of registrations in `io_handlers` made by the core itself.
+    internal_handlers: usize,
+    // the objects the core added for itself, which are left out of `iter_ids`.
+    internal_objects: Vec<ObjectId>,
     poll: Poll,
+    // the buffer every poll reads events into, sized by `with_capacity`.
+    events: MioEvents,
+    // the events of a poll, ordered by priority for dispatching.
+    batch: Vec<(Priority, Token, Ready)>,
+    // when the event loop last woke up, see `now`.
+    now: Instant,
+    #[cfg(feature = "stats")]
+    stats: stats::Recorder,
+    // the type names of the objects by id, for the tracing spans.
+    #[cfg(feature = "tracing")]
+    type_names: Vec<&'static str>,
+    panic_policy: PanicPolicy,
+    panic_hook: Option<PanicHook>,
     exit: bool,
     process_handler: proc_imp::ProcessHandler,
 }
@@ -72,20 +466,160 @@ impl Default for Core {
 }
 
 impl Core {
+    /// Creates a core with the default options, see `CoreBuilder` for others.
+    ///
+    /// Panics if the core can't be set up, which `CoreBuilder::build` reports as an
+    /// error instead.
     pub fn new() -> Core {
-        proc_imp::new_core()
+        CoreBuilder::new().build().unwrap()
     }
 
+    /// Creates a core with room for the given number of events per poll and objects.
+    ///
+    /// `new` reads up to 32 events per poll, and grows its storage as objects are
+    /// added. A server handling many connections at once can avoid this growth, and
+    /// wake up less often when busy, by sizing the core up front.
+    pub fn with_capacity(events: usize, objects: usize) -> Core {
+        CoreBuilder::new()
+            .events(events)
+            .objects(objects)
+            .build()
+            .unwrap()
+    }
+
+    /// Returns the id that the next added object will get.
+    ///
+    /// Registrations and reapers can be made against this id before the object is
+    /// added. Events and exits of children arriving before then are held back and
+    /// delivered once the object is added, as long as that happens within a few
+    /// iterations of the event loop.
     pub fn next_id(&self) -> ObjectId {
         self.objects.next_index()
     }
 
     pub fn add(&mut self, object: impl Any) -> ObjectId {
-        self.objects.put(Some(Box::new(object)))
+        #[cfg(feature = "tracing")]
+        let type_name = std::any::type_name_of_val(&object);
+        let object_id = self.objects.put(Some(Box::new(object)));
+        self.mark_added(object_id);
+        #[cfg(feature = "tracing")]
+        self.set_type_name(object_id, type_name);
+        object_id
+    }
+
+    /// Adds an object to the core, returning a handle that remembers its type.
+    pub fn add_typed<T: Any>(&mut self, object: T) -> Handle<T> {
+        Handle::new(self.add(object))
+    }
+
+    /// Adds an object to the core that gets its `on_remove` called when it is removed.
+    pub fn add_loop_object<T: LoopObject>(&mut self, object: T) -> ObjectId {
+        let object_id = self.add(object);
+        let idx: usize = object_id.into();
+        if self.remove_hooks.len() <= idx {
+            self.remove_hooks.resize(idx + 1, None);
+        }
+        self.remove_hooks[idx] = Some(loop_object::remove_hook::<T>);
+        object_id
+    }
+
+    /// Returns a typed handle for the object with the given id, if it is of type `T`.
+    ///
+    /// Like `get`, this returns `None` while the object's own callback runs.
+    pub fn handle<T: Any>(&self, object_id: ObjectId) -> Option<Handle<T>> {
+        self.get::<T>(object_id)?;
+        Some(Handle::new(object_id))
+    }
+
+    /// Removes the object with the given id from the core, along with its IO
+    /// registrations and reapers.
+    ///
+    /// Returns the object, unless it is borrowed by one of its own callbacks, in which
+    /// case it is dropped once that callback returns. Sources owned by the object are
+    /// closed when it is dropped, which also takes them out of the poll. Sources kept
+    /// open elsewhere should be deregistered first. For objects added with
+    /// `add_loop_object`, `on_remove` is called first, or once the callback returns if
+    /// the object is borrowed.
+    pub fn remove(&mut self, object_id: ObjectId) -> Option<Box<dyn Any>> {
+        let mut object = self.objects.take(object_id)?;
+        let idx: usize = object_id.into();
+        if let Some(hook) = self.remove_hooks.get_mut(idx).and_then(Option::take) {
+            match &mut object {
+                Some(object) => hook(object.as_mut(), self),
+                None => self.deferred_remove_hooks.push((object_id, hook)),
+            }
+        }
+        self.unregister_object(object_id);
+        object
+    }
+
+    /// Drops the registrations and reapers made for the object with the given id.
+    ///
+    /// This is for giving up on an object that hasn't been added yet, e.g. because one
+    /// of the registrations made for it with `next_id` failed, so that the next object
+    /// added doesn't inherit the others. Sources stay in the poll until they are
+    /// closed or deregistered.
+    pub fn unregister_object(&mut self, object_id: ObjectId) {
+        let idx: usize = object_id.into();
+        if let Some(tokens) = self.object_tokens.get_mut(idx) {
+            for token in mem::take(tokens) {
+                self.io_handlers.take(token);
+            }
+        }
+        self.process_handler.drop_object_reapers(object_id);
+    }
+
+    /// Removes the object with the given id from the core and returns it by value.
+    ///
+    /// Returns `None`, leaving the object in place, if it isn't of type `T`. While an
+    /// object's own callback runs, the object is borrowed by that callback and can't
+    /// be taken, so this returns `None` too. Use `map_object` to consume an object
+    /// from its own callbacks.
+    pub fn take_object<T: Any>(&mut self, object_id: ObjectId) -> Option<T> {
+        self.get::<T>(object_id)?;
+        self.remove(object_id)
+            .and_then(|object| object.downcast().ok())
+            .map(|object| *object)
+    }
+
+    /// Replaces the object with the given id by the result of calling `f` on it.
+    ///
+    /// The new object keeps the id of the old one, so this allows a state machine to
+    /// move from one state to the next by value, e.g. for `Child::close_stdin`.
+    /// Callbacks registered for the old type won't be called for the new one, unless
+    /// the types are the same. If the object is currently borrowed by one of its own
+    /// callbacks, `f` is called right after that callback returns. Nothing happens if
+    /// the object isn't of type `T`.
+    pub fn map_object<T, U, F>(&mut self, object_id: ObjectId, f: F)
+    where
+        T: Any,
+        U: Any,
+        F: 'static + FnOnce(T, &mut Core) -> U,
+    {
+        let map: ObjectMap = Box::new(move |object, core| match object.downcast::<T>() {
+            Ok(object) => {
+                #[cfg(feature = "tracing")]
+                core.set_type_name(object_id, std::any::type_name::<U>());
+                Box::new(f(*object, core))
+            }
+            Err(object) => object,
+        });
+        if let Some(object) = self.objects.get_mut(object_id).and_then(Option::take) {
+            let object = map(object, self);
+            if let Some(option) = self.objects.get_mut(object_id) {
+                *option = Some(object);
+            }
+        } else if let Some(None) = self.objects.get(object_id) {
+            self.object_maps.push((object_id, map));
+        }
     }
 
-    pub fn remove(&mut self, object_id: ObjectId) -> Option<Box<Any>> {
-        self.objects.take(object_id).unwrap_or(None)
+    /// Returns whether there is an object with the given id.
+    ///
+    /// Unlike `get`, this is also true while the object is borrowed by one of its own
+    /// callbacks.
+    pub fn contains(&self, object_id: ObjectId) -> bool {
+        self.objects.get(object_id).is_some()
     }
 
     pub fn get<T: Any>(&self, object_id: ObjectId) -> Option<&T> {
@@ -93,7 +627,7 @@ impl Core {
             .get(object_id)
             .and_then(Option::as_ref)
             .map(Borrow::borrow)
-            .and_then(Any::downcast_ref)
+            .and_then(<dyn Any>::downcast_ref)
     }
 
     pub fn get_mut<T: Any>(&mut self, object_id: ObjectId) -> Option<&mut T> {
@@ -101,10 +635,55 @@ impl Core {
             .get_mut(object_id)
             .and_then(Option::as_mut)
             .map(BorrowMut::borrow_mut)
-            .and_then(Any::downcast_mut)
+            .and_then(<dyn Any>::downcast_mut)
+    }
+
+    /// Returns the ids of the objects in the core, in no particular order.
+    ///
+    /// This includes objects borrowed by their own callbacks, but not the ones the
+    /// core adds for itself. Collect the ids first to add or remove objects while
+    /// going through them.
+    pub fn iter_ids(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        self.objects
+            .iter()
+            .map(|(object_id, _)| object_id)
+            .filter(move |object_id| !self.internal_objects.contains(object_id))
     }
 
-    pub fn register_reader<F, T>(&mut self, evented: &Evented, object_id: ObjectId, f: F)
+    /// Returns the ids of the objects of type `T`, e.g. to broadcast to all
+    /// connections of a kind.
+    ///
+    /// Like `get`, this leaves out objects while they are borrowed by their own
+    /// callbacks.
+    pub fn ids_of<T: Any>(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        self.iter_ids()
+            .filter(move |object_id| self.get::<T>(*object_id).is_some())
+    }
+
+    /// Returns the number of objects in the core, counted like for `iter_ids`.
+    pub fn len(&self) -> usize {
+        let internal = self.internal_objects.iter();
+        self.objects.len() - internal.filter(|id| self.contains(**id)).count()
+    }
+
+    /// Returns whether there are no objects in the core, see `len`.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Registers `f` to be called on the object with the given id when `evented`
+    /// becomes readable.
+    ///
+    /// Returns the token identifying the registration, which is also what the poll
+    /// reports events with. Fails if `evented` can't be added to the poll, e.g. because
+    /// the process is out of file descriptors or `evented` is already registered. The
+    /// same goes for the other registration methods.
+    pub fn register_reader<F, T>(
+        &mut self,
+        evented: &dyn Source,
+        object_id: ObjectId,
+        f: F,
+    ) -> io::Result<Token>
     where
         F: 'static + FnMut(&mut T, &mut Core),
         T: Any,
@@ -115,10 +694,47 @@ impl Core {
             object_id,
             Some(Box::new(Callback::new(f))),
             None,
-        );
+            None,
+        )
+    }
+
+    /// Like `register_reader`, but with the given triggering instead of `Edge`.
+    ///
+    /// With `Trigger::Level` a callback doesn't need to read until the source would
+    /// block, but must stop the source from being ready in some other way once it is
+    /// done with it, e.g. by deregistering it at the end of the stream, or it will be
+    /// called over and over. Fails with `ErrorKind::Unsupported` for `Trigger::Level`
+    /// on Windows.
+    pub fn register_reader_with_opts<F, T>(
+        &mut self,
+        evented: &dyn Source,
+        object_id: ObjectId,
+        trigger: Trigger,
+        f: F,
+    ) -> io::Result<Token>
+    where
+        F: 'static + FnMut(&mut T, &mut Core),
+        T: Any,
+    {
+        if cfg!(windows) && trigger == Trigger::Level {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Level triggering is not supported on Windows.",
+            ));
+        }
+        let token = self.register_for_object(evented, Ready::readable(), trigger, object_id)?;
+        if let Some(Some(io_handler)) = self.io_handlers.get_mut(token) {
+            io_handler.read_fn = Some(Box::new(Callback::new(f)));
+        }
+        Ok(token)
     }
 
-    pub fn register_writer<F, T>(&mut self, evented: &Evented, object_id: ObjectId, f: F)
+    pub fn register_writer<F, T>(
+        &mut self,
+        evented: &dyn Source,
+        object_id: ObjectId,
+        f: F,
+    ) -> io::Result<Token>
     where
         F: 'static + FnMut(&mut T, &mut Core),
         T: Any,
@@ -129,16 +745,18 @@ impl Core {
             object_id,
             None,
             Some(Box::new(Callback::new(f))),
-        );
+            None,
+        )
     }
 
     pub fn register_reader_writer<FR, FW, T>(
         &mut self,
-        evented: &Evented,
+        evented: &dyn Source,
         object_id: ObjectId,
         f_read: FR,
         f_write: FW,
-    ) where
+    ) -> io::Result<Token>
+    where
         FR: 'static + FnMut(&mut T, &mut Core),
         FW: 'static + FnMut(&mut T, &mut Core),
         T: Any,
@@ -149,118 +767,1880 @@ impl Core {
             object_id,
             Some(Box::new(Callback::new(f_read))),
             Some(Box::new(Callback::new(f_write))),
-        );
+            None,
+        )
     }
 
-    pub fn register_reaper<F, T, S>(&mut self, child: &Child<S>, object_id: ObjectId, f: F)
+    /// Registers a writer, with a separate callback for when the other end hangs up.
+    ///
+    /// Once the reading end of a pipe or socket is closed, e.g. because a child exited
+    /// before reading all of its stdin, all further writes fail. Instead of calling
+    /// `f_write` and having it run into a broken pipe error, `f_hangup` is then called,
+    /// giving the object a chance to stop writing and clean up. On Windows hangups
+    /// are not reported separately and show up as write errors.
+    pub fn register_writer_with_hangup<FW, FH, T>(
+        &mut self,
+        evented: &dyn Source,
+        object_id: ObjectId,
+        f_write: FW,
+        f_hangup: FH,
+    ) -> io::Result<Token>
     where
-        F: 'static + FnMut(&mut T, &mut Core),
+        FW: 'static + FnMut(&mut T, &mut Core),
+        FH: 'static + FnMut(&mut T, &mut Core),
         T: Any,
     {
-        proc_imp::register_reaper(self, child, object_id, f);
+        self.internal_register(
+            evented,
+            Ready::writable(),
+            object_id,
+            None,
+            Some(Box::new(Callback::new(f_write))),
+            Some(Box::new(Callback::new(f_hangup))),
+        )
     }
 
-    pub fn run(&mut self) {
-        let mut mio_events = MioEvents::with_capacity(32);
-        loop {
-            if self.exit || self.io_handlers.is_empty() {
-                break;
-            }
-            trace!("About to sleep and wait for IO events.");
-            self.poll.poll(&mut mio_events, None).unwrap();
-            for event in &mio_events {
-                let token = event.token();
-                let mut io_handler = match self.io_handlers.get_mut(token).and_then(Option::take) {
-                    Some(handler) => handler,
-                    None => continue,
-                };
-                let obj_exists = self.call_on_object(io_handler.object_id, |object, core| {
-                    if let Some(read_fn) = &mut io_handler.read_fn {
-                        if event.readiness().is_readable() {
-                            read_fn.make_call(object, core);
-                        }
-                    }
-                    if let Some(write_fn) = &mut io_handler.write_fn {
-                        if event.readiness().is_writable() {
-                            write_fn.make_call(object, core);
-                        }
-                    }
-                });
-                if !obj_exists {
-                    self.io_handlers.take(token);
-                }
-                if let Some(option) = self.io_handlers.get_mut(token) {
-                    *option = Some(io_handler);
-                }
-            }
+    /// Registers `f` to be called with what happened whenever there are events for
+    /// `evented`.
+    ///
+    /// `interest` is readable, writable or both. Unlike with the other registration
+    /// methods, the callback can tell the other end closing or an error apart from the
+    /// source just being ready, without a read of zero bytes or a failing write. For a
+    /// single event `f` may be called several times, first for readability and
+    /// writability, then for the other end closing or an error. On Windows only
+    /// readability and writability are reported.
+    pub fn register_with_events<F, T>(
+        &mut self,
+        evented: &dyn Source,
+        interest: Ready,
+        object_id: ObjectId,
+        f: F,
+    ) -> io::Result<Token>
+    where
+        F: 'static + FnMut(&mut T, EventKind, &mut Core),
+        T: Any,
+    {
+        let token = self.internal_register(evented, interest, object_id, None, None, None)?;
+        if let Some(Some(io_handler)) = self.io_handlers.get_mut(token) {
+            io_handler.event_fn = Some(Box::new(Callback::new(f)));
         }
+        Ok(token)
     }
 
-    pub fn exit(&mut self) {
-        self.exit = true;
+    /// Registers the source that `source` returns for `object`, along with the object
+    /// itself, which is owned by the registration.
+    ///
+    /// `f` is called with the object and the event whenever there are events for the
+    /// source, which is usually a field of the object. As the type of the object is
+    /// known to the registration, the call is made directly, without looking the
+    /// object up and checking its type the way callbacks of objects added with `add`
+    /// are, which adds up for sources with a high rate of events. In turn the object
+    /// doesn't get an id, so there are no timers or other callbacks for it. Use
+    /// `owned_mut` to get to it from elsewhere, and `drop_owned` or `deregister` to
+    /// drop it.
+    pub fn register_owned<S, F, T>(
+        &mut self,
+        object: T,
+        source: S,
+        interest: Ready,
+        f: F,
+    ) -> io::Result<Token>
+    where
+        S: FnOnce(&T) -> &dyn Source,
+        F: 'static + FnMut(&mut T, Event, &mut Core),
+        T: Any,
+    {
+        let owned = Owned { f, object };
+        let evented = source(&owned.object);
+        let token = self.register_token(evented, interest, Trigger::Edge, None)?;
+        self.io_handlers.put(Some(IoHandler {
+            object_id: None,
+            generation: 0,
+            read_fn: None,
+            write_fn: None,
+            hangup_fn: None,
+            event_fn: None,
+            owned: Some(Box::new(owned)),
+        }));
+        Ok(token)
     }
 
-    /// Starts running the given command.
+    /// Returns the object of the registration with the given token, if it was made
+    /// with `register_owned` for an object of type `T`.
     ///
-    /// All three of stdin, stdout and stderr will be piped to/from this process.
-    pub fn spawn(&self, mut cmd: impl BorrowMut<Command>) -> io::Result<Child<Stdin>> {
-        // this is a method on core which takes a self parameter just to ensure that
-        // a Core instance has been created first, needed for unix imp to register
-        // a signal handler.
-        let cmd = cmd.borrow_mut();
-        cmd.stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-        proc_imp::new_child(cmd.spawn()?)
+    /// Like `get_mut`, this returns `None` while the registration's callback runs.
+    pub fn owned_mut<T: Any>(&mut self, token: Token) -> Option<&mut T> {
+        self.io_handlers
+            .get_mut(token)
+            .and_then(Option::as_mut)
+            .and_then(|io_handler| io_handler.owned.as_mut())
+            .and_then(|owned| owned.object().downcast_mut())
     }
 
-    fn call_on_object(&mut self, object_id: ObjectId, f: impl FnOnce(&mut Any, &mut Core)) -> bool {
-        if let Some(mut box_object) = self.objects.get_mut(object_id).and_then(Option::take) {
-            f(box_object.borrow_mut(), self);
-            if let Some(option) = self.objects.get_mut(object_id) {
-                *option = Some(box_object);
-                return true;
+    /// Drops the registration with the given token along with its object, if it was
+    /// made with `register_owned`.
+    ///
+    /// Returns whether there was such a registration. Sources owned by the object are
+    /// closed, which also takes them out of the poll. This doesn't work from the
+    /// registration's own callback, which can call `deregister` with its source
+    /// instead, after which the object is dropped once the callback returns.
+    pub fn drop_owned(&mut self, token: Token) -> bool {
+        match self.io_handlers.get(token) {
+            Some(Some(io_handler)) if io_handler.owned.is_some() => {
+                self.drop_registration(token);
+                true
             }
+            _ => false,
         }
-        false
     }
 
-    fn internal_register(
+    /// Turns interest in writability on or off for a registration.
+    ///
+    /// `evented` must be what the registration with the given token was made for.
+    /// Turning interest back on reports the source as writable right away if it is,
+    /// so an object can register as a reader and writer, switch writes off while it
+    /// has nothing to send and switch them on again once it queues data. Only
+    /// registrations with a write callback are ever called for writability.
+    pub fn set_write_interest(
         &mut self,
-        e: &Evented,
-        r: Ready,
-        object_id: ObjectId,
-        read_fn: Option<Box<Call>>,
-        write_fn: Option<Box<Call>>,
-    ) {
-        let token = self.io_handlers.next_index();
-        self.poll.register(e, token, r, PollOpt::edge()).unwrap();
-        self.io_handlers.put(Some(IoHandler {
-            object_id,
-            read_fn,
-            write_fn,
-        }));
+        evented: &dyn Source,
+        token: Token,
+        enabled: bool,
+    ) -> io::Result<()> {
+        self.set_interest(evented, token, Ready::writable(), enabled)
     }
-}
-
-#[path = "process_unix.rs"]
-#[cfg(unix)]
-mod proc_imp;
+
+    /// Turns interest in readability on or off for a registration.
+    ///
+    /// Works like `set_write_interest`, which allows an object to stop being called
+    /// for incoming data while it can't take any more, e.g. to apply backpressure.
+    pub fn set_read_interest(
+        &mut self,
+        evented: &dyn Source,
+        token: Token,
+        enabled: bool,
+    ) -> io::Result<()> {
+        self.set_interest(evented, token, Ready::readable(), enabled)
+    }
+
+    /// Enables a registration made with `Trigger::Oneshot` again after it has been
+    /// reported.
+    ///
+    /// `evented` must be what the registration with the given token was made for.
+    /// Turning interest on or off with `set_read_interest` or `set_write_interest`
+    /// rearms the registration as well.
+    pub fn rearm(&mut self, evented: &dyn Source, token: Token) -> io::Result<()> {
+        if self.io_handlers.get(token).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No registration with the given token.",
+            ));
+        }
+        let idx: usize = token.into();
+        let registry = Registry::new(&self.poll);
+        evented.reregister(&registry, token, self.interests[idx], self.triggers[idx])
+    }
+
+    /// Sets the priority of a registration, which is `Normal` to begin with.
+    ///
+    /// Of the events arriving together, the ones for registrations with a higher
+    /// priority are dispatched first. This lets e.g. a control connection be served
+    /// before the output of a busy child is drained.
+    pub fn set_priority(&mut self, token: Token, priority: Priority) -> io::Result<()> {
+        if self.io_handlers.get(token).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No registration with the given token.",
+            ));
+        }
+        self.priorities[usize::from(token)] = priority;
+        Ok(())
+    }
+
+    fn set_interest(
+        &mut self,
+        evented: &dyn Source,
+        token: Token,
+        ready: Ready,
+        enabled: bool,
+    ) -> io::Result<()> {
+        if self.io_handlers.get(token).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No registration with the given token.",
+            ));
+        }
+        let idx: usize = token.into();
+        let mut interest = self.interests[idx];
+        if enabled {
+            interest.insert(ready);
+        } else {
+            interest.remove(ready);
+        }
+        let registry = Registry::new(&self.poll);
+        evented.reregister(&registry, token, interest, self.triggers[idx])?;
+        self.interests[idx] = interest;
+        Ok(())
+    }
+
+    /// Removes a registration, so its callbacks are never called again.
+    ///
+    /// `evented` must be what the registration with the given token was made for. This
+    /// is only needed when a source is dropped or closed while its object lives on;
+    /// registrations of removed objects are dropped by the core on their own.
+    pub fn deregister(&mut self, evented: &dyn Source, token: Token) -> io::Result<()> {
+        if self.io_handlers.get(token).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No registration with the given token.",
+            ));
+        }
+        evented.deregister(&Registry::new(&self.poll))?;
+        self.drop_registration(token);
+        Ok(())
+    }
+
+    pub fn register_reaper<F, T, S, O, E>(
+        &mut self,
+        child: &Child<S, O, E>,
+        object_id: ObjectId,
+        f: F,
+    ) where
+        F: 'static + FnMut(&mut T, &mut Core),
+        T: Any,
+    {
+        proc_imp::register_reaper(self, child, object_id, f);
+    }
+
+    /// Like `register_reaper`, but also passes the exit status of the child to `f`.
+    ///
+    /// The status is `None` only if it could not be read, which can happen on Windows.
+    pub fn register_reaper_with_status<F, T, S, O, E>(
+        &mut self,
+        child: &Child<S, O, E>,
+        object_id: ObjectId,
+        mut f: F,
+    ) where
+        F: 'static + FnMut(&mut T, Option<ExitStatus>, &mut Core),
+        T: Any,
+    {
+        let exit_status = child.exit_status.clone();
+        self.register_reaper(child, object_id, move |object: &mut T, core| {
+            f(object, exit_status.get(), core)
+        });
+    }
+
+    /// Like `register_reaper`, but also calls `on_still_running` if the child hasn't
+    /// exited within `deadline`, e.g. to terminate it.
+    ///
+    /// Either way the reaper goes on waiting for the child, and `f` is called once it
+    /// exits. Both callbacks are called on the object with the given id. The returned
+    /// timer is canceled once the child has been reaped, and can be canceled before
+    /// then to drop the deadline.
+    pub fn register_reaper_with_deadline<FS, F, T, S, O, E>(
+        &mut self,
+        child: &Child<S, O, E>,
+        object_id: ObjectId,
+        deadline: Duration,
+        on_still_running: FS,
+        mut f: F,
+    ) -> TimerId
+    where
+        FS: 'static + FnOnce(&mut T, &mut Core),
+        F: 'static + FnMut(&mut T, &mut Core),
+        T: Any,
+    {
+        let timer_id = self.set_timeout(deadline, object_id, on_still_running);
+        self.register_reaper(child, object_id, move |object: &mut T, core| {
+            core.cancel_timer(timer_id);
+            f(object, core)
+        });
+        timer_id
+    }
+
+    /// Registers a callback to run once at the end of every iteration of the event loop.
+    ///
+    /// The callback runs after all events of an iteration have been dispatched and
+    /// before the loop goes back to waiting for new events, which makes it a good
+    /// place to flush work batched up by the event handlers. It does not keep the
+    /// loop awake: an iteration only happens when there are events, so if nothing
+    /// happens the loop keeps sleeping and the callback is not run. The callback is
+    /// dropped once its object has been removed.
+    pub fn on_tick<F, T>(&mut self, object_id: ObjectId, f: F)
+    where
+        F: 'static + FnMut(&mut T, &mut Core),
+        T: Any,
+    {
+        self.tick_handlers.push(ObjectCallback {
+            object_id,
+            callback: Box::new(Callback::new(f)),
+        });
+    }
+
+    /// Registers a callback to run when the process is asked to reload its
+    /// configuration.
+    ///
+    /// On unix that is when it receives SIGHUP. On Windows it is when the named event
+    /// `Local\looper-reload-<pid>` is set, or when Ctrl-Break is pressed in its
+    /// console. `request_reload` does either for a given process.
+    ///
+    /// This allows long-running services to re-read their configuration and update
+    /// their objects in place, without restarting and dropping connections. The
+    /// callback is dropped once its object has been removed. Fails if reload requests
+    /// can't be watched for, e.g. because the process is out of file descriptors.
+    pub fn on_reload<F, T>(&mut self, object_id: ObjectId, f: F) -> io::Result<()>
+    where
+        F: 'static + FnMut(&mut T, &mut Core),
+        T: Any,
+    {
+        if !self.watching_reload {
+            proc_imp::watch_reload(self)?;
+            self.watching_reload = true;
+        }
+        self.reload_handlers.push(ObjectCallback {
+            object_id,
+            callback: Box::new(Callback::new(f)),
+        });
+        Ok(())
+    }
+
+    /// Calls the callbacks registered with `on_reload` right away.
+    pub fn reload(&mut self) {
+        self.call_each(|core| &mut core.reload_handlers);
+    }
+
+    /// Registers a callback to run once `shutdown` is called.
+    ///
+    /// This is where an object should stop taking on new work, e.g. by closing its
+    /// listening sockets, and start finishing what it has, so the loop can run out of
+    /// work and return. The callback is dropped once its object has been removed.
+    pub fn on_shutdown<F, T>(&mut self, object_id: ObjectId, f: F)
+    where
+        F: 'static + FnMut(&mut T, &mut Core),
+        T: Any,
+    {
+        self.shutdown_handlers.push(ObjectCallback {
+            object_id,
+            callback: Box::new(Callback::new(f)),
+        });
+    }
+
+    /// Registers a callback to run when the event loop becomes idle, for low-priority
+    /// work such as flushing statistics.
+    ///
+    /// The loop is idle once it has gone a short while without IO events, timers or
+    /// posted callbacks. The callback then runs once, and not again until the loop
+    /// has had more to do, so it doesn't keep the loop busy. Like `on_tick` it doesn't
+    /// keep `run` from returning. The callback is dropped once its object has been
+    /// removed.
+    pub fn register_idle<F, T>(&mut self, object_id: ObjectId, f: F) -> IdleId
+    where
+        F: 'static + FnMut(&mut T, &mut Core),
+        T: Any,
+    {
+        let id = IdleId(self.next_idle_id);
+        self.next_idle_id += 1;
+        self.idle_handlers.push(IdleHandler {
+            id,
+            object_id,
+            callback: Some(Box::new(Callback::new(f))),
+        });
+        id
+    }
+
+    /// Drops a callback registered with `register_idle`, so it is never called again.
+    ///
+    /// Returns false if there was no such callback.
+    pub fn unregister_idle(&mut self, idle_id: IdleId) -> bool {
+        match self.idle_handlers.iter().position(|h| h.id == idle_id) {
+            Some(pos) => {
+                self.idle_handlers.remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Queues `f` to be called on the object with the given id before the event loop
+    /// next waits for events.
+    ///
+    /// This lets an object hand work to another one without borrowing it, so it works
+    /// even for the object whose callback is currently running. Posted callbacks run
+    /// in order, and are dropped if their object is gone by then.
+    pub fn post<F, T>(&mut self, object_id: ObjectId, f: F)
+    where
+        F: 'static + FnOnce(&mut T, &mut Core),
+        T: Any,
+    {
+        self.post_with_priority(object_id, Priority::Normal, f);
+    }
+
+    /// Like `post`, but runs `f` before the posted callbacks with a lower priority.
+    pub fn post_with_priority<F, T>(&mut self, object_id: ObjectId, priority: Priority, f: F)
+    where
+        F: 'static + FnOnce(&mut T, &mut Core),
+        T: Any,
+    {
+        let callback = ObjectCallback {
+            object_id,
+            callback: Box::new(Callback::new(once(f))),
+        };
+        self.posted.push((priority, callback));
+    }
+
+    /// Queues `f` to be called once the callbacks for the current batch of events,
+    /// timers or posted callbacks have all run.
+    ///
+    /// Unlike a callback, `f` isn't tied to an object, and no object is borrowed
+    /// while it runs. This makes it safe to do anything there, e.g. remove several
+    /// objects including the one whose callback deferred it, or take objects out of
+    /// the core by value. Closures deferred by `f` run after the ones already queued,
+    /// before the loop moves on. Closures deferred outside of the loop run at the
+    /// start of its next iteration.
+    pub fn defer<F>(&mut self, f: F)
+    where
+        F: 'static + FnOnce(&mut Core),
+    {
+        self.deferred.push(Box::new(f));
+    }
+
+    /// Makes the object with the given id the one errors are reported to.
+    ///
+    /// Components that drop a resource because of an error, such as a connection
+    /// whose socket failed, pass the error to `report_error` along with the id of
+    /// the object that failed, which `f` is called with. This lets a supervising
+    /// object react to failures instead of them only being logged. Replaces any
+    /// earlier sink.
+    pub fn set_error_sink<F, T>(&mut self, object_id: ObjectId, f: F)
+    where
+        F: 'static + FnMut(&mut T, ObjectId, &(dyn Error + 'static), &mut Core),
+        T: Any,
+    {
+        self.error_sink = Some((object_id, Box::new(Callback::new(f))));
+    }
+
+    /// Reports an error that made the object with id `source` fail to the error sink.
+    ///
+    /// Returns whether the error was passed on, which it isn't if there is no sink,
+    /// or while one of the sink's own callbacks runs.
+    pub fn report_error(&mut self, source: ObjectId, error: &(dyn Error + 'static)) -> bool {
+        let (sink_id, mut callback) = match self.error_sink.take() {
+            Some(sink) => sink,
+            None => return false,
+        };
+        let reported = self.call_on_object(sink_id, |object, core| {
+            callback.make_call(object, source, error, core)
+        });
+        // the callback may have set a different sink.
+        if self.error_sink.is_none() && self.contains(sink_id) {
+            self.error_sink = Some((sink_id, callback));
+        }
+        reported
+    }
+
+    /// Returns a handle that other threads can use to run code on this core.
+    ///
+    /// Code sent through the handle runs on the thread running the event loop, which
+    /// is woken up for it. Remotes don't keep `run` from returning once there is no
+    /// other work left. Fails if the remote can't be registered with the core.
+    pub fn remote(&mut self) -> io::Result<Remote> {
+        if self.remote.is_none() {
+            self.remote = Some(remote::new_remote(self)?);
+        }
+        Ok(self.remote.clone().unwrap())
+    }
+
+    /// Calls `f` on the object with the given id with everything read from our stdin.
+    ///
+    /// `f` gets `None` once the input has ended, after which the registration is
+    /// dropped, so it doesn't keep `run` from returning. Where stdin can be polled it
+    /// is made non-blocking until the object is removed, otherwise it is read by a
+    /// thread of its own, which is always the case on Windows. Stdin should only be
+    /// registered once, see `register_stdin_lines` for reading it line by line.
+    pub fn register_stdin<F, T>(&mut self, object_id: ObjectId, f: F) -> io::Result<()>
+    where
+        F: 'static + FnMut(&mut T, Option<&[u8]>, &mut Core),
+        T: Any,
+    {
+        stdin::register(self, object_id, f)
+    }
+
+    /// Like `register_stdin`, but calls `f` with every line read from stdin.
+    ///
+    /// Lines are split and decoded like with `Utf8LineReader`.
+    pub fn register_stdin_lines<F, T>(&mut self, object_id: ObjectId, mut f: F) -> io::Result<()>
+    where
+        F: 'static + FnMut(&mut T, Option<String>, &mut Core),
+        T: Any,
+    {
+        let mut lines = Utf8LineReader::new();
+        self.register_stdin(object_id, move |object: &mut T, bytes, core| {
+            let mut found = Vec::new();
+            match bytes {
+                Some(bytes) => lines.push(bytes, |line| found.push(line)),
+                None => lines.finish(|line| found.push(line)),
+            }
+            for line in found {
+                f(object, Some(line), core);
+            }
+            if bytes.is_none() {
+                f(object, None, core);
+            }
+        })
+    }
+
+    /// Returns a handle for waking the core up, e.g. from a signal handler.
+    ///
+    /// Like remotes, wakers don't keep `run` from returning once there is no other
+    /// work left. Fails if the pipe for the wakeups can't be set up.
+    pub fn waker(&mut self) -> io::Result<Waker> {
+        if self.waker.is_none() {
+            self.waker = Some(waker::new_waker(self)?);
+        }
+        Ok(self.waker.clone().unwrap())
+    }
+
+    /// Calls `f` on the object with the given id once `delay` has passed.
+    ///
+    /// The timer is dropped without firing if the object has been removed by then.
+    pub fn set_timeout<F, T>(&mut self, delay: Duration, object_id: ObjectId, f: F) -> TimerId
+    where
+        F: 'static + FnOnce(&mut T, &mut Core),
+        T: Any,
+    {
+        self.timers
+            .add(delay, None, object_id, Box::new(Callback::new(once(f))))
+    }
+
+    /// Calls `f` on the object with the given id every `interval`, until the timer is
+    /// canceled or the object is removed.
+    ///
+    /// The first call happens once `interval` has passed. If the event loop falls
+    /// behind by more than an interval, the missed calls are skipped rather than made
+    /// in a burst. Intervals shorter than a millisecond are rounded up to one.
+    pub fn set_interval<F, T>(&mut self, interval: Duration, object_id: ObjectId, f: F) -> TimerId
+    where
+        F: 'static + FnMut(&mut T, &mut Core),
+        T: Any,
+    {
+        let interval = interval.max(Duration::from_millis(1));
+        self.timers.add(
+            interval,
+            Some(interval),
+            object_id,
+            Box::new(Callback::new(f)),
+        )
+    }
+
+    /// Cancels a timer that has not fired yet, or stops a repeating timer.
+    ///
+    /// Returns false if there was no such timer.
+    pub fn cancel_timer(&mut self, timer_id: TimerId) -> bool {
+        self.timers.cancel(timer_id)
+    }
+
+    /// Changes a timer to fire once `delay` has passed from now instead of at its
+    /// original deadline.
+    ///
+    /// This is cheaper than canceling and setting a new timer, which matters for things
+    /// like idle timeouts that are pushed back on every message. Resetting a timer that
+    /// has already fired or been canceled does nothing and returns false. A repeating
+    /// timer fires after `delay` and then keeps its interval from there.
+    pub fn reset_timer(&mut self, timer_id: TimerId, delay: Duration) -> bool {
+        self.timers.reset(timer_id, delay)
+    }
+
+    /// Registers a reader that calls `f` with every line of text read from `source`.
+    ///
+    /// `source` stays with the object, and `access` is how the reader gets at it, e.g.
+    /// `|job: &mut Job| &mut job.child.stdout`. Lines are decoded like with
+    /// `Utf8LineReader`, and an unterminated last line is passed on at the end of the
+    /// stream. Read errors are logged.
+    pub fn read_lines<R, A, F, T>(
+        &mut self,
+        source: &R,
+        object_id: ObjectId,
+        access: A,
+        mut f: F,
+    ) -> io::Result<Token>
+    where
+        R: Read + Source,
+        A: 'static + Fn(&mut T) -> &mut R,
+        F: 'static + FnMut(&mut T, String, &mut Core),
+        T: Any,
+    {
+        let mut reader = Utf8LineReader::new();
+        self.register_reader(source, object_id, move |object: &mut T, core| {
+            // lines are collected first, as the source is borrowed from the object.
+            let mut lines = Vec::new();
+            if let Err(err) = reader.read_lines(access(object), |line| lines.push(line)) {
+                error!("Failed to read lines: {}", err);
+            }
+            for line in lines {
+                f(object, line, core);
+            }
+        })
+    }
+
+    /// Registers a reader that calls `f` with the bytes read from `source` as they
+    /// arrive, for sources with a lot of data such as the output of a busy child.
+    ///
+    /// Reads are vectored, filling up to four 16 KiB buffers with one system call, and
+    /// `f` is called with each filled buffer. The buffers are shared by all such
+    /// readers of the core rather than allocated for every read. `source` and
+    /// `access` work like with `read_lines`. Read errors are logged.
+    pub fn read_chunks<R, A, F, T>(
+        &mut self,
+        source: &R,
+        object_id: ObjectId,
+        access: A,
+        mut f: F,
+    ) -> io::Result<Token>
+    where
+        R: Read + Source,
+        A: 'static + Fn(&mut T) -> &mut R,
+        F: 'static + FnMut(&mut T, &[u8], &mut Core),
+        T: Any,
+    {
+        self.register_reader(source, object_id, move |object: &mut T, core| {
+            // taken out while reading, so a reader started by `f` gets buffers of its own.
+            let mut buffers = core
+                .chunk_buffers
+                .pop()
+                .unwrap_or_else(|| Box::new([[0; CHUNK_LEN]; CHUNKS_PER_READ]));
+            loop {
+                let mut slices = buffers.each_mut().map(|b| IoSliceMut::new(b));
+                let mut n = match access(object).read_vectored(&mut slices) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        error!("Failed to read chunks: {}", e);
+                        break;
+                    }
+                };
+                for buffer in buffers.iter() {
+                    let len = n.min(CHUNK_LEN);
+                    f(object, &buffer[..len], core);
+                    n -= len;
+                    if n == 0 {
+                        break;
+                    }
+                }
+            }
+            core.chunk_buffers.push(buffers);
+        })
+    }
+
+    /// Registers a reader that gives up if no data arrives within `timeout`.
+    ///
+    /// If the reader becomes readable first, the timeout is canceled and `on_data`
+    /// keeps being called for as long as the registration lives, like with
+    /// `register_reader`. If the timeout expires first, the registration is dropped
+    /// and `on_timeout` is called, so `on_data` is never called after `on_timeout`.
+    /// The source itself stays in the poll until it is closed or deregistered, which
+    /// `on_timeout` is the place to do. If the source is deregistered before either
+    /// happens, `on_timeout` isn't called.
+    pub fn read_with_timeout<FD, FT, T>(
+        &mut self,
+        evented: &dyn Source,
+        object_id: ObjectId,
+        timeout: Duration,
+        mut on_data: FD,
+        on_timeout: FT,
+    ) -> io::Result<Token>
+    where
+        FD: 'static + FnMut(&mut T, &mut Core),
+        FT: 'static + FnOnce(&mut T, &mut Core),
+        T: Any,
+    {
+        // holds the pending timer until data arrives or the timer fires.
+        let timer = Rc::new(Cell::new(None));
+        let reader_timer = timer.clone();
+        let token = self.register_reader(evented, object_id, move |object: &mut T, core| {
+            if let Some(timer_id) = reader_timer.take() {
+                core.cancel_timer(timer_id);
+            }
+            on_data(object, core);
+        })?;
+        let timeout_timer = timer.clone();
+        let timer_id = self.set_timeout(timeout, object_id, move |object: &mut T, core| {
+            // the reader may have been deregistered and its token handed to another
+            // registration, even one of the same object. Only the reader's callback
+            // holds another clone of `timer`, so that tells them apart.
+            let ours = Rc::strong_count(&timeout_timer) > 1;
+            if ours && core.is_registered(token, object_id) {
+                core.drop_registration(token);
+                on_timeout(object, core);
+            }
+        });
+        timer.set(Some(timer_id));
+        Ok(token)
+    }
+
+    /// Registers a reader that calls `on_timeout` whenever `timeout` passes without
+    /// any events for `evented`.
+    ///
+    /// Unlike with `read_with_timeout`, the timeout starts over with every event, so
+    /// this catches a source that stalls at any point, e.g. a child that hangs or a
+    /// peer that stops talking. The registration lives on after a timeout, so
+    /// `on_timeout` is usually where the object gives up and removes itself. If it
+    /// doesn't, the timeout starts again with the next event.
+    pub fn register_reader_with_timeout<FD, FT, T>(
+        &mut self,
+        evented: &dyn Source,
+        object_id: ObjectId,
+        mut on_data: FD,
+        timeout: Duration,
+        on_timeout: FT,
+    ) -> io::Result<Token>
+    where
+        FD: 'static + FnMut(&mut T, &mut Core),
+        FT: 'static + FnMut(&mut T, &mut Core),
+        T: Any,
+    {
+        let idle = IdleTimeout {
+            object_id,
+            timeout,
+            token: Rc::new(Cell::new(None)),
+            timer: Rc::new(Cell::new(None)),
+            on_timeout: Rc::new(RefCell::new(on_timeout)),
+        };
+        let reader_idle = idle.clone();
+        let token = self.register_reader(evented, object_id, move |object: &mut T, core| {
+            reader_idle.restart(core);
+            on_data(object, core);
+        })?;
+        idle.token.set(Some(token));
+        idle.restart(self);
+        Ok(token)
+    }
+
+    /// Sets a hook to call with the id of an object that was removed because one of its
+    /// callbacks panicked, along with the panic message.
+    ///
+    /// Only called with `PanicPolicy::RemoveObject`, see `CoreBuilder::panic_policy`.
+    /// The object is gone by the time the hook runs, but e.g. a replacement can be
+    /// added from it. Replaces any earlier hook.
+    pub fn on_panic<F>(&mut self, f: F)
+    where
+        F: 'static + FnMut(ObjectId, &str, &mut Core),
+    {
+        self.panic_hook = Some(Box::new(f));
+    }
+
+    /// Sets a hook to call with the token and readiness of events that arrive for a
+    /// registration that no longer exists.
+    ///
+    /// Such events are normally ignored, but can point to races between removing
+    /// objects and their IO still being active, so this can help with debugging.
+    pub fn on_orphan_event(&mut self, f: impl 'static + FnMut(Token, Ready)) {
+        self.orphan_hook = Some(Box::new(f));
+    }
+
+    /// Returns what the event loop has been up to, to help with finding busy loops.
+    ///
+    /// This needs the `stats` feature, which also logs the stats every so many seconds
+    /// if the `LOOPER_STATS_INTERVAL` environment variable is set to a number of them.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> &Stats {
+        &self.stats.stats
+    }
+
+    /// Starts counting the stats from zero again.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.stats.stats = Stats::default();
+    }
+
+    /// Runs the event loop until `exit` is called or there is no work left.
+    ///
+    /// Fails if waiting for events fails, which leaves the core as it was, so `run`
+    /// may be called again.
+    pub fn run(&mut self) -> io::Result<()> {
+        while self.run_once(None)? {}
+        Ok(())
+    }
+
+    /// Runs one iteration of the event loop, waiting at most `timeout` for events.
+    ///
+    /// Runs the posted callbacks, polls for events and calls the callbacks for the
+    /// events that arrived, the timers that expired and `on_tick`. Returns whether
+    /// there is work left, i.e. false once `run` would have returned. This allows the
+    /// loop to be driven by another loop, such as the one of a GUI toolkit. A timeout
+    /// of `None` waits until there is an event or a timer expires.
+    pub fn run_once(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+        match self.panic_policy {
+            // panics are caught around each callback instead, see `call_on_object`.
+            PanicPolicy::Unwind | PanicPolicy::RemoveObject => self.run_iteration(timeout),
+            PanicPolicy::Abort => {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| self.run_iteration(timeout)));
+                // the panic message has been printed by the panic hook already.
+                result.unwrap_or_else(|_| process::abort())
+            }
+        }
+    }
+
+    fn run_iteration(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+        if self.exit || !self.has_user_work() {
+            return Ok(false);
+        }
+        self.now = Instant::now();
+        self.dispatch_pending();
+        if !self.posted.is_empty() {
+            self.idle_due = true;
+        }
+        self.run_posted();
+        self.run_deferred();
+        // the posted callbacks may have been the last work left, or called `exit`.
+        if self.exit || !self.has_user_work() {
+            return Ok(false);
+        }
+        trace!("About to sleep and wait for IO events.");
+        // callbacks posted by the ones that just ran are only run after the poll, so
+        // don't wait for events if there are any. The timeout is rounded up to whole
+        // milliseconds by mio, so the poll doesn't return just before a deadline and
+        // leave the timer to spin on.
+        let mut timeout = if self.posted.is_empty() {
+            match (self.timers.next_timeout(), timeout) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        } else {
+            Some(Duration::from_secs(0))
+        };
+        // only wait for the loop to become idle if it wouldn't wake up sooner anyway.
+        let waiting_for_idle = self.idle_due
+            && !self.idle_handlers.is_empty()
+            && timeout.is_none_or(|t| t > IDLE_WINDOW);
+        if waiting_for_idle {
+            timeout = Some(IDLE_WINDOW);
+        }
+        if let Some(deadline) = self.shutdown_deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            timeout = Some(timeout.map_or(left, |t| t.min(left)));
+        }
+        // the buffer is taken out while dispatching, as callbacks need the core.
+        let mut mio_events = mem::replace(&mut self.events, MioEvents::with_capacity(0));
+        if let Err(err) = self.poll.poll(&mut mio_events, timeout) {
+            self.events = mio_events;
+            return Err(err);
+        }
+
+        // timers set while handling these events wait for the next poll, even with
+        // no delay, so they can be used to yield to other IO.
+        self.now = Instant::now();
+        let mut batch = mem::take(&mut self.batch);
+        for event in &mio_events {
+            let priority = self
+                .priorities
+                .get(usize::from(event.token()))
+                .cloned()
+                .unwrap_or_default();
+            batch.push((priority, event.token(), event.readiness()));
+        }
+        let had_events = !mio_events.is_empty();
+        self.events = mio_events;
+        // the sort is stable, so events of the same priority keep their order.
+        batch.sort_by_key(|(priority, _, _)| *priority);
+        for (_, token, readiness) in batch.drain(..) {
+            #[cfg(feature = "stats")]
+            self.stats.event(token);
+            self.dispatch(token, readiness, PENDING_EVENT_POLLS);
+        }
+        self.batch = batch;
+        self.run_deferred();
+        let timers_fired = self.fire_timers(self.now);
+        #[cfg(feature = "stats")]
+        self.stats.wakeup(self.now, !had_events && !timers_fired);
+        self.tick();
+        if had_events || timers_fired {
+            self.idle_due = true;
+        } else if waiting_for_idle {
+            self.idle_due = false;
+            self.run_idle();
+        }
+        self.run_deferred();
+        if self
+            .shutdown_deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+        {
+            warn!("Shutdown grace period is over, exiting with work left.");
+            self.exit = true;
+        }
+        Ok(!self.exit && self.has_user_work())
+    }
+
+    /// Runs one iteration of the event loop without waiting for events.
+    ///
+    /// Meant to be called regularly from a loop that does its own waiting, see
+    /// `run_once`.
+    pub fn turn(&mut self) -> io::Result<bool> {
+        self.run_once(Some(Duration::from_secs(0)))
+    }
+
+    /// Returns whether there is anything left for the event loop to wait for.
+    ///
+    /// This is the case while there are registrations for IO, children that have not
+    /// exited yet, timers that have not fired yet or posted or deferred callbacks.
+    /// Registrations the core makes for itself, such as the handler for SIGCHLD on
+    /// unix, are not counted. `run` returns once this is false.
+    pub fn has_user_work(&self) -> bool {
+        self.io_handlers.len() > self.internal_handlers
+            || self.pending_reapers() > 0
+            || self.pending_timers() > 0
+            || !self.posted.is_empty()
+            || !self.deferred.is_empty()
+    }
+
+    /// Drops the reapers registered for the child with the given id, so they are never
+    /// called.
+    ///
+    /// Returns whether there were any. On unix the child is still waited for once it
+    /// exits, so it doesn't linger as a zombie, but that doesn't keep `run` from
+    /// returning.
+    pub fn unregister_reaper(&mut self, child_id: u32) -> bool {
+        self.process_handler.drop_reapers(child_id)
+    }
+
+    /// Returns the number of reapers waiting for their child to exit.
+    ///
+    /// Useful during a graceful shutdown, to know when all children are gone.
+    pub fn pending_reapers(&self) -> usize {
+        self.process_handler.reaper_count()
+    }
+
+    /// Returns the number of timers that have not fired yet.
+    pub fn pending_timers(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Returns the time at which the event loop last woke up.
+    ///
+    /// This is the same for all callbacks run for the events of one iteration, and
+    /// cheaper than calling `Instant::now` each time, so it suits timestamping messages
+    /// and the like. It lags behind the actual time by however long the callbacks run
+    /// before have taken.
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    pub fn exit(&mut self) {
+        self.exit = true;
+    }
+
+    /// Starts shutting down gracefully, exiting once all work is done or `grace` has
+    /// passed.
+    ///
+    /// Unlike `exit`, which returns from `run` right away, this lets objects finish
+    /// up: the `on_shutdown` callbacks are run before the next poll, children with a
+    /// reaper are asked to exit (with SIGTERM on unix, while on Windows they are
+    /// killed), and no new IO registrations are accepted. `run` then keeps running
+    /// until there is no work left, e.g. once queued writes have been flushed and
+    /// children have been reaped, or until the grace period is over. Calling this
+    /// again during a shutdown does nothing.
+    pub fn shutdown(&mut self, grace: Duration) {
+        if self.shutdown_deadline.is_some() {
+            return;
+        }
+        self.shutdown_deadline = Some(Instant::now() + grace);
+        let handlers = mem::take(&mut self.shutdown_handlers);
+        self.posted
+            .extend(handlers.into_iter().map(|h| (Priority::Normal, h)));
+        self.process_handler.terminate_all();
+    }
+
+    /// Returns whether `shutdown` has been called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_deadline.is_some()
+    }
+
+    /// Starts running the given command.
+    ///
+    /// All three of stdin, stdout and stderr will be piped to/from this process. If the
+    /// command fails to start, the returned error names the program that was run.
+    pub fn spawn(&self, mut cmd: impl BorrowMut<Command>) -> io::Result<Child<Stdin>> {
+        // this is a method on core which takes a self parameter just to ensure that
+        // a Core instance has been created first, needed for unix imp to register
+        // a signal handler.
+        let cmd = cmd.borrow_mut();
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let child = self.spawn_with_stdio(cmd)?;
+        Ok(Child {
+            child: child.child,
+            exit_status: child.exit_status,
+            group: child.group,
+            stdin: child.stdin.unwrap(),
+            stdout: child.stdout.unwrap(),
+            stderr: child.stderr.unwrap(),
+        })
+    }
+
+    /// Starts running the given command, keeping the stdio it has been configured with.
+    ///
+    /// Unlike with `spawn`, stdin, stdout and stderr are left as set up on `cmd`, so
+    /// e.g. stderr can be inherited or stdout discarded, avoiding pipes nobody reads
+    /// from. Only streams set to `Stdio::piped()` are available on the child, the
+    /// others are `None`. Streams that aren't set up are inherited.
+    pub fn spawn_with_stdio(
+        &self,
+        mut cmd: impl BorrowMut<Command>,
+    ) -> io::Result<Child<Option<Stdin>, Option<Stdout>, Option<Stderr>>> {
+        let cmd = cmd.borrow_mut();
+        cmd.spawn().and_then(proc_imp::new_child).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("Failed to spawn {:?}: {}", cmd.get_program(), err),
+            )
+        })
+    }
+
+    /// Starts running the given command on a new pseudo-terminal.
+    ///
+    /// The child gets the terminal as its stdin, stdout and stderr, and as the
+    /// controlling terminal of a new session, so it behaves like it would when run
+    /// interactively. Its terminal is read and written through the returned
+    /// `PtyMaster`, which can be registered with the core like a pipe.
+    ///
+    /// This adds a `pre_exec` hook to `cmd` to set up the session, which stays there
+    /// once it has been spawned. The hook fails unless stdin is a terminal, so `cmd`
+    /// can only be spawned again with this method.
+    #[cfg(unix)]
+    pub fn spawn_pty(
+        &self,
+        mut cmd: impl BorrowMut<Command>,
+        options: PtyOptions,
+    ) -> io::Result<(Child<(), (), ()>, PtyMaster)> {
+        let cmd = cmd.borrow_mut();
+        let (child, master) = pty::spawn(cmd, options).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("Failed to spawn {:?}: {}", cmd.get_program(), err),
+            )
+        })?;
+        let child = Child {
+            group: proc_imp::ProcessGroup::new(&child),
+            child,
+            exit_status: Rc::default(),
+            stdin: (),
+            stdout: (),
+            stderr: (),
+        };
+        Ok((child, master))
+    }
+
+    /// Starts running the given commands as a pipeline, like `cmd1 | cmd2` in a shell.
+    ///
+    /// The stdout of each command is connected straight to the stdin of the next one,
+    /// so the data doesn't pass through this process. The stdin of the first command,
+    /// the stdout of the last one and all stderr streams are left as configured, like
+    /// with `spawn_with_stdio`, which is how the ends of the pipeline can be piped.
+    /// Returns the children in the order of the commands. If a command fails to start,
+    /// the ones started before it are killed.
+    pub fn spawn_pipeline(&self, cmds: &mut [Command]) -> io::Result<Vec<OptionalStdioChild>> {
+        let mut children: Vec<ProcessChild> = Vec::with_capacity(cmds.len());
+        let last = cmds.len().saturating_sub(1);
+        for (i, cmd) in cmds.iter_mut().enumerate() {
+            if let Some(previous) = children.last_mut() {
+                cmd.stdin(Stdio::from(previous.stdout.take().unwrap()));
+            }
+            if i < last {
+                cmd.stdout(Stdio::piped());
+            }
+            let result = cmd.spawn();
+            // `cmd` keeps its stdin open, which would keep the previous command from
+            // noticing that this one is gone.
+            if i > 0 {
+                cmd.stdin(Stdio::null());
+            }
+            match result {
+                Ok(child) => children.push(child),
+                Err(err) => {
+                    for mut child in children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(io::Error::new(
+                        err.kind(),
+                        format!("Failed to spawn {:?}: {}", cmd.get_program(), err),
+                    ));
+                }
+            }
+        }
+        children.into_iter().map(proc_imp::new_child).collect()
+    }
+
+    /// Connects to the given address, calling `on_connected` with the stream once the
+    /// connection is made, or `on_error` if that fails.
+    ///
+    /// The callbacks are called on the object with the given id, and only one of them
+    /// is. Connecting fails with `ErrorKind::TimedOut` if it takes longer than
+    /// `timeout`. The stream is handed over without registrations, so it can be
+    /// registered like any other. Fails right away if the socket can't be set up.
+    pub fn connect_tcp<FC, FE, T>(
+        &mut self,
+        address: SocketAddr,
+        timeout: Option<Duration>,
+        object_id: ObjectId,
+        on_connected: FC,
+        on_error: FE,
+    ) -> io::Result<()>
+    where
+        FC: 'static + FnOnce(&mut T, TcpStream, &mut Core),
+        FE: 'static + FnOnce(&mut T, io::Error, &mut Core),
+        T: Any,
+    {
+        let deliver = move |result, core: &mut Core| {
+            core.post(object_id, move |object: &mut T, core| match result {
+                Ok(stream) => on_connected(object, stream, core),
+                Err(err) => on_error(object, err, core),
+            });
+        };
+        connect::connect(self, address, timeout, Box::new(deliver))
+    }
+
+    /// Runs the given command to completion, passing its output and exit status to `f`.
+    ///
+    /// Like `Command::output`, stdout and stderr are collected in full and stdin is
+    /// connected to nothing. `f` is called on the object with the given id once the
+    /// command has exited and closed both streams, with an error if reading the
+    /// output or getting the exit status failed. Fails right away if the command
+    /// can't be spawned, or if its output can't be registered, in which case the
+    /// child is killed.
+    pub fn run_command<F, T>(
+        &mut self,
+        mut cmd: impl BorrowMut<Command>,
+        object_id: ObjectId,
+        f: F,
+    ) -> io::Result<()>
+    where
+        F: 'static + FnOnce(&mut T, io::Result<Output>, &mut Core),
+        T: Any,
+    {
+        let deliver = move |output, core: &mut Core| {
+            core.post(object_id, move |object: &mut T, core| {
+                f(object, output, core)
+            });
+        };
+        command::run(self, cmd.borrow_mut(), Box::new(deliver))
+    }
+
+    /// Spawns the given command, passing the lines it writes to stdout and stderr and
+    /// its exit status on to the object with the given id.
+    ///
+    /// This registers the readers and the reaper that otherwise have to be set up by
+    /// hand. When the child exits, the output left in its pipes is passed on and the
+    /// readers are dropped before `on_exit` is called, so no output is lost and nothing
+    /// stays registered. The child's stdin is closed. Returns the child without its
+    /// pipes, e.g. to `terminate` it. If the pipes can't be registered, the child is
+    /// killed and the error returned.
+    pub fn spawn_with_handlers<O, E, X, T>(
+        &mut self,
+        cmd: impl BorrowMut<Command>,
+        object_id: ObjectId,
+        on_stdout: O,
+        on_stderr: E,
+        mut on_exit: X,
+    ) -> io::Result<Child<(), (), ()>>
+    where
+        O: 'static + FnMut(&mut T, String, &mut Core),
+        E: 'static + FnMut(&mut T, String, &mut Core),
+        X: 'static + FnMut(&mut T, Option<ExitStatus>, &mut Core),
+        T: Any,
+    {
+        let Child {
+            child,
+            exit_status,
+            group,
+            stdout,
+            stderr,
+            ..
+        } = self.spawn(cmd)?.close_stdin();
+        let child = Child {
+            child,
+            exit_status,
+            group,
+            stdin: (),
+            stdout: (),
+            stderr: (),
+        };
+        let stdout = LinePipe::new(stdout, on_stdout);
+        let stderr = LinePipe::new(stderr, on_stderr);
+        let pipe = stdout.clone();
+        let result = self.register_reader(
+            &RefCell::borrow(&stdout).pipe,
+            object_id,
+            move |object, core| RefCell::borrow_mut(&pipe).read(object, core),
+        );
+        let stdout_token = match result {
+            Ok(token) => token,
+            Err(err) => {
+                self.abandon_child(child, object_id);
+                return Err(err);
+            }
+        };
+        RefCell::borrow_mut(&stdout).token = Some(stdout_token);
+        let pipe = stderr.clone();
+        let result = self.register_reader(
+            &RefCell::borrow(&stderr).pipe,
+            object_id,
+            move |object, core| RefCell::borrow_mut(&pipe).read(object, core),
+        );
+        let stderr_token = match result {
+            Ok(token) => token,
+            Err(err) => {
+                self.drop_registration(stdout_token);
+                self.abandon_child(child, object_id);
+                return Err(err);
+            }
+        };
+        RefCell::borrow_mut(&stderr).token = Some(stderr_token);
+        self.register_reaper_with_status(&child, object_id, move |object, status, core| {
+            RefCell::borrow_mut(&stdout).finish(object, core);
+            RefCell::borrow_mut(&stderr).finish(object, core);
+            on_exit(object, status, core);
+        });
+        Ok(child)
+    }
+
+    // kills a child that can't be supervised after all. It is still waited for, so it
+    // doesn't linger as a zombie.
+    pub(crate) fn abandon_child<S, O, E>(
+        &mut self,
+        mut child: Child<S, O, E>,
+        object_id: ObjectId,
+    ) {
+        if let Err(err) = child.kill() {
+            error!("Failed to kill process {}: {}", child.id(), err);
+        }
+        self.register_reaper(&child, object_id, |_: &mut (), _| {});
+        self.unregister_reaper(child.id());
+    }
+
+    /// Spawns the given command and adds an object supervising it.
+    ///
+    /// `make_object` receives the spawned child together with the id its object will
+    /// get, and `on_exit` is registered as reaper for the child on that object, with
+    /// the exit status like for `register_reaper_with_status`. This
+    /// saves having to get the `spawn`, `next_id`, `register_reaper` and `add` steps
+    /// right by hand. The id is reserved before `make_object` is called, so it may
+    /// freely register readers or add other objects to the core.
+    pub fn supervise<M, F, T>(
+        &mut self,
+        cmd: impl BorrowMut<Command>,
+        make_object: M,
+        on_exit: F,
+    ) -> io::Result<ObjectId>
+    where
+        M: FnOnce(Child<Stdin>, ObjectId, &mut Core) -> T,
+        F: 'static + FnMut(&mut T, Option<ExitStatus>, &mut Core),
+        T: Any,
+    {
+        let child = self.spawn(cmd)?;
+        let object_id = self.objects.put(None);
+        self.register_reaper_with_status(&child, object_id, on_exit);
+        let object = make_object(child, object_id, self);
+        if let Some(option) = self.objects.get_mut(object_id) {
+            *option = Some(Box::new(object));
+        }
+        self.mark_added(object_id);
+        #[cfg(feature = "tracing")]
+        self.set_type_name(object_id, std::any::type_name::<T>());
+        Ok(object_id)
+    }
+
+    fn dispatch(&mut self, token: Token, readiness: Ready, polls_left: usize) {
+        let mut io_handler = match self.io_handlers.get_mut(token).and_then(Option::take) {
+            Some(handler) => handler,
+            None => {
+                if let Some(hook) = &mut self.orphan_hook {
+                    hook(token, readiness);
+                }
+                return;
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(
+            "dispatch",
+            object_id = io_handler.object_id.map(usize::from),
+            object_type = io_handler.object_id.map(|id| self.type_name(id)),
+            token = usize::from(token),
+            readiness = ?readiness,
+        )
+        .entered();
+        if let Some(owned) = &mut io_handler.owned {
+            #[cfg(feature = "stats")]
+            let start = Instant::now();
+            let panic =
+                self.catch_panic(|core| owned.make_call(Event::new(readiness, token), core));
+            #[cfg(feature = "stats")]
+            self.stats.callback(start.elapsed());
+            if let Some(message) = panic {
+                // the object of the registration has no id, so only it is dropped.
+                error!(
+                    "Callback of owned registration {:?} panicked, dropping it: {}",
+                    token, message
+                );
+                self.drop_registration(token);
+                return;
+            }
+            // unless the callback dropped the registration.
+            if let Some(option @ None) = self.io_handlers.get_mut(token) {
+                *option = Some(io_handler);
+            }
+            return;
+        }
+        let object_id = match io_handler.object_id {
+            Some(object_id) => object_id,
+            // only owned registrations have no object.
+            None => return,
+        };
+        let interest = self.interests[usize::from(token)];
+        let obj_exists = self.call_on_object(object_id, |object, core| {
+            if let Some(event_fn) = &mut io_handler.event_fn {
+                for kind in event_kinds(readiness, interest) {
+                    event_fn.make_call(object, kind, core);
+                }
+            }
+            if let Some(read_fn) = &mut io_handler.read_fn {
+                // a hangup can come without the source being readable, but reading is
+                // still how the reader finds out about the end of the stream.
+                if readiness.is_readable() || proc_imp::is_hangup(readiness) {
+                    read_fn.make_call(object, core);
+                }
+            }
+            match (&mut io_handler.write_fn, &mut io_handler.hangup_fn) {
+                (_, Some(hangup_fn)) if proc_imp::is_hangup(readiness) => {
+                    hangup_fn.make_call(object, core);
+                }
+                (Some(write_fn), _) if readiness.is_writable() => {
+                    write_fn.make_call(object, core);
+                }
+                _ => {}
+            }
+        });
+        // the registration may have been dropped by one of the callbacks, e.g. by
+        // removing the object, and its token reused by a new one.
+        if !matches!(self.io_handlers.get(token), Some(None)) {
+            return;
+        }
+        if !obj_exists {
+            if self.generation(object_id).wrapping_add(1) != io_handler.generation {
+                trace!(
+                    "Object {:?} was removed, dropping its registration.",
+                    object_id
+                );
+                self.drop_registration(token);
+            } else if polls_left == 0 {
+                warn!(
+                    "Object {:?} was never added to the core, dropping its registration. \
+                     Objects must be added soon after calling `next_id` and registering.",
+                    object_id
+                );
+                self.drop_registration(token);
+            } else {
+                trace!(
+                    "Object {:?} has not been added yet, holding back its event.",
+                    object_id
+                );
+                match self.pending_events.iter_mut().find(|p| p.token == token) {
+                    Some(pending) => pending.readiness |= readiness,
+                    None => self.pending_events.push(PendingEvent {
+                        token,
+                        readiness,
+                        polls_left,
+                    }),
+                }
+            }
+        }
+        if let Some(option) = self.io_handlers.get_mut(token) {
+            *option = Some(io_handler);
+        }
+    }
+
+    fn dispatch_pending(&mut self) {
+        for pending in mem::take(&mut self.pending_events) {
+            self.dispatch(pending.token, pending.readiness, pending.polls_left - 1);
+        }
+        proc_imp::dispatch_pending_reapers(self);
+    }
+
+    fn run_posted(&mut self) {
+        let mut posted = mem::take(&mut self.posted);
+        posted.sort_by_key(|(priority, _)| *priority);
+        for (_, mut posted) in posted {
+            self.call_on_object(posted.object_id, |object, core| {
+                posted.callback.make_call(object, core)
+            });
+        }
+    }
+
+    fn run_deferred(&mut self) {
+        while !self.deferred.is_empty() {
+            for f in mem::take(&mut self.deferred) {
+                f(self);
+            }
+        }
+    }
+
+    // returns whether any timers fired.
+    fn fire_timers(&mut self, now: Instant) -> bool {
+        let mut fired = false;
+        while let Some((timer_id, mut timer)) = self.timers.pop_expired(now) {
+            let object_exists = self.call_on_object(timer.object_id, |object, core| {
+                timer.callback.make_call(object, core)
+            });
+            self.timers.finish(timer_id, timer, object_exists);
+            fired = true;
+        }
+        fired
+    }
+
+    fn tick(&mut self) {
+        self.call_each(|core| &mut core.tick_handlers);
+    }
+
+    fn run_idle(&mut self) {
+        // handlers registered by the ones that run now wait for the next idle period.
+        let end = self.next_idle_id;
+        let mut last = None;
+        while let Some(pos) = self
+            .idle_handlers
+            .iter()
+            .position(|h| Some(h.id) > last && h.id.0 < end)
+        {
+            let handler = &mut self.idle_handlers[pos];
+            last = Some(handler.id);
+            let object_id = handler.object_id;
+            let mut callback = handler.callback.take().unwrap();
+            let object_exists =
+                self.call_on_object(object_id, |object, core| callback.make_call(object, core));
+            // the handler may have been unregistered, or others removed, meanwhile.
+            if let Some(pos) = self.idle_handlers.iter().position(|h| Some(h.id) == last) {
+                if object_exists {
+                    self.idle_handlers[pos].callback = Some(callback);
+                } else {
+                    self.idle_handlers.remove(pos);
+                }
+            }
+        }
+    }
+
+    // calls each of the callbacks in the given list, dropping the ones whose objects
+    // no longer exist. Callbacks may add to the list while it is being called.
+    fn call_each(&mut self, list: fn(&mut Core) -> &mut Vec<ObjectCallback>) {
+        let mut handlers = mem::take(list(self));
+        handlers.retain_mut(|handler| {
+            self.call_on_object(handler.object_id, |object, core| {
+                handler.callback.make_call(object, core)
+            })
+        });
+        handlers.append(list(self));
+        *list(self) = handlers;
+    }
+
+    fn call_on_object(
+        &mut self,
+        object_id: ObjectId,
+        f: impl FnOnce(&mut dyn Any, &mut Core),
+    ) -> bool {
+        if let Some(mut box_object) = self.objects.get_mut(object_id).and_then(Option::take) {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::trace_span!(
+                "callback",
+                object_id = usize::from(object_id),
+                object_type = self.type_name(object_id),
+            )
+            .entered();
+            #[cfg(feature = "stats")]
+            let start = Instant::now();
+            let panic = self.catch_panic(|core| f(box_object.borrow_mut(), core));
+            #[cfg(feature = "stats")]
+            self.stats.callback(start.elapsed());
+            if let Some(message) = panic {
+                self.remove_panicked(object_id, Some(box_object), message);
+                return false;
+            }
+            while let Some(pos) = self.object_maps.iter().position(|(id, _)| *id == object_id) {
+                let (_, map) = self.object_maps.remove(pos);
+                box_object = map(box_object, self);
+            }
+            if let Some(option) = self.objects.get_mut(object_id) {
+                *option = Some(box_object);
+                return true;
+            }
+            let deferred = &self.deferred_remove_hooks;
+            if let Some(pos) = deferred.iter().position(|(id, _)| *id == object_id) {
+                let (_, hook) = self.deferred_remove_hooks.remove(pos);
+                hook(box_object.as_mut(), self);
+            }
+        }
+        false
+    }
+
+    // calls `f`, catching a panic under `PanicPolicy::RemoveObject`, in which case the
+    // panic message is returned.
+    fn catch_panic(&mut self, f: impl FnOnce(&mut Core)) -> Option<String> {
+        if self.panic_policy != PanicPolicy::RemoveObject {
+            f(self);
+            return None;
+        }
+        // the core is in a consistent state again once the callback is out of the
+        // picture, as it only ever runs with the object taken out.
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| f(self))).err()?;
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "Box<dyn Any>".to_string(),
+            },
+        };
+        Some(message)
+    }
+
+    // removes an object whose callback panicked, given back by the callback if it was
+    // borrowed by it, and passes the panic on to the hook.
+    fn remove_panicked(
+        &mut self,
+        object_id: ObjectId,
+        object: Option<Box<dyn Any>>,
+        message: String,
+    ) {
+        error!(
+            "Callback of object {:?} panicked, removing it: {}",
+            object_id, message
+        );
+        self.object_maps.retain(|(id, _)| *id != object_id);
+        match self.objects.get_mut(object_id) {
+            Some(option @ None) => {
+                *option = object;
+                self.remove(object_id);
+            }
+            // the callback removed the object itself before panicking.
+            _ => {
+                let deferred = &self.deferred_remove_hooks;
+                if let Some(pos) = deferred.iter().position(|(id, _)| *id == object_id) {
+                    let (_, hook) = self.deferred_remove_hooks.remove(pos);
+                    if let Some(mut object) = object {
+                        hook(object.as_mut(), self);
+                    }
+                }
+            }
+        }
+        if let Some(mut hook) = self.panic_hook.take() {
+            hook(object_id, &message, self);
+            if self.panic_hook.is_none() {
+                self.panic_hook = Some(hook);
+            }
+        }
+    }
+
+    // returns whether there is a registration with the given token for the object.
+    fn is_registered(&self, token: Token, object_id: ObjectId) -> bool {
+        self.io_handlers.get(token).is_some()
+            && self.owners.get(usize::from(token)) == Some(&Some(object_id))
+    }
+
+    fn generation(&self, object_id: ObjectId) -> u32 {
+        let idx: usize = object_id.into();
+        self.generations.get(idx).cloned().unwrap_or(0)
+    }
+
+    // adds an object the core needs for itself, along with the one registration made
+    // for it, which shouldn't keep `run` going.
+    pub(crate) fn add_internal(&mut self, object: impl Any) -> ObjectId {
+        let object_id = self.add(object);
+        self.internal_objects.push(object_id);
+        self.internal_handlers += 1;
+        object_id
+    }
+
+    // returns the generation of the object with the given id, or the one it will have
+    // once added if there is no such object.
+    pub(crate) fn expected_generation(&self, object_id: ObjectId) -> u32 {
+        match self.objects.get(object_id) {
+            Some(_) => self.generation(object_id),
+            None => self.generation(object_id).wrapping_add(1),
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    fn set_type_name(&mut self, object_id: ObjectId, type_name: &'static str) {
+        let idx: usize = object_id.into();
+        if self.type_names.len() <= idx {
+            self.type_names.resize(idx + 1, "");
+        }
+        self.type_names[idx] = type_name;
+    }
+
+    #[cfg(feature = "tracing")]
+    fn type_name(&self, object_id: ObjectId) -> &'static str {
+        let idx: usize = object_id.into();
+        self.type_names.get(idx).cloned().unwrap_or("")
+    }
+
+    fn reserve_objects(&mut self, objects: usize) {
+        self.objects.reserve(objects);
+        self.generations.reserve(objects);
+        self.object_tokens.reserve(objects);
+        self.remove_hooks.reserve(objects);
+        // most objects have a registration for their IO.
+        self.io_handlers.reserve(objects);
+        self.interests.reserve(objects);
+        self.owners.reserve(objects);
+        self.priorities.reserve(objects);
+    }
+
+    fn mark_added(&mut self, object_id: ObjectId) {
+        let idx: usize = object_id.into();
+        if self.generations.len() <= idx {
+            self.generations.resize(idx + 1, 0);
+        }
+        self.generations[idx] = self.generations[idx].wrapping_add(1);
+    }
+
+    // drops the registration with the given token, which may be being dispatched.
+    fn drop_registration(&mut self, token: Token) {
+        if self.io_handlers.take(token).is_none() {
+            return;
+        }
+        if let Some(owner) = self.owners[usize::from(token)] {
+            if let Some(tokens) = self.object_tokens.get_mut(usize::from(owner)) {
+                tokens.retain(|t| *t != token);
+            }
+        }
+    }
+
+    fn internal_register(
+        &mut self,
+        e: &dyn Source,
+        r: Ready,
+        object_id: ObjectId,
+        read_fn: Option<Box<dyn Call>>,
+        write_fn: Option<Box<dyn Call>>,
+        hangup_fn: Option<Box<dyn Call>>,
+    ) -> io::Result<Token> {
+        let token = self.register_for_object(e, r, Trigger::Edge, object_id)?;
+        if let Some(Some(io_handler)) = self.io_handlers.get_mut(token) {
+            io_handler.read_fn = read_fn;
+            io_handler.write_fn = write_fn;
+            io_handler.hangup_fn = hangup_fn;
+        }
+        Ok(token)
+    }
+
+    // registers `e` for the given object, without any callbacks yet.
+    fn register_for_object(
+        &mut self,
+        e: &dyn Source,
+        r: Ready,
+        trigger: Trigger,
+        object_id: ObjectId,
+    ) -> io::Result<Token> {
+        let token = self.register_token(e, r, trigger, Some(object_id))?;
+        let object_idx: usize = object_id.into();
+        if self.object_tokens.len() <= object_idx {
+            self.object_tokens.resize(object_idx + 1, Vec::new());
+        }
+        self.object_tokens[object_idx].push(token);
+        let generation = self.expected_generation(object_id);
+        self.io_handlers.put(Some(IoHandler {
+            object_id: Some(object_id),
+            generation,
+            read_fn: None,
+            write_fn: None,
+            hangup_fn: None,
+            event_fn: None,
+            owned: None,
+        }));
+        Ok(token)
+    }
+
+    // registers `e` with the poll under the token the next registration will get, and
+    // sets up what is kept for each token.
+    fn register_token(
+        &mut self,
+        e: &dyn Source,
+        r: Ready,
+        trigger: Trigger,
+        owner: Option<ObjectId>,
+    ) -> io::Result<Token> {
+        if self.shutdown_deadline.is_some() {
+            return Err(io::Error::other("The core is shutting down."));
+        }
+        let token = self.io_handlers.next_index();
+        e.register(&Registry::new(&self.poll), token, r, trigger)?;
+        let idx: usize = token.into();
+        if self.interests.len() <= idx {
+            self.interests.resize(idx + 1, Ready::empty());
+        }
+        self.interests[idx] = r;
+        if self.owners.len() <= idx {
+            self.owners.resize(idx + 1, None);
+        }
+        self.owners[idx] = owner;
+        if self.priorities.len() <= idx {
+            self.priorities.resize(idx + 1, Priority::Normal);
+        }
+        self.priorities[idx] = Priority::Normal;
+        if self.triggers.len() <= idx {
+            self.triggers.resize(idx + 1, Trigger::Edge);
+        }
+        self.triggers[idx] = trigger;
+        Ok(token)
+    }
+}
+
+/// Adds an object to a core along with callbacks for its IO and children.
+///
+/// Takes care of getting a new id and doing the registrations before adding the
+/// object, and evaluates to the object's id. If a registration fails, the ones made
+/// before it are dropped, the object isn't added and the error is returned instead.
+/// Each registration names the kind of callback, the source it is registered for and
+/// the callback itself. `read` and `write` take a `Source`, while `on_exit` takes a
+/// `Child`:
+///
+/// ```no_run
+/// # use looper::{register, Child, Core};
+/// # use std::process::Command;
+/// struct Job {
+///     child: Child<()>,
+/// }
+///
+/// impl Job {
+///     fn read(&mut self, _core: &mut Core) {}
+///     fn handle_exit(&mut self, _core: &mut Core) {}
+/// }
+///
+/// let mut core = Core::new();
+/// let child = core.spawn(Command::new("ls")).unwrap().close_stdin();
+/// let id = register!(&mut core, Job { child }, {
+///     read(&child.stdout) => Job::read,
+///     on_exit(&child) => Job::handle_exit,
+/// })
+/// .unwrap();
+/// ```
+#[macro_export]
+macro_rules! register {
+    ($core:expr, $object:expr, { $($kind:ident($source:expr) => $f:expr),* $(,)? }) => {{
+        let core: &mut $crate::Core = $core;
+        let object_id = core.next_id();
+        let result: ::std::io::Result<()> = Ok(());
+        $(let result = result.and_then(|()| $crate::register!(@one core, object_id, $kind, $source, $f).map(|_| ()));)*
+        match result {
+            Ok(()) => Ok(core.add($object)),
+            Err(err) => {
+                // the registrations made so far would be inherited by the next object.
+                core.unregister_object(object_id);
+                Err(err)
+            }
+        }
+    }};
+    (@one $core:ident, $id:ident, read, $source:expr, $f:expr) => {
+        $core.register_reader($source, $id, $f)
+    };
+    (@one $core:ident, $id:ident, write, $source:expr, $f:expr) => {
+        $core.register_writer($source, $id, $f)
+    };
+    (@one $core:ident, $id:ident, on_exit, $source:expr, $f:expr) => {{
+        $core.register_reaper($source, $id, $f);
+        Ok(())
+    }};
+}
+
+#[path = "process_unix.rs"]
+#[cfg(unix)]
+mod proc_imp;
 
 #[path = "process_win.rs"]
 #[cfg(windows)]
 mod proc_imp;
 
-pub use proc_imp::{Stderr, Stdin, Stdout};
+pub use proc_imp::{request_reload, Stderr, Stdin, Stdout};
+
+mod channel;
+mod child_stdin;
+mod command;
+mod command_builder;
+mod connect;
+mod core_builder;
+#[cfg(unix)]
+mod forward;
+mod group;
+mod handle;
+mod lines;
+mod loop_object;
+mod monitor;
+mod pool;
+mod proxy;
+#[cfg(unix)]
+mod pty;
+#[cfg(unix)]
+mod raw_fd;
+#[cfg(windows)]
+mod raw_handle;
+mod remote;
+pub mod source;
+#[cfg(feature = "stats")]
+mod stats;
+mod stdin;
+mod throttle;
+mod timer;
+mod topic;
+mod waker;
+mod write_buffer;
 
-pub struct Child<S> {
+pub use channel::{channel, Sender};
+pub use child_stdin::ChildStdinWriter;
+pub use command_builder::CommandBuilder;
+pub use core_builder::{CoreBuilder, PanicPolicy};
+#[cfg(unix)]
+pub use forward::Forwarder;
+pub use group::ObjectGroup;
+pub use handle::Handle;
+pub use lines::Utf8LineReader;
+pub use loop_object::LoopObject;
+use loop_object::RemoveHook;
+pub use monitor::{ChildMonitor, ResourceSample};
+pub use pool::ProcessPool;
+pub use proxy::Proxy;
+#[cfg(unix)]
+pub use pty::{PtyMaster, PtyOptions};
+#[cfg(unix)]
+pub use raw_fd::RawFdSource;
+#[cfg(windows)]
+pub use raw_handle::RawHandleSource;
+pub use remote::Remote;
+use source::{Registry, Source};
+#[cfg(feature = "stats")]
+pub use stats::{Stats, LOG_INTERVAL_VAR};
+pub use throttle::Throttled;
+pub use timer::TimerId;
+pub use topic::Topic;
+pub use waker::Waker;
+pub use write_buffer::WriteBuffer;
+
+// a child with only the stdio streams that were set to be piped.
+type OptionalStdioChild = Child<Option<Stdin>, Option<Stdout>, Option<Stderr>>;
+
+/// A running child process, along with the pipes to its stdio.
+///
+/// The type parameters are the types of the stdin, stdout and stderr pipes, which
+/// are `()` or wrapped in an `Option` when the pipe may not be there.
+pub struct Child<S, O = Stdout, E = Stderr> {
     child: ProcessChild,
+    exit_status: Rc<Cell<Option<ExitStatus>>>,
+    // what `kill_group` kills.
+    group: proc_imp::ProcessGroup,
     pub stdin: S,
-    pub stdout: Stdout,
-    pub stderr: Stderr,
+    pub stdout: O,
+    pub stderr: E,
 }
 
-impl<S> Child<S> {
+impl<S, O, E> Child<S, O, E> {
     /// Returns the OS-assigned process identifier associated with this child.
     pub fn id(&self) -> u32 {
         self.child.id()
@@ -272,15 +2652,99 @@ impl<S> Child<S> {
     pub fn kill(&mut self) -> io::Result<()> {
         self.child.kill()
     }
+
+    /// Sends a signal, such as `libc::SIGTERM` or `libc::SIGUSR1`, to the child.
+    ///
+    /// Does nothing if the child is known to have exited, as its pid may have been
+    /// reused by then.
+    #[cfg(unix)]
+    pub fn signal(&mut self, signal: i32) -> io::Result<()> {
+        if self.exit_status().is_some() {
+            return Ok(());
+        }
+        proc_imp::send_signal(self.id(), signal)
+    }
+
+    /// Sends a signal to the child's process group, reaching the processes it started
+    /// as well, unless they moved to a group of their own.
+    ///
+    /// The child must lead a process group, i.e. it must have been spawned with
+    /// `CommandBuilder::new_process_group` or `new_session`, or with `spawn_pty`.
+    /// Once the child has exited, the rest of the group is still signaled, as long as
+    /// its pid hasn't been reused.
+    #[cfg(unix)]
+    pub fn signal_group(&mut self, signal: i32) -> io::Result<()> {
+        proc_imp::signal_group(self, signal)
+    }
+
+    /// Forces the child and the processes it started to exit.
+    ///
+    /// On unix this sends a SIGKILL to the child's process group, see `signal_group`.
+    /// On Windows every child is put in a job object of its own when spawned, which
+    /// its children join as well, and all of them are terminated.
+    pub fn kill_group(&mut self) -> io::Result<()> {
+        proc_imp::kill_group(self)
+    }
+
+    /// Asks the child to exit, killing it if it is still running after `grace`.
+    ///
+    /// On unix the child is sent a SIGTERM, and a timer for the object with the given
+    /// id sends a SIGKILL later, unless the child has been reaped by then. The timer is
+    /// returned so it can be canceled, e.g. once the child's reaper has been called,
+    /// and like any timer it is dropped if the object is removed. On Windows there is
+    /// no way to ask, so the child is killed right away and `None` is returned. `None`
+    /// is also returned if the child is known to have exited already.
+    pub fn terminate(
+        &mut self,
+        grace: Duration,
+        object_id: ObjectId,
+        core: &mut Core,
+    ) -> io::Result<Option<TimerId>> {
+        if self.exit_status().is_some() {
+            return Ok(None);
+        }
+        proc_imp::terminate(self, grace, object_id, core)
+    }
+
+    /// Returns the exit status of the child, once it has exited.
+    ///
+    /// The status is only known after the child has been reaped, so it is always
+    /// `None` for children without a registered reaper.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status.get()
+    }
 }
 
-impl Child<Stdin> {
-    pub fn close_stdin(self) -> Child<()> {
-        Child {
+impl<O, E> Child<Stdin, O, E> {
+    pub fn close_stdin(self) -> Child<(), O, E> {
+        self.take_stdin().1
+    }
+
+    /// Takes stdin out of the child without closing it, e.g. for a `ChildStdinWriter`.
+    pub fn take_stdin(self) -> (Stdin, Child<(), O, E>) {
+        let child = Child {
             child: self.child,
+            exit_status: self.exit_status,
+            group: self.group,
             stdin: (),
             stdout: self.stdout,
             stderr: self.stderr,
+        };
+        (self.stdin, child)
+    }
+}
+
+impl<O, E> Child<Option<Stdin>, O, E> {
+    /// Closes the child's stdin in place, so the child reads the end of its input.
+    ///
+    /// If stdin was registered with the core, `token` must be the token of that
+    /// registration, which is removed before the pipe is closed. Does nothing if stdin
+    /// is already closed.
+    pub fn close_stdin(&mut self, token: Option<Token>, core: &mut Core) -> io::Result<()> {
+        if let (Some(stdin), Some(token)) = (&self.stdin, token) {
+            core.deregister(stdin, token)?;
         }
+        self.stdin = None;
+        Ok(())
     }
 }
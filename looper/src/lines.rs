@@ -0,0 +1,77 @@
+use std::io::{self, ErrorKind, Read};
+
+/// Splits the output of a non-blocking reader into lines of text.
+///
+/// Bytes are buffered until a full line has been read, so characters split across
+/// reads are put back together before decoding. Invalid UTF-8 is replaced with
+/// U+FFFD rather than treated as an error, which makes this suitable for arbitrary
+/// process output.
+#[derive(Debug, Default)]
+pub struct Utf8LineReader {
+    buffer: Vec<u8>,
+}
+
+impl Utf8LineReader {
+    pub fn new() -> Utf8LineReader {
+        Utf8LineReader::default()
+    }
+
+    /// Reads everything currently available from `reader`, calling `f` with every
+    /// completed line.
+    ///
+    /// Lines are passed on without their line ending. Returns `Ok(true)` once the end
+    /// of the stream has been reached, after passing on any unterminated last line,
+    /// and `Ok(false)` when the reader would block.
+    pub fn read_lines(
+        &mut self,
+        reader: &mut impl Read,
+        mut f: impl FnMut(String),
+    ) -> io::Result<bool> {
+        let mut chunk = [0; 4096];
+        loop {
+            match reader.read(&mut chunk) {
+                Ok(0) => {
+                    self.finish(f);
+                    return Ok(true);
+                }
+                Ok(n) => self.push(&chunk[..n], &mut f),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Adds bytes read elsewhere, calling `f` with every line they complete.
+    pub fn push(&mut self, bytes: &[u8], mut f: impl FnMut(String)) {
+        let start = self.buffer.len();
+        self.buffer.extend_from_slice(bytes);
+        self.split_lines(start, &mut f);
+    }
+
+    /// Calls `f` with the unterminated last line, if any, once the input has ended.
+    pub fn finish(&mut self, mut f: impl FnMut(String)) {
+        if !self.buffer.is_empty() {
+            let rest = std::mem::take(&mut self.buffer);
+            f(decode(&rest));
+        }
+    }
+
+    // a newline byte never occurs inside a multi-byte UTF-8 sequence, so splitting
+    // on it before decoding can't cut a character in two.
+    fn split_lines(&mut self, mut search_from: usize, f: &mut impl FnMut(String)) {
+        let mut line_start = 0;
+        while let Some(pos) = self.buffer[search_from..].iter().position(|&b| b == b'\n') {
+            let line_end = search_from + pos;
+            f(decode(&self.buffer[line_start..line_end]));
+            line_start = line_end + 1;
+            search_from = line_start;
+        }
+        self.buffer.drain(..line_start);
+    }
+}
+
+fn decode(line: &[u8]) -> String {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    String::from_utf8_lossy(line).into_owned()
+}
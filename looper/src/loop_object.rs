@@ -0,0 +1,21 @@
+use crate::Core;
+use std::any::Any;
+
+/// An object that wants to know when it is removed from its core.
+///
+/// Objects added with `Core::add_loop_object` have `on_remove` called by
+/// `Core::remove`, before the core drops their IO registrations and reapers. This
+/// gives them a chance to cancel their timers, terminate their children or deregister
+/// sources they share with other objects, instead of leaving that to whoever removes
+/// them.
+pub trait LoopObject: Any {
+    fn on_remove(&mut self, core: &mut Core);
+}
+
+pub(crate) type RemoveHook = fn(&mut dyn Any, &mut Core);
+
+pub(crate) fn remove_hook<T: LoopObject>(object: &mut dyn Any, core: &mut Core) {
+    if let Some(object) = object.downcast_mut::<T>() {
+        object.on_remove(core);
+    }
+}
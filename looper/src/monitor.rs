@@ -0,0 +1,61 @@
+use crate::{proc_imp, Child, Core, ObjectId, TimerId};
+use log::warn;
+use std::any::Any;
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// The resource usage of a process at one point in time.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ResourceSample {
+    /// The memory the process has resident, in bytes.
+    pub rss: u64,
+    /// The CPU time the process has used so far, in user and kernel mode together.
+    pub cpu_time: Duration,
+}
+
+/// Samples the resource usage of children at regular intervals, e.g. to enforce
+/// memory limits.
+pub struct ChildMonitor;
+
+impl ChildMonitor {
+    /// Calls `f` on the object with the given id with a sample of the resource usage
+    /// of `child` every `period`.
+    ///
+    /// Sampling stops once the child has been reaped, or when the returned timer is
+    /// canceled or the object is removed. Periods in which no sample can be taken,
+    /// e.g. because the process is exiting, are skipped. Fails if processes can't be
+    /// sampled on this platform, which is supported on Linux and Windows.
+    pub fn start<F, T, S, O, E>(
+        child: &Child<S, O, E>,
+        period: Duration,
+        object_id: ObjectId,
+        mut f: F,
+        core: &mut Core,
+    ) -> io::Result<TimerId>
+    where
+        F: 'static + FnMut(&mut T, ResourceSample, &mut Core),
+        T: Any,
+    {
+        let process = proc_imp::ProcessRef::new(child)?;
+        let pid = child.id();
+        let exit_status = child.exit_status.clone();
+        let own_timer = Rc::new(Cell::new(None));
+        let timer = own_timer.clone();
+        let timer_id = core.set_interval(period, object_id, move |object: &mut T, core| {
+            if exit_status.get().is_some() {
+                if let Some(timer_id) = timer.get() {
+                    core.cancel_timer(timer_id);
+                }
+                return;
+            }
+            match process.sample() {
+                Ok(sample) => f(object, sample, core),
+                Err(err) => warn!("Failed to sample process {}: {}", pid, err),
+            }
+        });
+        own_timer.set(Some(timer_id));
+        Ok(timer_id)
+    }
+}
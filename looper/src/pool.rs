@@ -0,0 +1,101 @@
+use crate::{Child, Core, ObjectId, Stdin};
+use log::error;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::process::{Command, ExitStatus};
+
+type Job = Box<dyn FnOnce(ObjectId, &mut Core)>;
+
+/// Runs commands with a limit on how many of them may run at the same time.
+///
+/// Commands beyond the limit are queued and started, in order, as running ones exit.
+/// The pool lives in the core like any other object and is referred to by its id.
+pub struct ProcessPool {
+    limit: usize,
+    running: usize,
+    queue: VecDeque<Job>,
+}
+
+impl ProcessPool {
+    /// Adds a new pool running at most `limit` commands at a time to the core.
+    pub fn start(limit: usize, core: &mut Core) -> ObjectId {
+        core.add(ProcessPool {
+            limit,
+            running: 0,
+            queue: VecDeque::new(),
+        })
+    }
+
+    /// Queues a command to be supervised by the pool.
+    ///
+    /// Once there is a free slot, the command is spawned like with `Core::supervise`,
+    /// with `make_object` creating the object owning the child and `on_exit` called
+    /// on that object with the exit status when the child exits. Commands that fail
+    /// to spawn are logged and skipped.
+    pub fn supervise<M, F, T>(
+        pool_id: ObjectId,
+        core: &mut Core,
+        cmd: Command,
+        make_object: M,
+        on_exit: F,
+    ) where
+        M: 'static + FnOnce(Child<Stdin>, ObjectId, &mut Core) -> T,
+        F: 'static + FnMut(&mut T, Option<ExitStatus>, &mut Core),
+        T: Any,
+    {
+        let job = Box::new(move |pool_id, core: &mut Core| {
+            // the slot is freed by a reaper of the pool's own, so it is freed even if
+            // the object goes away before its child does.
+            let make_object = move |child: Child<Stdin>, object_id, core: &mut Core| {
+                core.register_reaper(&child, pool_id, move |pool: &mut ProcessPool, core| {
+                    pool.running -= 1;
+                    // the pool is borrowed by this callback, so queued commands are
+                    // started once it returns.
+                    core.defer(move |core| ProcessPool::start_queued(pool_id, core));
+                });
+                make_object(child, object_id, core)
+            };
+            if let Err(err) = core.supervise(cmd, make_object, on_exit) {
+                error!("{}", err);
+                ProcessPool::finished(pool_id, core);
+            }
+        });
+        if let Some(pool) = core.get_mut::<ProcessPool>(pool_id) {
+            pool.queue.push_back(job);
+        }
+        ProcessPool::start_queued(pool_id, core);
+    }
+
+    /// Returns the number of commands currently running.
+    pub fn running(&self) -> usize {
+        self.running
+    }
+
+    /// Returns the number of commands waiting for a free slot.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn finished(pool_id: ObjectId, core: &mut Core) {
+        if let Some(pool) = core.get_mut::<ProcessPool>(pool_id) {
+            pool.running -= 1;
+        }
+        ProcessPool::start_queued(pool_id, core);
+    }
+
+    fn start_queued(pool_id: ObjectId, core: &mut Core) {
+        loop {
+            let job = match core.get_mut::<ProcessPool>(pool_id) {
+                Some(pool) if pool.running < pool.limit => match pool.queue.pop_front() {
+                    Some(job) => {
+                        pool.running += 1;
+                        job
+                    }
+                    None => return,
+                },
+                _ => return,
+            };
+            job(pool_id, core);
+        }
+    }
+}
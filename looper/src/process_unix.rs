@@ -1,76 +1,245 @@
-use crate::{Call, Callback, Child, Core, ObjectId};
-use libc;
-use log::error;
+use crate::{
+    Call, Callback, Child, Core, CoreBuilder, CoreCallback, ObjectId, ResourceSample, TimerId,
+    PENDING_EVENT_POLLS,
+};
+use log::{error, trace, warn};
 use mio::{
     unix::{EventedFd, UnixReady},
-    Evented, Poll, PollOpt, Ready, Token,
+    Evented, Events as MioEvents, Poll, PollOpt, Ready, Token,
 };
 use signal_hook::iterator::Signals;
 use stash::Stash;
 use std::any::Any;
+use std::cell::Cell;
 use std::collections::VecDeque;
+use std::fs;
 use std::io;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::process;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{self, ExitStatus, Stdio};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-pub fn new_core() -> Core {
-    let signals = Signals::new(&[signal_hook::SIGCHLD]).unwrap();
+pub fn new_core(builder: &CoreBuilder) -> io::Result<Core> {
     let mut core = Core {
         io_handlers: Stash::default(),
+        interests: Vec::new(),
+        owners: Vec::new(),
+        priorities: Vec::new(),
+        triggers: Vec::new(),
         objects: Stash::default(),
-        poll: Poll::new().unwrap(),
+        generations: Vec::new(),
+        object_tokens: Vec::new(),
+        remove_hooks: Vec::new(),
+        deferred_remove_hooks: Vec::new(),
+        pending_events: Vec::new(),
+        tick_handlers: Vec::new(),
+        reload_handlers: Vec::new(),
+        shutdown_handlers: Vec::new(),
+        shutdown_deadline: None,
+        idle_handlers: Vec::new(),
+        next_idle_id: 0,
+        idle_due: true,
+        posted: Vec::new(),
+        deferred: Vec::new(),
+        chunk_buffers: Vec::new(),
+        watching_reload: false,
+        orphan_hook: None,
+        error_sink: None,
+        timers: Default::default(),
+        remote: None,
+        waker: None,
+        object_maps: Vec::new(),
+        internal_handlers: 0,
+        internal_objects: Vec::new(),
+        poll: Poll::new()?,
+        events: MioEvents::with_capacity(builder.events),
+        batch: Vec::new(),
+        now: Instant::now(),
+        #[cfg(feature = "stats")]
+        stats: crate::stats::Recorder::new(),
+        #[cfg(feature = "tracing")]
+        type_names: Vec::new(),
+        panic_policy: builder.panic_policy,
+        panic_hook: None,
         exit: false,
         process_handler: ProcessHandler {
             reapers: VecDeque::new(),
+            pending: Vec::new(),
         },
     };
-    core.register_reader(&signals, core.next_id(), reap_all);
-    core.add(signals);
-    core
+    if builder.reap_children {
+        let signals = Signals::new([signal_hook::SIGCHLD])?;
+        core.register_reader(&signals, core.next_id(), reap_all)?;
+        core.add_internal(signals);
+    }
+    Ok(core)
+}
+
+pub fn watch_reload(core: &mut Core) -> io::Result<()> {
+    let signals = Signals::new([signal_hook::SIGHUP])?;
+    core.register_reader(&signals, core.next_id(), |signals: &mut Signals, core| {
+        if signals.pending().count() > 0 {
+            core.reload();
+        }
+    })?;
+    core.add_internal(signals);
+    Ok(())
 }
 
-pub fn register_reaper<F, T, S>(core: &mut Core, child: &Child<S>, object_id: ObjectId, f: F)
-where
+/// Asks the process with the given id to reload its configuration, by sending it
+/// SIGHUP.
+///
+/// This runs the callbacks it registered with `Core::on_reload`.
+pub fn request_reload(pid: u32) -> io::Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGHUP) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn register_reaper<F, T, S, O, E>(
+    core: &mut Core,
+    child: &Child<S, O, E>,
+    object_id: ObjectId,
+    f: F,
+) where
     F: 'static + FnMut(&mut T, &mut Core),
     T: Any,
 {
     core.process_handler.reapers.push_back(Reaper {
         pid: child.child.id() as libc::pid_t,
+        exit_status: child.exit_status.clone(),
         object_id,
-        callback: Box::new(Callback::new(f)),
+        generation: core.expected_generation(object_id),
+        callback: Some(Box::new(Callback::new(f))),
     });
 }
 
 struct Reaper {
     pid: libc::pid_t,
+    exit_status: Rc<Cell<Option<ExitStatus>>>,
     object_id: ObjectId,
-    callback: Box<Call>,
+    // the generation the object is expected to have, see Core::generations.
+    generation: u32,
+    // taken when the reaper is dropped. The child is still waited for, so it doesn't
+    // linger as a zombie.
+    callback: Option<Box<dyn Call>>,
 }
 
 pub struct ProcessHandler {
     reapers: VecDeque<Reaper>,
+    // reapers of children that have exited before their object was added, along with
+    // the number of polls left to wait for it.
+    pending: Vec<(Reaper, usize)>,
+}
+
+impl ProcessHandler {
+    pub fn reaper_count(&self) -> usize {
+        let pending = self.pending.iter().map(|(r, _)| r);
+        self.reapers
+            .iter()
+            .chain(pending)
+            .filter(|r| r.callback.is_some())
+            .count()
+    }
+
+    pub fn drop_reapers(&mut self, pid: u32) -> bool {
+        let mut dropped = false;
+        let pending = self.pending.iter_mut().map(|(r, _)| r);
+        for r in self.reapers.iter_mut().chain(pending) {
+            if r.pid as u32 == pid {
+                dropped |= r.callback.take().is_some();
+            }
+        }
+        dropped
+    }
+
+    pub fn terminate_all(&self) {
+        for reaper in &self.reapers {
+            if let Err(err) = send_signal(reaper.pid as u32, libc::SIGTERM) {
+                error!("Failed to terminate process {}: {}", reaper.pid, err);
+            }
+        }
+    }
+
+    pub fn drop_object_reapers(&mut self, object_id: ObjectId) {
+        let pending = self.pending.iter_mut().map(|(r, _)| r);
+        for r in self.reapers.iter_mut().chain(pending) {
+            if r.object_id == object_id {
+                r.callback = None;
+            }
+        }
+    }
 }
 
 fn reap_all(signals: &mut Signals, core: &mut Core) {
     // drain all pending signals, but we don't need to check which signal we got.
     for _ in signals.pending() {}
+    // a child can have several reapers, but can only be waited for once.
+    let mut reaped = Vec::new();
     for _ in 0..core.process_handler.reapers.len() {
-        let mut r = core.process_handler.reapers.pop_front().unwrap();
-        match reap(r.pid) {
-            Ok(false) => core.process_handler.reapers.push_back(r),
-            Ok(true) => {
-                core.call_on_object(r.object_id, |obj, c| r.callback.make_call(obj, c));
+        let r = core.process_handler.reapers.pop_front().unwrap();
+        let result = match reaped.iter().find(|(pid, _)| *pid == r.pid) {
+            Some((_, exit_status)) => Ok(Some(*exit_status)),
+            None => {
+                let result = reap(r.pid);
+                if let Ok(Some(exit_status)) = result {
+                    reaped.push((r.pid, exit_status));
+                }
+                result
+            }
+        };
+        match result {
+            Ok(None) => core.process_handler.reapers.push_back(r),
+            Ok(Some(exit_status)) => {
+                r.exit_status.set(Some(exit_status));
+                deliver(r, PENDING_EVENT_POLLS, core);
             }
             Err(e) => error!("Failed to check if process has exited: {}", e),
         }
     }
 }
 
-fn reap(pid: libc::pid_t) -> io::Result<bool> {
+pub fn dispatch_pending_reapers(core: &mut Core) {
+    for (r, polls_left) in std::mem::take(&mut core.process_handler.pending) {
+        deliver(r, polls_left - 1, core);
+    }
+}
+
+// calls the reaper of a child that has exited, or holds it back if its object hasn't
+// been added yet, like the events of the object's registrations.
+fn deliver(mut r: Reaper, polls_left: usize, core: &mut Core) {
+    let callback = match &mut r.callback {
+        Some(callback) => callback,
+        None => return,
+    };
+    if core.call_on_object(r.object_id, |obj, c| callback.make_call(obj, c))
+        || core.contains(r.object_id)
+        || core.expected_generation(r.object_id) != r.generation
+    {
+        return;
+    }
+    if polls_left == 0 {
+        warn!(
+            "Object {:?} was never added to the core, dropping the reaper of process {}.",
+            r.object_id, r.pid
+        );
+        return;
+    }
+    trace!(
+        "Object {:?} has not been added yet, holding back the exit of process {}.",
+        r.object_id,
+        r.pid
+    );
+    core.process_handler.pending.push((r, polls_left));
+}
+
+fn reap(pid: libc::pid_t) -> io::Result<Option<ExitStatus>> {
     let mut status = 0;
     loop {
         match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } {
-            0 => return Ok(false),
+            0 => return Ok(None),
             n if n < 0 => {
                 let err = io::Error::last_os_error();
                 if err.kind() == io::ErrorKind::Interrupted {
@@ -80,21 +249,169 @@ fn reap(pid: libc::pid_t) -> io::Result<bool> {
             }
             n => {
                 assert_eq!(n, pid);
-                return Ok(true);
+                return Ok(Some(ExitStatus::from_raw(status)));
+            }
+        }
+    }
+}
+
+pub fn send_signal(pid: u32, signal: i32) -> io::Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn signal_group<S, O, E>(child: &Child<S, O, E>, signal: i32) -> io::Result<()> {
+    let pid = child.id() as libc::pid_t;
+    let pgid = unsafe { libc::getpgid(pid) };
+    if child.exit_status().is_none() {
+        // the child hasn't been reaped, so the pid is still its own.
+        if pgid != pid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The child doesn't lead a process group.",
+            ));
+        }
+    } else if pgid != -1 {
+        // the group can't outlive the child's pid being taken by another process.
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "The child's process group is gone.",
+        ));
+    }
+    if unsafe { libc::killpg(pid, signal) } == -1 {
+        let err = io::Error::last_os_error();
+        // the whole group has exited already.
+        if err.raw_os_error() == Some(libc::ESRCH) && child.exit_status().is_some() {
+            return Ok(());
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+pub fn kill_group<S, O, E>(child: &mut Child<S, O, E>) -> io::Result<()> {
+    signal_group(child, libc::SIGKILL)
+}
+
+// nothing needs to be kept around for a process group on unix, its id is the pid of
+// the child leading it.
+pub struct ProcessGroup;
+
+impl ProcessGroup {
+    pub fn new(_child: &process::Child) -> ProcessGroup {
+        ProcessGroup
+    }
+}
+
+pub fn terminate<S, O, E>(
+    child: &mut Child<S, O, E>,
+    grace: Duration,
+    object_id: ObjectId,
+    core: &mut Core,
+) -> io::Result<Option<TimerId>> {
+    child.signal(libc::SIGTERM)?;
+    let pid = child.id();
+    let exit_status = child.exit_status.clone();
+    let kill = move |_: &mut Core| {
+        if exit_status.get().is_none() {
+            if let Err(err) = send_signal(pid, libc::SIGKILL) {
+                error!("Failed to kill process {}: {}", pid, err);
             }
         }
+    };
+    Ok(Some(core.timers.add(
+        grace,
+        None,
+        object_id,
+        Box::new(CoreCallback(kill)),
+    )))
+}
+
+// a way to look at a process for `ChildMonitor`.
+pub struct ProcessRef {
+    pid: u32,
+}
+
+impl ProcessRef {
+    pub fn new<S, O, E>(child: &Child<S, O, E>) -> io::Result<ProcessRef> {
+        if !cfg!(target_os = "linux") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Sampling processes is only supported on Linux and Windows.",
+            ));
+        }
+        Ok(ProcessRef { pid: child.id() })
+    }
+
+    pub fn sample(&self) -> io::Result<ResourceSample> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Unexpected /proc format.");
+        let stat = fs::read_to_string(format!("/proc/{}/stat", self.pid))?;
+        // the command name may contain spaces and parentheses, so fields are counted
+        // from the end of it. The first field after it is the third one.
+        let name_end = stat.rfind(')').ok_or_else(invalid)?;
+        let fields: Vec<&str> = stat[name_end + 1..].split_whitespace().collect();
+        let field = |n: usize| -> io::Result<u64> {
+            fields
+                .get(n - 3)
+                .and_then(|f| f.parse().ok())
+                .ok_or_else(invalid)
+        };
+        // utime and stime, in clock ticks.
+        let ticks = field(14)? + field(15)?;
+        let statm = fs::read_to_string(format!("/proc/{}/statm", self.pid))?;
+        let pages: u64 = statm
+            .split_whitespace()
+            .nth(1)
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(invalid)?;
+        let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        Ok(ResourceSample {
+            rss: pages * page_size,
+            cpu_time: Duration::from_secs(ticks / ticks_per_second)
+                + Duration::from_nanos(ticks % ticks_per_second * 1_000_000_000 / ticks_per_second),
+        })
     }
 }
 
+pub fn is_hangup(readiness: Ready) -> bool {
+    let readiness = UnixReady::from(readiness);
+    readiness.is_hup() || readiness.is_error()
+}
+
+pub fn is_error(readiness: Ready) -> bool {
+    UnixReady::from(readiness).is_error()
+}
+
 #[derive(Debug)]
 pub struct Fd<T>(T);
 
-// FIXME: should be able to impl Into<Stdio> so that it can be passed to another Command
+// allows a pipe to be passed on to another command, e.g. to connect the stdout of a
+// child to the stdin of another one. The pipe is made blocking again, as children
+// generally don't expect anything else.
+impl<T: AsRawFd + Into<Stdio>> From<Fd<T>> for Stdio {
+    fn from(fd: Fd<T>) -> Stdio {
+        let raw = fd.as_raw_fd();
+        unsafe {
+            let flags = libc::fcntl(raw, libc::F_GETFL);
+            if flags != -1 {
+                libc::fcntl(raw, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+            }
+        }
+        fd.0.into()
+    }
+}
 
 impl<T: io::Read> io::Read for Fd<T> {
     fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
         self.0.read(bytes)
     }
+
+    fn read_vectored(&mut self, buffers: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        self.0.read_vectored(buffers)
+    }
 }
 
 impl<T: io::Write> io::Write for Fd<T> {
@@ -143,12 +460,16 @@ pub type Stdin = Fd<process::ChildStdin>;
 pub type Stdout = Fd<process::ChildStdout>;
 pub type Stderr = Fd<process::ChildStderr>;
 
-pub fn new_child(mut child: process::Child) -> io::Result<Child<Stdin>> {
-    let stdin = make_nonblocking(child.stdin.take().unwrap())?;
-    let stdout = make_nonblocking(child.stdout.take().unwrap())?;
-    let stderr = make_nonblocking(child.stderr.take().unwrap())?;
+pub fn new_child(
+    mut child: process::Child,
+) -> io::Result<Child<Option<Stdin>, Option<Stdout>, Option<Stderr>>> {
+    let stdin = child.stdin.take().map(make_nonblocking).transpose()?;
+    let stdout = child.stdout.take().map(make_nonblocking).transpose()?;
+    let stderr = child.stderr.take().map(make_nonblocking).transpose()?;
     Ok(Child {
+        group: ProcessGroup::new(&child),
         child,
+        exit_status: Rc::default(),
         stdin,
         stdout,
         stderr,
@@ -156,7 +477,7 @@ pub fn new_child(mut child: process::Child) -> io::Result<Child<Stdin>> {
 }
 
 // Set the fd to nonblocking before we pass it to the event loop
-fn make_nonblocking<T: AsRawFd>(io: T) -> io::Result<Fd<T>> {
+pub fn make_nonblocking<T: AsRawFd>(io: T) -> io::Result<Fd<T>> {
     let fd = io.as_raw_fd();
     unsafe {
         let r = libc::fcntl(fd, libc::F_GETFL);
@@ -1,35 +1,96 @@
-use crate::{Call, Callback, Child, Core, ObjectId};
-use log::error;
-use mio::Poll;
+use crate::{
+    Call, Callback, Child, Core, CoreBuilder, ObjectId, ResourceSample, TimerId,
+    PENDING_EVENT_POLLS,
+};
+use log::{error, trace, warn};
+use mio::{Events as MioEvents, Poll, Ready};
 use mio_extras::channel::{channel, Receiver, Sender};
 use mio_named_pipes::NamedPipe;
 use stash::Stash;
 use std::any::Any;
+use std::cell::Cell;
 use std::collections::VecDeque;
+use std::ffi::OsStr;
 use std::io;
+use std::mem;
+use std::os::windows::ffi::OsStrExt;
 use std::os::windows::io::{AsRawHandle, FromRawHandle, IntoRawHandle};
-use std::process;
-use winapi::um::handleapi::INVALID_HANDLE_VALUE;
-use winapi::um::synchapi::WaitForSingleObject;
+use std::os::windows::process::ExitStatusExt;
+use std::process::{self, ExitStatus};
+use std::ptr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, FILETIME, TRUE};
+use winapi::um::consoleapi::SetConsoleCtrlHandler;
+use winapi::um::handleapi::{CloseHandle, DuplicateHandle, INVALID_HANDLE_VALUE};
+use winapi::um::jobapi2::{AssignProcessToJobObject, CreateJobObjectW, TerminateJobObject};
+use winapi::um::processthreadsapi::{
+    GetCurrentProcess, GetExitCodeProcess, GetProcessTimes, TerminateProcess,
+};
+use winapi::um::psapi::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+use winapi::um::synchapi::{CreateEventW, OpenEventW, SetEvent, WaitForSingleObject};
 use winapi::um::threadpoollegacyapiset::UnregisterWaitEx;
 use winapi::um::winbase::{RegisterWaitForSingleObject, INFINITE, WAIT_OBJECT_0};
-use winapi::um::winnt::{BOOLEAN, HANDLE, PVOID, WT_EXECUTEINWAITTHREAD, WT_EXECUTEONLYONCE};
+use winapi::um::wincon::CTRL_BREAK_EVENT;
+use winapi::um::winnt::{
+    BOOLEAN, DUPLICATE_SAME_ACCESS, EVENT_MODIFY_STATE, HANDLE, PVOID, WT_EXECUTEINWAITTHREAD,
+    WT_EXECUTEONLYONCE,
+};
 
-pub fn new_core() -> Core {
+pub fn new_core(builder: &CoreBuilder) -> io::Result<Core> {
     let (sender, receiver) = channel();
     let mut core = Core {
         io_handlers: Stash::default(),
+        interests: Vec::new(),
+        owners: Vec::new(),
+        priorities: Vec::new(),
+        triggers: Vec::new(),
         objects: Stash::default(),
-        poll: Poll::new().unwrap(),
+        generations: Vec::new(),
+        object_tokens: Vec::new(),
+        remove_hooks: Vec::new(),
+        deferred_remove_hooks: Vec::new(),
+        pending_events: Vec::new(),
+        tick_handlers: Vec::new(),
+        reload_handlers: Vec::new(),
+        shutdown_handlers: Vec::new(),
+        shutdown_deadline: None,
+        idle_handlers: Vec::new(),
+        next_idle_id: 0,
+        idle_due: true,
+        posted: Vec::new(),
+        deferred: Vec::new(),
+        chunk_buffers: Vec::new(),
+        watching_reload: false,
+        orphan_hook: None,
+        error_sink: None,
+        timers: Default::default(),
+        remote: None,
+        waker: None,
+        object_maps: Vec::new(),
+        internal_handlers: 0,
+        internal_objects: Vec::new(),
+        poll: Poll::new()?,
+        events: MioEvents::with_capacity(builder.events),
+        batch: Vec::new(),
+        now: Instant::now(),
+        #[cfg(feature = "stats")]
+        stats: crate::stats::Recorder::new(),
+        #[cfg(feature = "tracing")]
+        type_names: Vec::new(),
+        panic_policy: builder.panic_policy,
+        panic_hook: None,
         exit: false,
         process_handler: ProcessHandler {
             reapers: VecDeque::new(),
+            pending: Vec::new(),
             sender,
         },
     };
-    core.register_reader(&receiver, core.next_id(), reap);
-    core.add(receiver);
-    core
+    core.register_reader(&receiver, core.next_id(), reap)?;
+    core.add_internal(receiver);
+    Ok(core)
 }
 
 struct Sentinel {
@@ -50,9 +111,15 @@ unsafe extern "system" fn callback(ptr: PVOID, _timer_fired: BOOLEAN) {
 
 struct Reaper {
     wait_object: Option<HANDLE>,
+    // our own handle to the process, so that the exit code can be read even if the
+    // Child has been dropped in the meantime.
+    process: HANDLE,
+    exit_status: Rc<Cell<Option<ExitStatus>>>,
     sentinel: Box<Sentinel>,
     object_id: ObjectId,
-    callback: Box<Call>,
+    // the generation the object is expected to have, see Core::generations.
+    generation: u32,
+    callback: Box<dyn Call>,
 }
 
 impl Drop for Reaper {
@@ -63,19 +130,54 @@ impl Drop for Reaper {
                 error!("failed to unregister: {}", io::Error::last_os_error());
             }
         }
+        unsafe { CloseHandle(self.process) };
     }
 }
 pub struct ProcessHandler {
     reapers: VecDeque<Reaper>,
+    // reapers of children that have exited before their object was added, along with
+    // the number of polls left to wait for it.
+    pending: Vec<(Reaper, usize)>,
     sender: Sender<u32>,
 }
 
+impl ProcessHandler {
+    pub fn reaper_count(&self) -> usize {
+        self.reapers.len() + self.pending.len()
+    }
+
+    pub fn drop_reapers(&mut self, pid: u32) -> bool {
+        let count = self.reaper_count();
+        self.reapers.retain(|r| r.sentinel.id != pid);
+        self.pending.retain(|(r, _)| r.sentinel.id != pid);
+        self.reaper_count() != count
+    }
+
+    pub fn terminate_all(&self) {
+        for reaper in &self.reapers {
+            if unsafe { TerminateProcess(reaper.process, 1) } == 0 {
+                let err = io::Error::last_os_error();
+                error!(
+                    "Failed to terminate process {}: {}",
+                    reaper.sentinel.id, err
+                );
+            }
+        }
+    }
+
+    pub fn drop_object_reapers(&mut self, object_id: ObjectId) {
+        self.reapers.retain(|r| r.object_id != object_id);
+        self.pending.retain(|(r, _)| r.object_id != object_id);
+    }
+}
+
 fn reap(receiver: &mut Receiver<u32>, core: &mut Core) {
     while let Ok(id) = receiver.try_recv() {
         for _ in 0..core.process_handler.reapers.len() {
-            let mut r = core.process_handler.reapers.pop_front().unwrap();
+            let r = core.process_handler.reapers.pop_front().unwrap();
             if r.sentinel.id == id {
-                core.call_on_object(r.object_id, |obj, c| r.callback.make_call(obj, c));
+                r.exit_status.set(exit_status(r.process));
+                deliver(r, PENDING_EVENT_POLLS, core);
             } else {
                 core.process_handler.reapers.push_back(r);
             }
@@ -83,12 +185,187 @@ fn reap(receiver: &mut Receiver<u32>, core: &mut Core) {
     }
 }
 
-pub fn register_reaper<F, T, S>(core: &mut Core, child: &Child<S>, object_id: ObjectId, f: F)
-where
+pub fn dispatch_pending_reapers(core: &mut Core) {
+    for (r, polls_left) in mem::take(&mut core.process_handler.pending) {
+        deliver(r, polls_left - 1, core);
+    }
+}
+
+// calls the reaper of a child that has exited, or holds it back if its object hasn't
+// been added yet, like the events of the object's registrations.
+fn deliver(mut r: Reaper, polls_left: usize, core: &mut Core) {
+    let callback = &mut r.callback;
+    if core.call_on_object(r.object_id, |obj, c| callback.make_call(obj, c))
+        || core.contains(r.object_id)
+        || core.expected_generation(r.object_id) != r.generation
+    {
+        return;
+    }
+    if polls_left == 0 {
+        warn!(
+            "Object {:?} was never added to the core, dropping the reaper of process {}.",
+            r.object_id, r.sentinel.id
+        );
+        return;
+    }
+    trace!(
+        "Object {:?} has not been added yet, holding back the exit of process {}.",
+        r.object_id,
+        r.sentinel.id
+    );
+    core.process_handler.pending.push((r, polls_left));
+}
+
+// the event that Ctrl-Break sets, if reloads are being watched for.
+static RELOAD_EVENT: AtomicUsize = AtomicUsize::new(0);
+
+fn reload_event_name(pid: u32) -> Vec<u16> {
+    OsStr::new(&format!("Local\\looper-reload-{}", pid))
+        .encode_wide()
+        .chain(Some(0))
+        .collect()
+}
+
+unsafe extern "system" fn console_handler(ctrl_type: DWORD) -> BOOL {
+    let event = RELOAD_EVENT.load(Ordering::SeqCst);
+    if ctrl_type != CTRL_BREAK_EVENT || event == 0 {
+        return FALSE;
+    }
+    SetEvent(event as HANDLE);
+    TRUE
+}
+
+// the named event that reloads are requested through, and the wait for it.
+struct ReloadEvent {
+    receiver: Receiver<u32>,
+    event: HANDLE,
+    wait_object: Option<HANDLE>,
+    // what the wait callback sends through, so it must outlive the wait.
+    sentinel: Box<Sentinel>,
+}
+
+impl Drop for ReloadEvent {
+    fn drop(&mut self) {
+        if let Some(handle) = self.wait_object {
+            let rc = unsafe { UnregisterWaitEx(handle, INVALID_HANDLE_VALUE) };
+            if rc == 0 {
+                error!("failed to unregister: {}", io::Error::last_os_error());
+            }
+        }
+        let _ = RELOAD_EVENT.compare_exchange(
+            self.event as usize,
+            0,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+        unsafe { CloseHandle(self.event) };
+    }
+}
+
+pub fn watch_reload(core: &mut Core) -> io::Result<()> {
+    let name = reload_event_name(process::id());
+    // auto-reset, so that every time it is set wakes the wait once.
+    let event = unsafe { CreateEventW(ptr::null_mut(), FALSE, FALSE, name.as_ptr()) };
+    if event.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let (sender, receiver) = channel();
+    let mut reload = ReloadEvent {
+        receiver,
+        event,
+        wait_object: None,
+        sentinel: Box::new(Sentinel { id: 0, sender }),
+    };
+    let ptr = &mut *reload.sentinel as *mut Sentinel;
+    let mut wait_object = ptr::null_mut();
+    // not only once, so the wait goes on after each time the event is set.
+    let rc = unsafe {
+        RegisterWaitForSingleObject(
+            &mut wait_object,
+            event,
+            Some(callback),
+            ptr as *mut _,
+            INFINITE,
+            WT_EXECUTEINWAITTHREAD,
+        )
+    };
+    if rc == 0 {
+        // dropping `reload` closes the event.
+        return Err(io::Error::last_os_error());
+    }
+    reload.wait_object = Some(wait_object);
+    RELOAD_EVENT.store(event as usize, Ordering::SeqCst);
+    if unsafe { SetConsoleCtrlHandler(Some(console_handler), TRUE) } == 0 {
+        error!(
+            "Failed to set console control handler: {}",
+            io::Error::last_os_error()
+        );
+    }
+    let id = core.next_id();
+    core.register_reader(&reload.receiver, id, |reload: &mut ReloadEvent, core| {
+        let mut requested = false;
+        while reload.receiver.try_recv().is_ok() {
+            requested = true;
+        }
+        if requested {
+            core.reload();
+        }
+    })?;
+    core.add_internal(reload);
+    Ok(())
+}
+
+/// Asks the process with the given id to reload its configuration, by setting its
+/// reload event.
+///
+/// This runs the callbacks it registered with `Core::on_reload`. Fails if the
+/// process isn't watching for reloads.
+pub fn request_reload(pid: u32) -> io::Result<()> {
+    let name = reload_event_name(pid);
+    let event = unsafe { OpenEventW(EVENT_MODIFY_STATE, FALSE, name.as_ptr()) };
+    if event.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let rc = unsafe { SetEvent(event) };
+    let result = if rc == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    };
+    unsafe { CloseHandle(event) };
+    result
+}
+
+pub fn register_reaper<F, T, S, O, E>(
+    core: &mut Core,
+    child: &Child<S, O, E>,
+    object_id: ObjectId,
+    f: F,
+) where
     F: 'static + FnMut(&mut T, &mut Core),
     T: Any,
 {
-    let res = unsafe { WaitForSingleObject(child.child.as_raw_handle(), 0) };
+    let mut process = 0 as HANDLE;
+    let rc = unsafe {
+        DuplicateHandle(
+            GetCurrentProcess(),
+            child.child.as_raw_handle() as HANDLE,
+            GetCurrentProcess(),
+            &mut process,
+            0,
+            FALSE,
+            DUPLICATE_SAME_ACCESS,
+        )
+    };
+    if rc == 0 {
+        error!(
+            "Failed to duplicate process handle: {}",
+            io::Error::last_os_error()
+        );
+        return;
+    }
+    let generation = core.expected_generation(object_id);
+    let res = unsafe { WaitForSingleObject(process, 0) };
     let mut sentinel = Box::new(Sentinel {
         sender: core.process_handler.sender.clone(),
         id: child.child.id(),
@@ -98,7 +375,10 @@ where
         Reaper {
             sentinel,
             wait_object: None,
+            process,
+            exit_status: child.exit_status.clone(),
             object_id,
+            generation,
             callback: Box::new(Callback::new(f)),
         }
     } else {
@@ -107,7 +387,7 @@ where
         let rc = unsafe {
             RegisterWaitForSingleObject(
                 &mut wait_object,
-                child.child.as_raw_handle(),
+                process,
                 Some(callback),
                 ptr as *mut _,
                 INFINITE,
@@ -120,29 +400,189 @@ where
                 "Failed to register callback for process exit: {}",
                 io::Error::last_os_error()
             );
+            unsafe { CloseHandle(process) };
             return;
         }
 
         Reaper {
             sentinel,
             wait_object: Some(wait_object),
+            process,
+            exit_status: child.exit_status.clone(),
             object_id,
+            generation,
             callback: Box::new(Callback::new(f)),
         }
     };
     core.process_handler.reapers.push_back(reaper);
 }
 
+fn exit_status(process: HANDLE) -> Option<ExitStatus> {
+    let mut code = 0;
+    if unsafe { GetExitCodeProcess(process, &mut code) } == 0 {
+        error!(
+            "Failed to get exit code of process: {}",
+            io::Error::last_os_error()
+        );
+        return None;
+    }
+    // GetExitCodeProcess returns STILL_ACTIVE (259) for running processes, but we only
+    // get here once the process handle has been signaled, so the process has exited
+    // and 259 really is its exit code.
+    Some(ExitStatus::from_raw(code))
+}
+
+pub fn terminate<S, O, E>(
+    child: &mut Child<S, O, E>,
+    _grace: Duration,
+    _object_id: ObjectId,
+    _core: &mut Core,
+) -> io::Result<Option<TimerId>> {
+    // there is no signal to ask a process to exit with.
+    child.kill().map(|_| None)
+}
+
+// a job object holding a child and the processes it starts, for `kill_group`.
+pub struct ProcessGroup {
+    // null if the child couldn't be put in a job.
+    job: HANDLE,
+}
+
+impl ProcessGroup {
+    pub fn new(child: &process::Child) -> ProcessGroup {
+        let job = unsafe { CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+        if job.is_null() {
+            error!(
+                "Failed to create a job object: {}",
+                io::Error::last_os_error()
+            );
+            return ProcessGroup { job };
+        }
+        let process = child.as_raw_handle() as HANDLE;
+        if unsafe { AssignProcessToJobObject(job, process) } == 0 {
+            error!(
+                "Failed to put process {} in a job object: {}",
+                child.id(),
+                io::Error::last_os_error()
+            );
+            unsafe { CloseHandle(job) };
+            return ProcessGroup {
+                job: ptr::null_mut(),
+            };
+        }
+        ProcessGroup { job }
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        // the processes in the job keep running.
+        if !self.job.is_null() {
+            unsafe { CloseHandle(self.job) };
+        }
+    }
+}
+
+pub fn kill_group<S, O, E>(child: &mut Child<S, O, E>) -> io::Result<()> {
+    if child.group.job.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The child couldn't be put in a job object.",
+        ));
+    }
+    if unsafe { TerminateJobObject(child.group.job, 1) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// a way to look at a process for `ChildMonitor`.
+pub struct ProcessRef {
+    // our own handle, which stays valid if the Child is dropped.
+    process: HANDLE,
+}
+
+impl ProcessRef {
+    pub fn new<S, O, E>(child: &Child<S, O, E>) -> io::Result<ProcessRef> {
+        let mut process = 0 as HANDLE;
+        let rc = unsafe {
+            DuplicateHandle(
+                GetCurrentProcess(),
+                child.child.as_raw_handle() as HANDLE,
+                GetCurrentProcess(),
+                &mut process,
+                0,
+                FALSE,
+                DUPLICATE_SAME_ACCESS,
+            )
+        };
+        if rc == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ProcessRef { process })
+    }
+
+    pub fn sample(&self) -> io::Result<ResourceSample> {
+        let mut counters: PROCESS_MEMORY_COUNTERS = unsafe { mem::zeroed() };
+        counters.cb = mem::size_of::<PROCESS_MEMORY_COUNTERS>() as DWORD;
+        if unsafe { GetProcessMemoryInfo(self.process, &mut counters, counters.cb) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut creation: FILETIME = unsafe { mem::zeroed() };
+        let mut exit: FILETIME = unsafe { mem::zeroed() };
+        let mut kernel: FILETIME = unsafe { mem::zeroed() };
+        let mut user: FILETIME = unsafe { mem::zeroed() };
+        let rc = unsafe {
+            GetProcessTimes(
+                self.process,
+                &mut creation,
+                &mut exit,
+                &mut kernel,
+                &mut user,
+            )
+        };
+        if rc == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // file times count 100 nanosecond intervals.
+        let nanos =
+            |t: FILETIME| ((u64::from(t.dwHighDateTime) << 32) | u64::from(t.dwLowDateTime)) * 100;
+        Ok(ResourceSample {
+            rss: counters.WorkingSetSize as u64,
+            cpu_time: Duration::from_nanos(nanos(kernel) + nanos(user)),
+        })
+    }
+}
+
+impl Drop for ProcessRef {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.process) };
+    }
+}
+
+pub fn is_hangup(_readiness: Ready) -> bool {
+    // mio doesn't report hangups on windows.
+    false
+}
+
+pub fn is_error(_readiness: Ready) -> bool {
+    false
+}
+
 pub type Stdin = NamedPipe;
 pub type Stdout = NamedPipe;
 pub type Stderr = NamedPipe;
 
-pub fn new_child(mut child: process::Child) -> io::Result<Child<Stdin>> {
-    let stdin = stdio(child.stdin.take().unwrap());
-    let stdout = stdio(child.stdout.take().unwrap());
-    let stderr = stdio(child.stderr.take().unwrap());
+pub fn new_child(
+    mut child: process::Child,
+) -> io::Result<Child<Option<Stdin>, Option<Stdout>, Option<Stderr>>> {
+    let stdin = child.stdin.take().map(stdio);
+    let stdout = child.stdout.take().map(stdio);
+    let stderr = child.stderr.take().map(stdio);
     Ok(Child {
+        group: ProcessGroup::new(&child),
         child,
+        exit_status: Rc::default(),
         stdin,
         stdout,
         stderr,
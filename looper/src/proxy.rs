@@ -0,0 +1,126 @@
+use crate::source::Source;
+use crate::{Core, ObjectId};
+use std::io::{self, ErrorKind, Read, Write};
+
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+type Done = Box<dyn FnOnce(io::Result<u64>, &mut Core)>;
+
+/// Copies everything read from a source to a sink as the two become ready.
+///
+/// At most one buffer of data is held at a time, so reading stops while the sink is
+/// not keeping up and continues once it has taken the buffered data. When the source
+/// reaches its end and everything has been written, or either side fails, the proxy
+/// removes itself from the core, closing both ends, and calls the completion callback
+/// with the number of bytes copied or the error.
+pub struct Proxy<R, W> {
+    source: R,
+    sink: W,
+    buffer: Vec<u8>,
+    // the part of the buffer that has been read but not written yet.
+    start: usize,
+    end: usize,
+    eof: bool,
+    copied: u64,
+    object_id: ObjectId,
+    on_done: Option<Done>,
+}
+
+impl<R, W> Proxy<R, W>
+where
+    R: 'static + Read + Source,
+    W: 'static + Write + Source,
+{
+    /// Adds a proxy from `source` to `sink` to the core.
+    ///
+    /// Fails if either end can't be registered with the core.
+    ///
+    /// The source and sink must be registered separately, so they can't be the same
+    /// socket or pipe.
+    pub fn start<F>(source: R, sink: W, core: &mut Core, on_done: F) -> io::Result<ObjectId>
+    where
+        F: 'static + FnOnce(io::Result<u64>, &mut Core),
+    {
+        Proxy::with_buffer_size(source, sink, DEFAULT_BUFFER_SIZE, core, on_done)
+    }
+
+    /// Like `start`, but with a buffer of `buffer_size` bytes instead of the default
+    /// 64 KiB.
+    pub fn with_buffer_size<F>(
+        source: R,
+        sink: W,
+        buffer_size: usize,
+        core: &mut Core,
+        on_done: F,
+    ) -> io::Result<ObjectId>
+    where
+        F: 'static + FnOnce(io::Result<u64>, &mut Core),
+    {
+        let object_id = core.next_id();
+        core.register_reader(&source, object_id, Proxy::<R, W>::pump)?;
+        if let Err(err) = core.register_writer(&sink, object_id, Proxy::<R, W>::pump) {
+            core.unregister_object(object_id);
+            return Err(err);
+        }
+        Ok(core.add(Proxy {
+            source,
+            sink,
+            buffer: vec![0; buffer_size.max(1)],
+            start: 0,
+            end: 0,
+            eof: false,
+            copied: 0,
+            object_id,
+            on_done: Some(Box::new(on_done)),
+        }))
+    }
+
+    /// Returns the number of bytes copied so far.
+    pub fn copied(&self) -> u64 {
+        self.copied
+    }
+
+    fn pump(&mut self, core: &mut Core) {
+        let result = match self.transfer() {
+            Ok(false) => return,
+            Ok(true) => Ok(self.copied),
+            Err(err) => Err(err),
+        };
+        core.remove(self.object_id);
+        if let Some(on_done) = self.on_done.take() {
+            on_done(result, core);
+        }
+    }
+
+    // moves data until either side would block. Returns true once everything has been
+    // copied.
+    fn transfer(&mut self) -> io::Result<bool> {
+        loop {
+            if self.start < self.end {
+                match self.sink.write(&self.buffer[self.start..self.end]) {
+                    Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                    Ok(n) => {
+                        self.start += n;
+                        self.copied += n as u64;
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                    Err(e) => return Err(e),
+                }
+                continue;
+            }
+            if self.eof {
+                return Ok(true);
+            }
+            self.start = 0;
+            self.end = 0;
+            match self.source.read(&mut self.buffer) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.end = n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::proc_imp::{make_nonblocking, Fd};
+use mio::{Evented, Poll, PollOpt, Ready, Token};
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child as ProcessChild, Command, Stdio};
+
+/// Options for spawning a child on a pseudo-terminal, see `Core::spawn_pty`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PtyOptions {
+    /// The initial size of the terminal, in characters. Zero leaves it unset.
+    pub rows: u16,
+    pub columns: u16,
+}
+
+/// The controlling end of a pseudo-terminal, through which a child's terminal is read
+/// and written.
+///
+/// Data written is what the child reads as typed input, and data read is what the
+/// child writes to its terminal. Once the child and everything it started have
+/// closed the terminal, reads fail with an IO error on Linux rather than reporting
+/// the end of the stream.
+#[derive(Debug)]
+pub struct PtyMaster(Fd<File>);
+
+impl PtyMaster {
+    /// Changes the size of the terminal, which sends the child a SIGWINCH.
+    pub fn resize(&self, rows: u16, columns: u16) -> io::Result<()> {
+        let size = window_size(rows, columns);
+        check(unsafe { libc::ioctl(self.as_raw_fd(), libc::TIOCSWINSZ, &size) })
+    }
+}
+
+impl Read for PtyMaster {
+    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+        self.0.read(bytes)
+    }
+}
+
+impl Write for PtyMaster {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl AsRawFd for PtyMaster {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl Evented for PtyMaster {
+    fn register(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        self.0.register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        self.0.reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        self.0.deregister(poll)
+    }
+}
+
+fn window_size(rows: u16, columns: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: columns,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// opens a new terminal, returning its master and slave ends, both close-on-exec so
+// children spawned by other threads in the meantime don't inherit them.
+fn open() -> io::Result<(File, File)> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let flags = libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let flags = libc::O_RDWR | libc::O_NOCTTY;
+    let master = unsafe { libc::posix_openpt(flags) };
+    check(master)?;
+    let master = unsafe { File::from_raw_fd(master) };
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    check(unsafe { libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    check(unsafe { libc::grantpt(master.as_raw_fd()) })?;
+    check(unsafe { libc::unlockpt(master.as_raw_fd()) })?;
+    let name = slave_name(&master)?;
+    let slave = unsafe {
+        libc::open(
+            name.as_ptr(),
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        )
+    };
+    check(slave)?;
+    Ok((master, unsafe { File::from_raw_fd(slave) }))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn slave_name(master: &File) -> io::Result<CString> {
+    let mut name = [0 as libc::c_char; 128];
+    let result = unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+    Ok(unsafe { CStr::from_ptr(name.as_ptr()) }.to_owned())
+}
+
+// `ptsname_r` isn't everywhere, and `ptsname` returns a static buffer, so the name is
+// copied straight away.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn slave_name(master: &File) -> io::Result<CString> {
+    let name = unsafe { libc::ptsname(master.as_raw_fd()) };
+    if name.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { CStr::from_ptr(name) }.to_owned())
+}
+
+pub fn spawn(cmd: &mut Command, options: PtyOptions) -> io::Result<(ProcessChild, PtyMaster)> {
+    let (master, slave) = open()?;
+    if options.rows > 0 && options.columns > 0 {
+        let size = window_size(options.rows, options.columns);
+        check(unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) })?;
+    }
+    // the child dups these onto its stdio, which are the only copies of the slave it
+    // keeps across exec.
+    cmd.stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    unsafe {
+        // start a new session with the terminal as its controlling terminal, so the
+        // child gets signals for ^C and the like.
+        cmd.pre_exec(|| {
+            check(libc::setsid())?;
+            check(libc::ioctl(0, libc::TIOCSCTTY as _, 0))
+        });
+    }
+    let child = cmd.spawn();
+    // `cmd` holds the parent's copies of the slave, which must be closed for reads
+    // from the master to fail once the child is gone.
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    Ok((child?, PtyMaster(make_nonblocking(master)?)))
+}
@@ -0,0 +1,111 @@
+use crate::proc_imp::make_nonblocking;
+use mio::unix::{EventedFd, UnixReady};
+use mio::{Evented, Poll, PollOpt, Ready, Token};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem::ManuallyDrop;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+
+/// Any file descriptor, such as a serial port, a GPIO line or a netlink socket, made
+/// ready to be registered with the core.
+///
+/// The descriptor is made nonblocking. Like the pipes to children it is registered for
+/// hangups too, so readers and hangup callbacks are called once the other end goes
+/// away.
+#[derive(Debug)]
+pub struct RawFdSource {
+    file: ManuallyDrop<File>,
+    // whether the descriptor is closed on drop, as it is when made with `from_file`.
+    owned: bool,
+}
+
+impl RawFdSource {
+    /// Wraps a descriptor owned by someone else, which is left open on drop.
+    ///
+    /// The descriptor has to stay open for as long as this is registered.
+    pub fn new(fd: RawFd) -> io::Result<RawFdSource> {
+        make_nonblocking(fd)?;
+        Ok(RawFdSource {
+            file: ManuallyDrop::new(unsafe { File::from_raw_fd(fd) }),
+            owned: false,
+        })
+    }
+
+    /// Takes over `file`, which is closed on drop.
+    pub fn from_file(file: File) -> io::Result<RawFdSource> {
+        make_nonblocking(file.as_raw_fd())?;
+        Ok(RawFdSource {
+            file: ManuallyDrop::new(file),
+            owned: true,
+        })
+    }
+}
+
+impl Drop for RawFdSource {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { ManuallyDrop::drop(&mut self.file) };
+        }
+    }
+}
+
+impl Read for RawFdSource {
+    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+        self.file.read(bytes)
+    }
+
+    fn read_vectored(&mut self, buffers: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        self.file.read_vectored(buffers)
+    }
+}
+
+impl Write for RawFdSource {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.file.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl AsRawFd for RawFdSource {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for RawFdSource {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.as_raw_fd();
+        // the caller is responsible for closing it now.
+        std::mem::forget(self);
+        fd
+    }
+}
+
+impl Evented for RawFdSource {
+    fn register(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.as_raw_fd()).register(poll, token, interest | UnixReady::hup(), opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.as_raw_fd()).reregister(poll, token, interest | UnixReady::hup(), opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        EventedFd(&self.as_raw_fd()).deregister(poll)
+    }
+}
@@ -0,0 +1,75 @@
+use mio::{Evented, Poll, PollOpt, Ready, Token};
+use mio_named_pipes::NamedPipe;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::windows::io::{AsRawHandle, FromRawHandle, IntoRawHandle, RawHandle};
+
+/// Any pipe handle, made ready to be registered with the core.
+///
+/// Like the pipes to children, the handle is driven with overlapped IO, so it has to
+/// have been opened with `FILE_FLAG_OVERLAPPED`. Hangups show up as reads of zero
+/// bytes or broken pipe errors, as mio doesn't report them on windows. Sockets are
+/// best registered through mio's `TcpStream::from_stream` and friends instead.
+#[derive(Debug)]
+pub struct RawHandleSource(NamedPipe);
+
+impl RawHandleSource {
+    /// Takes over `file`, which is closed on drop.
+    pub fn from_file(file: File) -> RawHandleSource {
+        unsafe { RawHandleSource::from_raw_handle(file.into_raw_handle()) }
+    }
+}
+
+impl FromRawHandle for RawHandleSource {
+    unsafe fn from_raw_handle(handle: RawHandle) -> RawHandleSource {
+        RawHandleSource(NamedPipe::from_raw_handle(handle))
+    }
+}
+
+impl Read for RawHandleSource {
+    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+        self.0.read(bytes)
+    }
+}
+
+impl Write for RawHandleSource {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl AsRawHandle for RawHandleSource {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.as_raw_handle()
+    }
+}
+
+impl Evented for RawHandleSource {
+    fn register(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        self.0.register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        self.0.reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        self.0.deregister(poll)
+    }
+}
@@ -0,0 +1,63 @@
+use crate::Core;
+use log::error;
+use mio::{Ready, Registration, SetReadiness};
+use std::io;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+type Task = Box<dyn FnOnce(&mut Core) + Send>;
+
+/// A handle for running code on a core from other threads.
+///
+/// Remotes are obtained from `Core::remote`, and can be cloned and sent to any thread.
+#[derive(Clone)]
+pub struct Remote {
+    sender: Sender<Task>,
+    set_readiness: SetReadiness,
+}
+
+impl Remote {
+    /// Queues `f` to be called with the core on the thread running its event loop,
+    /// waking the loop up if it is waiting for events.
+    ///
+    /// Fails if the core has been dropped.
+    pub fn run(&self, f: impl 'static + Send + FnOnce(&mut Core)) -> io::Result<()> {
+        self.sender
+            .send(Box::new(f))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The core has been dropped."))?;
+        self.set_readiness.set_readiness(Ready::readable())
+    }
+}
+
+struct RemoteReceiver {
+    // kept for its registration with the core.
+    _registration: Registration,
+    set_readiness: SetReadiness,
+    receiver: Receiver<Task>,
+}
+
+impl RemoteReceiver {
+    fn run_tasks(&mut self, core: &mut Core) {
+        // clear the readiness first, so tasks sent from now on wake the loop again.
+        if let Err(err) = self.set_readiness.set_readiness(Ready::empty()) {
+            error!("Failed to reset readiness for remote tasks: {}", err);
+        }
+        while let Ok(task) = self.receiver.try_recv() {
+            task(core);
+        }
+    }
+}
+
+pub(crate) fn new_remote(core: &mut Core) -> io::Result<Remote> {
+    let (registration, set_readiness) = Registration::new2();
+    let (sender, receiver) = channel();
+    core.register_reader(&registration, core.next_id(), RemoteReceiver::run_tasks)?;
+    core.add_internal(RemoteReceiver {
+        _registration: registration,
+        set_readiness: set_readiness.clone(),
+        receiver,
+    });
+    Ok(Remote {
+        sender,
+        set_readiness,
+    })
+}
@@ -0,0 +1,189 @@
+use crate::Trigger;
+#[cfg(all(unix, feature = "mio08"))]
+use mio::unix::EventedFd;
+use mio::{Evented, Poll, Ready, Token};
+use std::io;
+#[cfg(all(unix, feature = "mio08"))]
+use std::io::{Read, Write};
+#[cfg(all(unix, feature = "mio08"))]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// Something that can be registered with the core, such as a socket or a pipe.
+///
+/// This is what the registration methods of `Core` take. Every mio 0.6 `Evented`
+/// is a source, so sockets and pipes are passed as they are. Sources of other kinds
+/// implement it by passing the calls on to a source they contain, see `Mio08` for
+/// the sources of mio 0.8.
+pub trait Source {
+    fn register(
+        &self,
+        registry: &Registry,
+        token: Token,
+        interest: Ready,
+        trigger: Trigger,
+    ) -> io::Result<()>;
+
+    fn reregister(
+        &self,
+        registry: &Registry,
+        token: Token,
+        interest: Ready,
+        trigger: Trigger,
+    ) -> io::Result<()>;
+
+    fn deregister(&self, registry: &Registry) -> io::Result<()>;
+}
+
+/// What sources are registered with, handed to them by the core.
+pub struct Registry<'a> {
+    poll: &'a Poll,
+}
+
+impl<'a> Registry<'a> {
+    pub(crate) fn new(poll: &'a Poll) -> Registry<'a> {
+        Registry { poll }
+    }
+}
+
+impl<E: Evented + ?Sized> Source for E {
+    fn register(
+        &self,
+        registry: &Registry,
+        token: Token,
+        interest: Ready,
+        trigger: Trigger,
+    ) -> io::Result<()> {
+        registry
+            .poll
+            .register(self, token, interest, trigger.poll_opt())
+    }
+
+    fn reregister(
+        &self,
+        registry: &Registry,
+        token: Token,
+        interest: Ready,
+        trigger: Trigger,
+    ) -> io::Result<()> {
+        registry
+            .poll
+            .reregister(self, token, interest, trigger.poll_opt())
+    }
+
+    fn deregister(&self, registry: &Registry) -> io::Result<()> {
+        registry.poll.deregister(self)
+    }
+}
+
+/// A mio 0.8 source, such as a `mio::net::TcpStream` of that version, made ready to be
+/// registered with the core.
+///
+/// The core still polls with mio 0.6, so the source is registered by its file
+/// descriptor, which is why this is only there on unix. It must not be registered
+/// with a mio 0.8 poll at the same time. Reads and writes go to the source.
+#[cfg(all(unix, feature = "mio08"))]
+#[derive(Debug)]
+pub struct Mio08<S>(S);
+
+#[cfg(all(unix, feature = "mio08"))]
+impl<S> Mio08<S>
+where
+    S: mio08::event::Source + AsRawFd,
+{
+    pub fn new(source: S) -> Mio08<S> {
+        Mio08(source)
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.0
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.0
+    }
+
+    pub fn into_inner(self) -> S {
+        self.0
+    }
+}
+
+#[cfg(all(unix, feature = "mio08"))]
+impl<S> Source for Mio08<S>
+where
+    S: mio08::event::Source + AsRawFd,
+{
+    fn register(
+        &self,
+        registry: &Registry,
+        token: Token,
+        interest: Ready,
+        trigger: Trigger,
+    ) -> io::Result<()> {
+        Source::register(
+            &EventedFd(&self.0.as_raw_fd()),
+            registry,
+            token,
+            interest,
+            trigger,
+        )
+    }
+
+    fn reregister(
+        &self,
+        registry: &Registry,
+        token: Token,
+        interest: Ready,
+        trigger: Trigger,
+    ) -> io::Result<()> {
+        Source::reregister(
+            &EventedFd(&self.0.as_raw_fd()),
+            registry,
+            token,
+            interest,
+            trigger,
+        )
+    }
+
+    fn deregister(&self, registry: &Registry) -> io::Result<()> {
+        Source::deregister(&EventedFd(&self.0.as_raw_fd()), registry)
+    }
+}
+
+#[cfg(all(unix, feature = "mio08"))]
+impl<S: AsRawFd> AsRawFd for Mio08<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[cfg(all(unix, feature = "mio08"))]
+impl<S: Read> Read for Mio08<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[cfg(all(unix, feature = "mio08"))]
+impl<S: Write> Write for Mio08<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Returns the readiness the core registers for to match a mio 0.8 `Interest`, e.g.
+/// for `Core::register_with_events`.
+#[cfg(feature = "mio08")]
+pub fn ready_for(interest: mio08::Interest) -> Ready {
+    let mut ready = Ready::empty();
+    if interest.is_readable() {
+        ready.insert(Ready::readable());
+    }
+    if interest.is_writable() {
+        ready.insert(Ready::writable());
+    }
+    ready
+}
@@ -0,0 +1,105 @@
+use log::info;
+use mio::Token;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+
+/// The environment variable that turns on logging the stats, every so many seconds.
+pub const LOG_INTERVAL_VAR: &str = "LOOPER_STATS_INTERVAL";
+
+/// What the event loop has been up to, see `Core::stats`.
+///
+/// A loop that keeps waking up without doing anything, or that gets the same events
+/// over and over, usually means a registration is level-triggered when it should
+/// be edge-triggered, or that a source isn't read until it would block.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    /// Number of times the loop woke up from waiting for events.
+    pub wakeups: u64,
+    /// Number of wakeups with no events and no expired timers, which includes the
+    /// ones caused by timeouts passed to `run_once`.
+    pub empty_wakeups: u64,
+    /// Number of events dispatched in total and for each registration.
+    pub events: u64,
+    pub events_per_token: HashMap<Token, u64>,
+    /// Number of callbacks called, and the time spent in them in total and at most.
+    pub callbacks: u64,
+    pub callback_time: Duration,
+    pub max_callback_time: Duration,
+}
+
+impl Stats {
+    // returns the registrations with the most events, most first.
+    fn busiest_tokens(&self, count: usize) -> Vec<(Token, u64)> {
+        let mut tokens: Vec<_> = self
+            .events_per_token
+            .iter()
+            .map(|(token, events)| (*token, *events))
+            .collect();
+        tokens.sort_by_key(|(_, events)| Reverse(*events));
+        tokens.truncate(count);
+        tokens
+    }
+}
+
+// collects the stats for a core, and logs them if asked to.
+pub struct Recorder {
+    pub stats: Stats,
+    log_interval: Option<Duration>,
+    next_log: Instant,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        let log_interval = env::var(LOG_INTERVAL_VAR)
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+        Recorder {
+            stats: Stats::default(),
+            log_interval,
+            next_log: Instant::now() + log_interval.unwrap_or_default(),
+        }
+    }
+
+    pub fn wakeup(&mut self, now: Instant, empty: bool) {
+        self.stats.wakeups += 1;
+        if empty {
+            self.stats.empty_wakeups += 1;
+        }
+        if let Some(interval) = self.log_interval {
+            if now >= self.next_log {
+                self.next_log = now + interval;
+                self.log();
+            }
+        }
+    }
+
+    pub fn event(&mut self, token: Token) {
+        self.stats.events += 1;
+        *self.stats.events_per_token.entry(token).or_insert(0) += 1;
+    }
+
+    pub fn callback(&mut self, time: Duration) {
+        self.stats.callbacks += 1;
+        self.stats.callback_time += time;
+        self.stats.max_callback_time = self.stats.max_callback_time.max(time);
+    }
+
+    fn log(&self) {
+        let stats = &self.stats;
+        info!(
+            "Event loop stats: {} wakeups ({} empty), {} events, {} callbacks taking {:?} \
+             ({:?} at most). Busiest registrations: {:?}",
+            stats.wakeups,
+            stats.empty_wakeups,
+            stats.events,
+            stats.callbacks,
+            stats.callback_time,
+            stats.max_callback_time,
+            stats.busiest_tokens(5),
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use crate::{Core, ObjectId};
+use log::error;
+use mio::{Evented, Poll, PollOpt, Ready, Registration, SetReadiness, Token};
+use std::any::Any;
+use std::cell::Cell;
+use std::io::{self, ErrorKind, Read};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+#[cfg(unix)]
+use mio::unix::EventedFd;
+
+// where the bytes on our stdin come from.
+enum Input {
+    #[cfg(unix)]
+    Nonblocking(NonblockingStdin),
+    Thread(ThreadedStdin),
+}
+
+impl Input {
+    fn new() -> io::Result<Input> {
+        #[cfg(unix)]
+        {
+            // files and some devices, such as /dev/null, can't be polled.
+            let probe = Poll::new()?;
+            let interest = Ready::readable();
+            match probe.register(&EventedFd(&0), Token(0), interest, PollOpt::edge()) {
+                Ok(()) => return Ok(Input::Nonblocking(NonblockingStdin::new()?)),
+                Err(ref e) if e.raw_os_error() == Some(libc::EPERM) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Input::Thread(ThreadedStdin::new()?))
+    }
+
+    fn source(&self) -> Source {
+        match self {
+            #[cfg(unix)]
+            Input::Nonblocking(_) => Source::Fd,
+            Input::Thread(stdin) => Source::Registration(stdin.registration.clone()),
+        }
+    }
+}
+
+// what is registered with the core for stdin.
+enum Source {
+    #[cfg(unix)]
+    Fd,
+    Registration(Rc<Registration>),
+}
+
+impl Evented for Source {
+    fn register(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Source::Fd => EventedFd(&0).register(poll, token, interest, opts),
+            Source::Registration(registration) => {
+                registration.register(poll, token, interest, opts)
+            }
+        }
+    }
+
+    fn reregister(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Source::Fd => EventedFd(&0).reregister(poll, token, interest, opts),
+            Source::Registration(registration) => {
+                registration.reregister(poll, token, interest, opts)
+            }
+        }
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Source::Fd => EventedFd(&0).deregister(poll),
+            Source::Registration(registration) => poll.deregister(&**registration),
+        }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+        match self {
+            #[cfg(unix)]
+            Input::Nonblocking(stdin) => stdin.read(bytes),
+            Input::Thread(stdin) => stdin.read(bytes),
+        }
+    }
+}
+
+// stdin made non-blocking, which is undone once it is dropped, as the flag is shared
+// with every other process using the same terminal or pipe.
+#[cfg(unix)]
+struct NonblockingStdin {
+    flags: libc::c_int,
+}
+
+#[cfg(unix)]
+impl NonblockingStdin {
+    fn new() -> io::Result<NonblockingStdin> {
+        let flags = unsafe { libc::fcntl(0, libc::F_GETFL) };
+        if flags == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::fcntl(0, libc::F_SETFL, flags | libc::O_NONBLOCK) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(NonblockingStdin { flags })
+    }
+}
+
+#[cfg(unix)]
+impl Read for NonblockingStdin {
+    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { libc::read(0, bytes.as_mut_ptr() as *mut _, bytes.len()) };
+        if n == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for NonblockingStdin {
+    fn drop(&mut self) {
+        unsafe { libc::fcntl(0, libc::F_SETFL, self.flags) };
+    }
+}
+
+// stdin read by a thread of its own, for when it can't be polled. An empty chunk marks
+// the end of the input.
+struct ThreadedStdin {
+    // shared with the registration's callback, which owns the rest.
+    registration: Rc<Registration>,
+    set_readiness: SetReadiness,
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    // how much of `chunk` has been read.
+    pos: usize,
+}
+
+impl ThreadedStdin {
+    fn new() -> io::Result<ThreadedStdin> {
+        let (registration, set_readiness) = Registration::new2();
+        let (sender, receiver) = mpsc::channel();
+        let thread_readiness = set_readiness.clone();
+        // the thread is left blocked in a read once the input is no longer wanted.
+        thread::Builder::new()
+            .name("looper-stdin".to_string())
+            .spawn(move || {
+                let stdin = io::stdin();
+                let mut stdin = stdin.lock();
+                let mut bytes = vec![0; 4096];
+                loop {
+                    let chunk = match stdin.read(&mut bytes) {
+                        Ok(n) => bytes[..n].to_vec(),
+                        Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                        Err(e) => {
+                            error!("Failed to read stdin: {}", e);
+                            Vec::new()
+                        }
+                    };
+                    let ended = chunk.is_empty();
+                    if sender.send(chunk).is_err() {
+                        return;
+                    }
+                    if let Err(err) = thread_readiness.set_readiness(Ready::readable()) {
+                        error!("Failed to wake up the event loop for stdin: {}", err);
+                    }
+                    if ended {
+                        return;
+                    }
+                }
+            })?;
+        Ok(ThreadedStdin {
+            registration: Rc::new(registration),
+            set_readiness,
+            receiver,
+            chunk: Vec::new(),
+            pos: 0,
+        })
+    }
+}
+
+impl Read for ThreadedStdin {
+    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            // clear the readiness first, so chunks sent from now on wake the loop again.
+            self.set_readiness.set_readiness(Ready::empty())?;
+            self.chunk = match self.receiver.try_recv() {
+                Ok(chunk) => chunk,
+                Err(TryRecvError::Empty) => return Err(ErrorKind::WouldBlock.into()),
+                Err(TryRecvError::Disconnected) => Vec::new(),
+            };
+            self.pos = 0;
+        }
+        let n = bytes.len().min(self.chunk.len() - self.pos);
+        bytes[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+pub fn register<F, T>(core: &mut Core, object_id: ObjectId, mut f: F) -> io::Result<()>
+where
+    F: 'static + FnMut(&mut T, Option<&[u8]>, &mut Core),
+    T: Any,
+{
+    let input = Input::new()?;
+    let source = input.source();
+    // the callback needs its own token, which is only known once it is registered.
+    let token = Rc::new(Cell::new(None));
+    let own_token = token.clone();
+    let mut input = Some(input);
+    let mut bytes = vec![0; 4096];
+    let read = move |object: &mut T, core: &mut Core| loop {
+        let stdin = match &mut input {
+            Some(stdin) => stdin,
+            None => return,
+        };
+        match stdin.read(&mut bytes) {
+            Ok(n) if n > 0 => f(object, Some(&bytes[..n]), core),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+            result => {
+                if let Err(e) = result {
+                    error!("Failed to read stdin: {}", e);
+                }
+                // nothing more will come, so the registration shouldn't keep the loop
+                // running.
+                if let Some(token) = own_token.get() {
+                    if let Err(err) = core.deregister(&stdin.source(), token) {
+                        error!("Failed to deregister stdin: {}", err);
+                    }
+                }
+                input = None;
+                f(object, None, core);
+                return;
+            }
+        }
+    };
+    token.set(Some(core.register_reader(&source, object_id, read)?));
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use crate::source::Source;
+use crate::{Core, ObjectId, TimerId};
+use log::error;
+use mio::{Ready, Token};
+use std::any::Any;
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+/// How long a throttled stream waits for its budget to refill once it ran out.
+const REFILL_DELAY: Duration = Duration::from_millis(100);
+
+// a token bucket holding up to a second worth of bytes.
+struct Bucket {
+    rate: u64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Bucket {
+        Bucket {
+            rate,
+            tokens: rate as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn available(&mut self) -> usize {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.refilled = now;
+        self.tokens as usize
+    }
+}
+
+/// Limits how many bytes per second are read from and written to a stream.
+///
+/// The stream is registered with the core first, and then kept throttled in the object
+/// it was registered for. Reads and writes through the throttled stream fail with
+/// `WouldBlock` once the budget for the current second is used up. Interest in that
+/// direction is then switched off, and a timer switches it back on once the budget
+/// has been refilled, so the registration's callbacks are called again as if the
+/// stream had just become ready. This keeps e.g. a chatty child or a flooding client
+/// from hogging the loop.
+pub struct Throttled<S> {
+    stream: S,
+    token: Token,
+    read_bucket: Option<Bucket>,
+    write_bucket: Option<Bucket>,
+    // the interest switched off because a bucket ran empty.
+    suspended: Ready,
+    refill_timer: Option<TimerId>,
+    set_refill_timer: Box<dyn Fn(&mut Core) -> TimerId>,
+}
+
+impl<S: 'static + Source> Throttled<S> {
+    /// Wraps a stream registered with the given token for the object with the given id.
+    ///
+    /// `access` returns the throttled stream from the object, which it must be added
+    /// to. Neither direction is limited until `limit_reads` or `limit_writes` is called.
+    pub fn new<T, A>(stream: S, token: Token, object_id: ObjectId, access: A) -> Throttled<S>
+    where
+        T: Any,
+        A: 'static + Copy + Fn(&mut T) -> &mut Throttled<S>,
+    {
+        let set_refill_timer = move |core: &mut Core| {
+            core.set_timeout(REFILL_DELAY, object_id, move |object: &mut T, core| {
+                let throttled = access(object);
+                throttled.refill_timer = None;
+                throttled.resume(core);
+            })
+        };
+        Throttled {
+            stream,
+            token,
+            read_bucket: None,
+            write_bucket: None,
+            suspended: Ready::empty(),
+            refill_timer: None,
+            set_refill_timer: Box::new(set_refill_timer),
+        }
+    }
+
+    /// Limits reads to the given number of bytes per second.
+    pub fn limit_reads(&mut self, bytes_per_second: u64) {
+        self.read_bucket = Some(Bucket::new(bytes_per_second));
+    }
+
+    /// Limits writes to the given number of bytes per second.
+    pub fn limit_writes(&mut self, bytes_per_second: u64) {
+        self.write_bucket = Some(Bucket::new(bytes_per_second));
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Returns the stream, with the interest that was switched off for throttling
+    /// still off.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Reads from the stream, as much as the budget for reading allows.
+    pub fn read(&mut self, bytes: &mut [u8], core: &mut Core) -> io::Result<usize>
+    where
+        S: Read,
+    {
+        let allowed = match &mut self.read_bucket {
+            Some(bucket) => bucket.available(),
+            None => bytes.len(),
+        };
+        if allowed == 0 && !bytes.is_empty() {
+            self.suspend(Ready::readable(), core)?;
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        let len = bytes.len().min(allowed);
+        let n = self.stream.read(&mut bytes[..len])?;
+        if let Some(bucket) = &mut self.read_bucket {
+            bucket.tokens -= n as f64;
+        }
+        Ok(n)
+    }
+
+    /// Writes to the stream, as much as the budget for writing allows.
+    pub fn write(&mut self, bytes: &[u8], core: &mut Core) -> io::Result<usize>
+    where
+        S: Write,
+    {
+        let allowed = match &mut self.write_bucket {
+            Some(bucket) => bucket.available(),
+            None => bytes.len(),
+        };
+        if allowed == 0 && !bytes.is_empty() {
+            self.suspend(Ready::writable(), core)?;
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        let len = bytes.len().min(allowed);
+        let n = self.stream.write(&bytes[..len])?;
+        if let Some(bucket) = &mut self.write_bucket {
+            bucket.tokens -= n as f64;
+        }
+        Ok(n)
+    }
+
+    fn suspend(&mut self, ready: Ready, core: &mut Core) -> io::Result<()> {
+        if !self.suspended.contains(ready) {
+            if ready.is_readable() {
+                core.set_read_interest(&self.stream, self.token, false)?;
+            } else {
+                core.set_write_interest(&self.stream, self.token, false)?;
+            }
+            self.suspended.insert(ready);
+        }
+        if self.refill_timer.is_none() {
+            self.refill_timer = Some((self.set_refill_timer)(core));
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self, core: &mut Core) {
+        // switching interest back on reports the stream right away if it is ready.
+        let mut result = Ok(());
+        if self.suspended.is_readable() {
+            result = core.set_read_interest(&self.stream, self.token, true);
+        }
+        if self.suspended.is_writable() {
+            result = result.and(core.set_write_interest(&self.stream, self.token, true));
+        }
+        self.suspended = Ready::empty();
+        if let Err(err) = result {
+            error!("Failed to resume a throttled stream: {}", err);
+        }
+    }
+}
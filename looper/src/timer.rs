@@ -0,0 +1,324 @@
+use crate::{Call, ObjectId};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Identifies a timer set on a `Core`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TimerId(u64);
+
+pub(crate) struct Timer {
+    pub object_id: ObjectId,
+    pub callback: Box<dyn Call>,
+    deadline: Instant,
+    interval: Option<Duration>,
+}
+
+// a timer taken out of the map while its callback runs. Canceling or resetting it in
+// the meantime is recorded here, for when a repeating timer is put back.
+struct Firing {
+    id: TimerId,
+    repeating: bool,
+    canceled: bool,
+    next_deadline: Option<Instant>,
+}
+
+// Timers are kept in a map, with their deadlines in a heap for finding the next one
+// to expire. Entries in the heap are never removed when a timer is canceled, instead
+// they are skipped when their timer no longer exists or has a different deadline.
+#[derive(Default)]
+pub(crate) struct Timers {
+    timers: HashMap<TimerId, Timer>,
+    deadlines: BinaryHeap<Reverse<(Instant, TimerId)>>,
+    next_id: u64,
+    firing: Option<Firing>,
+}
+
+impl Timers {
+    /// Adds a timer firing after `delay`, and then every `interval` if given.
+    pub fn add(
+        &mut self,
+        delay: Duration,
+        interval: Option<Duration>,
+        object_id: ObjectId,
+        callback: Box<dyn Call>,
+    ) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        let timer = Timer {
+            object_id,
+            callback,
+            deadline: Instant::now() + delay,
+            interval,
+        };
+        self.insert(id, timer);
+        id
+    }
+
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        match &mut self.firing {
+            Some(firing) if firing.id == id => {
+                let canceled = firing.repeating && !firing.canceled;
+                firing.canceled = true;
+                canceled
+            }
+            _ => self.timers.remove(&id).is_some(),
+        }
+    }
+
+    /// Moves the deadline of a timer to `delay` from now, leaving the old heap entry
+    /// to be skipped as stale.
+    pub fn reset(&mut self, id: TimerId, delay: Duration) -> bool {
+        if let Some(firing) = &mut self.firing {
+            if firing.id == id {
+                firing.next_deadline = Some(Instant::now() + delay);
+                return firing.repeating && !firing.canceled;
+            }
+        }
+        match self.timers.get_mut(&id) {
+            Some(timer) => {
+                timer.deadline = Instant::now() + delay;
+                self.deadlines.push(Reverse((timer.deadline, id)));
+                self.compact();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Returns how long until the next timer expires.
+    pub fn next_timeout(&mut self) -> Option<Duration> {
+        self.skip_stale();
+        self.deadlines
+            .peek()
+            .map(|Reverse((deadline, _))| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Removes and returns the next timer that expired at or before `now`.
+    ///
+    /// The timer must be handed back to `finish` after calling it.
+    pub fn pop_expired(&mut self, now: Instant) -> Option<(TimerId, Timer)> {
+        self.skip_stale();
+        match self.deadlines.peek() {
+            Some(Reverse((deadline, _))) if *deadline <= now => {}
+            _ => return None,
+        }
+        let Reverse((_, id)) = self.deadlines.pop().unwrap();
+        let timer = self.timers.remove(&id)?;
+        self.firing = Some(Firing {
+            id,
+            repeating: timer.interval.is_some(),
+            canceled: false,
+            next_deadline: None,
+        });
+        Some((id, timer))
+    }
+
+    /// Puts a repeating timer back after it has been called, unless it was canceled
+    /// or its object is gone.
+    pub fn finish(&mut self, id: TimerId, mut timer: Timer, object_exists: bool) {
+        let firing = match self.firing.take() {
+            Some(firing) => firing,
+            None => return,
+        };
+        let interval = match timer.interval {
+            Some(interval) if object_exists && !firing.canceled => interval,
+            _ => return,
+        };
+        // deadlines follow the original schedule, unless the loop has fallen behind by
+        // more than an interval, in which case missed calls are skipped.
+        let now = Instant::now();
+        timer.deadline = match firing.next_deadline {
+            Some(deadline) => deadline,
+            None if timer.deadline + interval > now => timer.deadline + interval,
+            None => now + interval,
+        };
+        self.insert(id, timer);
+    }
+
+    fn insert(&mut self, id: TimerId, timer: Timer) {
+        self.deadlines.push(Reverse((timer.deadline, id)));
+        self.timers.insert(id, timer);
+    }
+
+    // stale entries are normally dropped as they reach the top of the heap, but timers
+    // that keep being reset would let them pile up for as long as the delay.
+    fn compact(&mut self) {
+        if self.deadlines.len() > 2 * self.timers.len() + 64 {
+            let timers = &self.timers;
+            self.deadlines.retain(|Reverse((deadline, id))| {
+                timers
+                    .get(id)
+                    .is_some_and(|timer| timer.deadline == *deadline)
+            });
+        }
+    }
+
+    fn skip_stale(&mut self) {
+        while let Some(Reverse((deadline, id))) = self.deadlines.peek() {
+            match self.timers.get(id) {
+                Some(timer) if timer.deadline == *deadline => return,
+                _ => {
+                    self.deadlines.pop();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Core;
+    use std::any::Any;
+    use std::thread;
+
+    struct Nop;
+
+    impl Call for Nop {
+        fn make_call(&mut self, _: &mut dyn Any, _: &mut Core) {}
+    }
+
+    fn add(timers: &mut Timers, delay_ms: u64, interval_ms: Option<u64>) -> TimerId {
+        let interval = interval_ms.map(Duration::from_millis);
+        let delay = Duration::from_millis(delay_ms);
+        timers.add(delay, interval, ObjectId::from(0), Box::new(Nop))
+    }
+
+    // pops all timers expired by `now`, finishing each as if its object exists.
+    fn expire(timers: &mut Timers, now: Instant) -> Vec<TimerId> {
+        let mut expired = Vec::new();
+        while let Some((id, timer)) = timers.pop_expired(now) {
+            expired.push(id);
+            timers.finish(id, timer, true);
+        }
+        expired
+    }
+
+    fn later() -> Instant {
+        Instant::now() + Duration::from_secs(60)
+    }
+
+    #[test]
+    fn expire_in_deadline_order() {
+        let mut timers = Timers::default();
+        let c = add(&mut timers, 30, None);
+        let a = add(&mut timers, 10, None);
+        let b = add(&mut timers, 20, None);
+        let d = add(&mut timers, 3_600_000, None);
+        assert_eq!(expire(&mut timers, later()), vec![a, b, c]);
+        assert_eq!(timers.len(), 1);
+        let timeout = timers.next_timeout().unwrap();
+        assert!(timeout > Duration::from_secs(3500), "{:?}", timeout);
+        assert!(timers.cancel(d));
+        assert_eq!(timers.next_timeout(), None);
+    }
+
+    #[test]
+    fn not_expired_yet() {
+        let mut timers = Timers::default();
+        add(&mut timers, 3_600_000, None);
+        assert!(timers.pop_expired(Instant::now()).is_none());
+        assert_eq!(timers.len(), 1);
+    }
+
+    #[test]
+    fn cancel() {
+        let mut timers = Timers::default();
+        let a = add(&mut timers, 10, None);
+        let b = add(&mut timers, 20, None);
+        assert!(timers.cancel(a));
+        assert!(!timers.cancel(a));
+        assert_eq!(expire(&mut timers, later()), vec![b]);
+        assert_eq!(timers.len(), 0);
+        assert_eq!(timers.next_timeout(), None);
+    }
+
+    #[test]
+    fn reset_skips_the_old_deadline() {
+        let mut timers = Timers::default();
+        let a = add(&mut timers, 10, None);
+        let b = add(&mut timers, 20, None);
+        assert!(timers.reset(a, Duration::from_secs(120)));
+        assert_eq!(expire(&mut timers, later()), vec![b]);
+        assert_eq!(timers.len(), 1);
+        let far = Instant::now() + Duration::from_secs(180);
+        assert_eq!(expire(&mut timers, far), vec![a]);
+        assert!(!timers.reset(a, Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn stale_entries_are_compacted() {
+        let mut timers = Timers::default();
+        let a = add(&mut timers, 10, None);
+        for i in 0..10_000 {
+            assert!(timers.reset(a, Duration::from_millis(i)));
+        }
+        assert!(timers.deadlines.len() <= 2 * timers.len() + 65);
+        assert_eq!(expire(&mut timers, later()), vec![a]);
+    }
+
+    #[test]
+    fn repeating() {
+        let mut timers = Timers::default();
+        let a = add(&mut timers, 0, Some(3_600_000));
+        let (id, timer) = timers.pop_expired(later()).unwrap();
+        let deadline = timer.deadline;
+        timers.finish(id, timer, true);
+        // the next deadline follows the schedule.
+        assert_eq!(
+            timers.timers[&a].deadline,
+            deadline + Duration::from_secs(3600)
+        );
+        // and the timer is dropped along with its object.
+        let far = Instant::now() + Duration::from_secs(7200);
+        let (id, timer) = timers.pop_expired(far).unwrap();
+        timers.finish(id, timer, false);
+        assert_eq!(timers.len(), 0);
+    }
+
+    #[test]
+    fn repeating_falls_behind() {
+        let mut timers = Timers::default();
+        add(&mut timers, 0, Some(20));
+        thread::sleep(Duration::from_millis(50));
+        let (id, timer) = timers.pop_expired(Instant::now()).unwrap();
+        timers.finish(id, timer, true);
+        // the missed calls are skipped rather than made right away.
+        assert!(timers.next_timeout().unwrap() > Duration::from_millis(5));
+    }
+
+    #[test]
+    fn cancel_while_firing() {
+        let mut timers = Timers::default();
+        let a = add(&mut timers, 0, Some(10));
+        let (id, timer) = timers.pop_expired(later()).unwrap();
+        assert!(timers.cancel(a));
+        assert!(!timers.cancel(a));
+        timers.finish(id, timer, true);
+        assert_eq!(timers.len(), 0);
+
+        // a timer that doesn't repeat is done once it fires.
+        let b = add(&mut timers, 0, None);
+        let (id, timer) = timers.pop_expired(later()).unwrap();
+        assert!(!timers.cancel(b));
+        assert!(!timers.reset(b, Duration::from_secs(1)));
+        timers.finish(id, timer, true);
+        assert_eq!(timers.len(), 0);
+    }
+
+    #[test]
+    fn reset_while_firing() {
+        let mut timers = Timers::default();
+        let a = add(&mut timers, 0, Some(10));
+        let (id, timer) = timers.pop_expired(later()).unwrap();
+        assert!(timers.reset(a, Duration::from_secs(3600)));
+        timers.finish(id, timer, true);
+        let timeout = timers.next_timeout().unwrap();
+        assert!(timeout > Duration::from_secs(3500), "{:?}", timeout);
+    }
+}
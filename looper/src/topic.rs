@@ -0,0 +1,110 @@
+use crate::{Core, ObjectId};
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Deliver<T> = Rc<dyn Fn(Rc<T>, &mut Core)>;
+
+struct Subscriber<T> {
+    object_id: ObjectId,
+    // as ids are reused once an object has been removed.
+    generation: u32,
+    deliver: Deliver<T>,
+}
+
+/// Passes values published to it on to the objects subscribed to it.
+///
+/// A topic lives in the core like any other object, e.g. added with
+/// `core.add(Topic::<Event>::new())`, so anything with access to the core can publish
+/// to it by id. Every subscriber gets a reference to the value in a callback posted for
+/// it, so subscribers are called on the next dispatch, including one whose callback
+/// is publishing. Subscribers that have been removed are forgotten.
+pub struct Topic<T> {
+    subscribers: Vec<Subscriber<T>>,
+}
+
+impl<T> Default for Topic<T> {
+    fn default() -> Self {
+        Topic {
+            subscribers: Vec::new(),
+        }
+    }
+}
+
+impl<T: Any> Topic<T> {
+    pub fn new() -> Topic<T> {
+        Topic::default()
+    }
+
+    /// Makes `f` be called on the object with the given id with every value published
+    /// to the topic with id `topic_id`.
+    ///
+    /// The object may also be one that is about to be added with the id from
+    /// `Core::next_id`. Replaces an earlier subscription of the same object. Returns
+    /// false if there is no such topic.
+    pub fn subscribe<F, S>(topic_id: ObjectId, object_id: ObjectId, f: F, core: &mut Core) -> bool
+    where
+        F: 'static + FnMut(&mut S, &T, &mut Core),
+        S: Any,
+    {
+        let generation = core.expected_generation(object_id);
+        let f = Rc::new(RefCell::new(f));
+        let deliver: Deliver<T> = Rc::new(move |value, core| {
+            let f = f.clone();
+            core.post(object_id, move |object: &mut S, core| {
+                // posted callbacks run one at a time, so `f` is never borrowed already.
+                (f.borrow_mut())(object, &value, core)
+            });
+        });
+        let topic = match core.get_mut::<Topic<T>>(topic_id) {
+            Some(topic) => topic,
+            None => return false,
+        };
+        topic.subscribers.retain(|s| s.object_id != object_id);
+        topic.subscribers.push(Subscriber {
+            object_id,
+            generation,
+            deliver,
+        });
+        true
+    }
+
+    /// Ends the subscription of the object with the given id, if any.
+    ///
+    /// Returns whether there was one. Values published already are still delivered.
+    pub fn unsubscribe(topic_id: ObjectId, object_id: ObjectId, core: &mut Core) -> bool {
+        let topic = match core.get_mut::<Topic<T>>(topic_id) {
+            Some(topic) => topic,
+            None => return false,
+        };
+        let count = topic.subscribers.len();
+        topic.subscribers.retain(|s| s.object_id != object_id);
+        topic.subscribers.len() != count
+    }
+
+    /// Passes `value` on to all subscribers of the topic with id `topic_id`.
+    ///
+    /// Returns false if there is no such topic.
+    pub fn publish(topic_id: ObjectId, value: T, core: &mut Core) -> bool {
+        let topic = match core.get_mut::<Topic<T>>(topic_id) {
+            Some(topic) => topic,
+            None => return false,
+        };
+        let mut subscribers = std::mem::take(&mut topic.subscribers);
+        subscribers.retain(|s| core.expected_generation(s.object_id) == s.generation);
+        let value = Rc::new(value);
+        for subscriber in &subscribers {
+            (subscriber.deliver)(value.clone(), core);
+        }
+        // delivering only posts callbacks, so the topic can't have changed meanwhile.
+        if let Some(topic) = core.get_mut::<Topic<T>>(topic_id) {
+            topic.subscribers = subscribers;
+        }
+        true
+    }
+
+    /// Returns the number of subscribers, including ones that may have been removed.
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.len()
+    }
+}
@@ -0,0 +1,166 @@
+use crate::Core;
+use log::error;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[cfg(unix)]
+use mio::unix::EventedFd;
+#[cfg(unix)]
+use std::fs::File;
+#[cfg(unix)]
+use std::io::Read;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+#[cfg(windows)]
+use mio::{Ready, Registration, SetReadiness};
+
+/// A handle for waking up a core that is waiting for events.
+///
+/// Wakers are obtained from `Core::waker`, and can be cloned and sent to any thread.
+/// On unix waking up only writes to a pipe, which is safe to do from a signal
+/// handler, unlike anything that allocates or takes a lock such as `Remote::run`.
+#[derive(Clone)]
+pub struct Waker {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    #[cfg(unix)]
+    writer: File,
+    #[cfg(windows)]
+    set_readiness: SetReadiness,
+    exit: AtomicBool,
+}
+
+impl Waker {
+    /// Makes the core's current or next wait for events return right away.
+    ///
+    /// The loop then goes on as usual, so this is mostly useful after changing
+    /// something the loop checks between polls.
+    pub fn wake(&self) -> io::Result<()> {
+        self.inner.wake()
+    }
+
+    /// Wakes the core up and makes it exit, like `Core::exit`.
+    pub fn exit(&self) -> io::Result<()> {
+        self.inner.exit.store(true, Ordering::SeqCst);
+        self.inner.wake()
+    }
+}
+
+impl Inner {
+    #[cfg(unix)]
+    fn wake(&self) -> io::Result<()> {
+        let byte = 1u8;
+        let n = unsafe { libc::write(self.writer.as_raw_fd(), &byte as *const u8 as *const _, 1) };
+        if n == -1 {
+            let err = io::Error::last_os_error();
+            // a full pipe will wake the core up just as well.
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn wake(&self) -> io::Result<()> {
+        self.set_readiness.set_readiness(Ready::readable())
+    }
+}
+
+// the receiving end of the wakers, living in the core as an object of its own.
+struct WakeReceiver {
+    #[cfg(unix)]
+    reader: File,
+    #[cfg(windows)]
+    _registration: Registration,
+    inner: Arc<Inner>,
+}
+
+impl WakeReceiver {
+    fn woken(&mut self, core: &mut Core) {
+        self.drain();
+        if self.inner.exit.swap(false, Ordering::SeqCst) {
+            core.exit();
+        }
+    }
+
+    #[cfg(unix)]
+    fn drain(&mut self) {
+        let mut bytes = [0; 64];
+        loop {
+            match self.reader.read(&mut bytes) {
+                Ok(0) => return,
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    error!("Failed to read wakeups: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    fn drain(&mut self) {
+        // clear the readiness first, so wakeups from now on wake the loop again.
+        if let Err(err) = self.inner.set_readiness.set_readiness(Ready::empty()) {
+            error!("Failed to reset readiness for wakeups: {}", err);
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn new_waker(core: &mut Core) -> io::Result<Waker> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let reader = unsafe { File::from_raw_fd(fds[0]) };
+    let writer = unsafe { File::from_raw_fd(fds[1]) };
+    for file in [&reader, &writer] {
+        let fd = file.as_raw_fd();
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    let inner = Arc::new(Inner {
+        writer,
+        exit: AtomicBool::new(false),
+    });
+    core.register_reader(
+        &EventedFd(&reader.as_raw_fd()),
+        core.next_id(),
+        WakeReceiver::woken,
+    )?;
+    core.add_internal(WakeReceiver {
+        reader,
+        inner: inner.clone(),
+    });
+    Ok(Waker { inner })
+}
+
+#[cfg(windows)]
+pub(crate) fn new_waker(core: &mut Core) -> io::Result<Waker> {
+    let (registration, set_readiness) = Registration::new2();
+    let inner = Arc::new(Inner {
+        set_readiness,
+        exit: AtomicBool::new(false),
+    });
+    core.register_reader(&registration, core.next_id(), WakeReceiver::woken)?;
+    core.add_internal(WakeReceiver {
+        _registration: registration,
+        inner: inner.clone(),
+    });
+    Ok(Waker { inner })
+}
@@ -0,0 +1,271 @@
+use crate::source::Source;
+use crate::Core;
+use mio::Token;
+use std::io::{self, ErrorKind, Write};
+
+/// Queues bytes for a non-blocking stream, writing them as the stream takes them.
+///
+/// The buffer belongs to a registration made with a write callback, which should call
+/// `flush`. Interest in writability is switched off while nothing is queued and back
+/// on as soon as a write would block, so the object isn't woken up for nothing. Once
+/// more than the high watermark is queued, `write` and `flush` return false to tell the
+/// owner to stop producing data until the stream has caught up.
+#[derive(Debug)]
+pub struct WriteBuffer {
+    data: Vec<u8>,
+    // how much of `data` has been written already.
+    written: usize,
+    high_watermark: usize,
+    token: Token,
+    write_interest: bool,
+}
+
+impl WriteBuffer {
+    /// Creates an empty buffer for the registration with the given token.
+    ///
+    /// The registration is expected to still have its initial interest in writability.
+    pub fn new(token: Token, high_watermark: usize) -> WriteBuffer {
+        WriteBuffer {
+            data: Vec::new(),
+            written: 0,
+            high_watermark,
+            token,
+            write_interest: true,
+        }
+    }
+
+    /// Queues `bytes` and writes as much as possible to `stream`.
+    ///
+    /// Returns false if more than the high watermark is left queued. Fails on any
+    /// error but the stream blocking, after which the stream should be dropped.
+    pub fn write<W>(&mut self, stream: &mut W, bytes: &[u8], core: &mut Core) -> io::Result<bool>
+    where
+        W: Write + Source,
+    {
+        self.data.extend_from_slice(bytes);
+        self.flush(stream, core)
+    }
+
+    /// Writes as much of the queue as possible to `stream`.
+    ///
+    /// Meant to be called from the registration's write callback. Returns the same as
+    /// `write`.
+    pub fn flush<W>(&mut self, stream: &mut W, core: &mut Core) -> io::Result<bool>
+    where
+        W: Write + Source,
+    {
+        while self.written < self.data.len() {
+            match stream.write(&self.data[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        if self.written == self.data.len() {
+            self.data.clear();
+            self.written = 0;
+        } else if self.written > self.data.len() / 2 {
+            // drop what has been written once it makes up most of the queue.
+            self.data.drain(..self.written);
+            self.written = 0;
+        }
+        let pending = !self.is_empty();
+        if pending != self.write_interest {
+            core.set_write_interest(stream, self.token, pending)?;
+            self.write_interest = pending;
+        }
+        Ok(!self.is_full())
+    }
+
+    /// Returns the number of bytes waiting to be written.
+    pub fn len(&self) -> usize {
+        self.data.len() - self.written
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if more than the high watermark is queued.
+    pub fn is_full(&self) -> bool {
+        self.len() > self.high_watermark
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::source::Registry;
+    use crate::Trigger;
+    use mio::unix::EventedFd;
+    use mio::Ready;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    // a stream taking `budget` more bytes before it blocks, registered through a real
+    // socket so interest can be switched.
+    struct Stream {
+        socket: UnixStream,
+        budget: usize,
+        // errors to return before taking anything.
+        errors: Vec<ErrorKind>,
+        out: Vec<u8>,
+    }
+
+    impl Write for Stream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if let Some(kind) = self.errors.pop() {
+                return Err(kind.into());
+            }
+            if self.budget == 0 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(self.budget);
+            self.budget -= n;
+            self.out.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Source for Stream {
+        fn register(
+            &self,
+            registry: &Registry,
+            token: Token,
+            interest: Ready,
+            trigger: Trigger,
+        ) -> io::Result<()> {
+            Source::register(
+                &EventedFd(&self.socket.as_raw_fd()),
+                registry,
+                token,
+                interest,
+                trigger,
+            )
+        }
+
+        fn reregister(
+            &self,
+            registry: &Registry,
+            token: Token,
+            interest: Ready,
+            trigger: Trigger,
+        ) -> io::Result<()> {
+            Source::reregister(
+                &EventedFd(&self.socket.as_raw_fd()),
+                registry,
+                token,
+                interest,
+                trigger,
+            )
+        }
+
+        fn deregister(&self, registry: &Registry) -> io::Result<()> {
+            Source::deregister(&EventedFd(&self.socket.as_raw_fd()), registry)
+        }
+    }
+
+    fn setup(high_watermark: usize) -> (Core, Stream, WriteBuffer, UnixStream) {
+        let mut core = Core::new();
+        let (socket, peer) = UnixStream::pair().unwrap();
+        let stream = Stream {
+            socket,
+            budget: 0,
+            errors: Vec::new(),
+            out: Vec::new(),
+        };
+        let id = core.next_id();
+        let token = core
+            .register_writer(&stream, id, |_: &mut (), _| {})
+            .unwrap();
+        core.add(());
+        (core, stream, WriteBuffer::new(token, high_watermark), peer)
+    }
+
+    #[test]
+    fn writes_through() {
+        let (mut core, mut stream, mut buffer, _peer) = setup(10);
+        stream.budget = 100;
+        assert!(buffer.write(&mut stream, b"hello", &mut core).unwrap());
+        assert!(buffer.is_empty());
+        assert_eq!(stream.out, b"hello");
+        // nothing is queued, so there is no need to hear about writability.
+        assert!(!buffer.write_interest);
+    }
+
+    #[test]
+    fn high_watermark() {
+        let (mut core, mut stream, mut buffer, _peer) = setup(10);
+        assert!(buffer.write(&mut stream, b"01234567", &mut core).unwrap());
+        assert!(buffer.write(&mut stream, b"89", &mut core).unwrap());
+        assert_eq!(buffer.len(), 10);
+        assert!(!buffer.is_full());
+        assert!(buffer.write_interest);
+        // one byte over the watermark tells the owner to stop.
+        assert!(!buffer.write(&mut stream, b"a", &mut core).unwrap());
+        assert!(buffer.is_full());
+        stream.budget = 1;
+        assert!(buffer.flush(&mut stream, &mut core).unwrap());
+        assert_eq!(buffer.len(), 10);
+        stream.budget = 100;
+        assert!(buffer.flush(&mut stream, &mut core).unwrap());
+        assert!(buffer.is_empty());
+        assert!(!buffer.write_interest);
+        assert_eq!(stream.out, b"0123456789a");
+    }
+
+    #[test]
+    fn interest_follows_the_queue() {
+        let (mut core, mut stream, mut buffer, _peer) = setup(10);
+        stream.budget = 3;
+        assert!(buffer.write(&mut stream, b"abc", &mut core).unwrap());
+        assert!(!buffer.write_interest);
+        // blocking switches interest back on.
+        assert!(buffer.write(&mut stream, b"def", &mut core).unwrap());
+        assert!(buffer.write_interest);
+        assert_eq!(buffer.len(), 3);
+        stream.budget = 3;
+        assert!(buffer.flush(&mut stream, &mut core).unwrap());
+        assert!(!buffer.write_interest);
+        assert_eq!(stream.out, b"abcdef");
+    }
+
+    #[test]
+    fn drops_what_was_written() {
+        let (mut core, mut stream, mut buffer, _peer) = setup(1000);
+        assert!(buffer.write(&mut stream, &[1; 100], &mut core).unwrap());
+        stream.budget = 60;
+        assert!(buffer.flush(&mut stream, &mut core).unwrap());
+        // most of the queue was written, so it is dropped from the buffer.
+        assert_eq!(buffer.data.len(), 40);
+        assert_eq!(buffer.written, 0);
+        stream.budget = 10;
+        assert!(buffer.flush(&mut stream, &mut core).unwrap());
+        // less than half was, so it is kept for now.
+        assert_eq!(buffer.data.len(), 40);
+        assert_eq!(buffer.len(), 30);
+        stream.budget = 100;
+        assert!(buffer.write(&mut stream, &[2; 5], &mut core).unwrap());
+        assert!(buffer.is_empty());
+        assert_eq!(stream.out.len(), 105);
+        assert_eq!(&stream.out[100..], &[2; 5]);
+    }
+
+    #[test]
+    fn errors() {
+        let (mut core, mut stream, mut buffer, _peer) = setup(10);
+        stream.budget = 100;
+        stream.errors = vec![ErrorKind::Interrupted];
+        assert!(buffer.write(&mut stream, b"abc", &mut core).unwrap());
+        assert_eq!(stream.out, b"abc");
+        stream.errors = vec![ErrorKind::BrokenPipe];
+        let err = buffer.write(&mut stream, b"def", &mut core).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,92 @@
+#![cfg(unix)]
+
+use looper::{register, Core};
+use mio::unix::EventedFd;
+use std::cell::Cell;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::time::Duration;
+
+struct Job {
+    stream: UnixStream,
+    read: Rc<Cell<bool>>,
+}
+
+impl Job {
+    fn new(stream: UnixStream) -> (Job, Rc<Cell<bool>>) {
+        let read = Rc::new(Cell::new(false));
+        let job = Job {
+            stream,
+            read: read.clone(),
+        };
+        (job, read)
+    }
+
+    fn read(&mut self, core: &mut Core) {
+        let mut buf = [0; 16];
+        while let Ok(n) = self.stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            self.read.set(true);
+        }
+        core.shutdown(Duration::from_millis(0));
+    }
+}
+
+fn pair() -> (UnixStream, UnixStream) {
+    let (a, b) = UnixStream::pair().unwrap();
+    a.set_nonblocking(true).unwrap();
+    (a, b)
+}
+
+// runs the core until the job's reader shuts it down, or until the timeout.
+fn run(core: &mut Core, timeout: Duration) {
+    let id = core.add(());
+    core.set_timeout(timeout, id, |_: &mut (), core| {
+        core.shutdown(Duration::from_millis(0))
+    });
+    core.run().unwrap();
+}
+
+#[test]
+fn registers_and_adds() {
+    let mut core = Core::new();
+    let (stream, mut peer) = pair();
+    let fd = stream.as_raw_fd();
+    let (job, read) = Job::new(stream);
+    let id = register!(&mut core, job, {
+        read(&EventedFd(&fd)) => Job::read,
+    })
+    .unwrap();
+    assert!(core.get::<Job>(id).is_some());
+    peer.write_all(b"x").unwrap();
+    run(&mut core, Duration::from_secs(5));
+    assert!(read.get());
+}
+
+#[test]
+fn failed_registration_drops_earlier_ones() {
+    let mut core = Core::new();
+    let (stream, mut peer) = pair();
+    let fd = stream.as_raw_fd();
+    let (job, read) = Job::new(stream.try_clone().unwrap());
+    let id = core.next_id();
+    // the second registration of the same descriptor fails.
+    let result = register!(&mut core, job, {
+        read(&EventedFd(&fd)) => Job::read,
+        read(&EventedFd(&fd)) => Job::read,
+    });
+    assert!(result.is_err());
+    assert!(!core.contains(id));
+    assert_eq!(core.len(), 0);
+    // the next object gets the same id, and must not inherit the first registration.
+    let (job, next_read) = Job::new(stream);
+    assert_eq!(core.add(job), id);
+    peer.write_all(b"x").unwrap();
+    run(&mut core, Duration::from_millis(200));
+    assert!(!read.get());
+    assert!(!next_read.get());
+}
@@ -0,0 +1,15 @@
+use looper::{register, Core};
+
+struct Job;
+
+impl Job {
+    fn hangup(&mut self, _core: &mut Core) {}
+}
+
+fn main() {
+    let mut core = Core::new();
+    let stdin = std::io::stdin();
+    let _ = register!(&mut core, Job, {
+        hangup(&stdin) => Job::hangup,
+    });
+}
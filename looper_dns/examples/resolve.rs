@@ -0,0 +1,26 @@
+use looper::Core;
+use looper_dns::resolve;
+use std::env;
+use std::io;
+use std::net::IpAddr;
+
+struct Printer;
+
+fn main() {
+    let host = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "localhost".to_string());
+    let mut core = Core::new();
+    let printer_id = core.add(Printer);
+    let print = |_: &mut Printer, addresses: io::Result<Vec<IpAddr>>, _: &mut Core| match addresses
+    {
+        Ok(addresses) => {
+            for address in addresses {
+                println!("{}", address);
+            }
+        }
+        Err(err) => println!("Lookup failed: {}", err),
+    };
+    resolve(&host, printer_id, print, &mut core).expect("Failed to start the lookup.");
+    core.run().expect("Failed to run the event loop.");
+}
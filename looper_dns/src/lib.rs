@@ -0,0 +1,94 @@
+use log::{debug, error};
+use looper::{Core, ObjectId};
+use mio::{Ready, Registration};
+use std::any::Any;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+type Addresses = io::Result<Vec<IpAddr>>;
+
+type Deliver = Box<dyn FnOnce(Addresses, &mut Core)>;
+
+/// Looks up the addresses of `host` without blocking the event loop.
+///
+/// `f` is called on the object with the given id once the lookup is done, with the
+/// addresses found or the error it failed with. Lookups are done by the system's
+/// resolver, like with `ToSocketAddrs`, each on a thread of its own. A pending lookup
+/// counts as work for the event loop, so `run` doesn't return before it is done.
+pub fn resolve<F, T>(host: &str, object_id: ObjectId, f: F, core: &mut Core) -> io::Result<()>
+where
+    F: 'static + FnOnce(&mut T, Addresses, &mut Core),
+    T: Any,
+{
+    let (registration, set_readiness) = Registration::new2();
+    let (sender, receiver) = mpsc::channel();
+    let owned_host = host.to_string();
+    thread::Builder::new()
+        .name("looper-dns".to_string())
+        .spawn(move || {
+            let result = lookup(&owned_host);
+            // the lookup has been dropped if the core has, there is no one to tell.
+            if sender.send(result).is_ok() {
+                if let Err(err) = set_readiness.set_readiness(Ready::readable()) {
+                    error!(
+                        "Failed to wake up the event loop for {}: {}",
+                        owned_host, err
+                    );
+                }
+            }
+        })?;
+    let lookup_id = core.next_id();
+    core.register_reader(&registration, lookup_id, Lookup::read)?;
+    let deliver = move |addresses, core: &mut Core| {
+        core.post(object_id, move |object: &mut T, core| {
+            f(object, addresses, core)
+        });
+    };
+    core.add(Lookup {
+        object_id: lookup_id,
+        host: host.to_string(),
+        _registration: registration,
+        receiver,
+        deliver: Some(Box::new(deliver)),
+    });
+    Ok(())
+}
+
+fn lookup(host: &str) -> Addresses {
+    // the port doesn't matter, but `ToSocketAddrs` needs one.
+    let addresses: Vec<IpAddr> = (host, 0).to_socket_addrs()?.map(|a| a.ip()).collect();
+    if addresses.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            "The host has no addresses.",
+        ));
+    }
+    Ok(addresses)
+}
+
+// a lookup in progress, living in the core as an object of its own until it is done.
+struct Lookup {
+    object_id: ObjectId,
+    host: String,
+    // wakes the loop up once the result has been sent, as long as it is kept around.
+    _registration: Registration,
+    receiver: Receiver<Addresses>,
+    deliver: Option<Deliver>,
+}
+
+impl Lookup {
+    fn read(&mut self, core: &mut Core) {
+        let result = match self.receiver.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err(io::Error::other("The lookup thread died.")),
+        };
+        debug!("Looked up {}: {:?}", self.host, result);
+        core.remove(self.object_id);
+        if let Some(deliver) = self.deliver.take() {
+            deliver(result, core);
+        }
+    }
+}
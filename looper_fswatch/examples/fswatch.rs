@@ -0,0 +1,16 @@
+use looper::Core;
+use looper_fswatch::{FsEvent, Watcher};
+use std::env;
+
+struct Printer;
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or_else(|| ".".to_string());
+    let mut core = Core::new();
+    let printer_id = core.add(Printer);
+    let print = |_: &mut Printer, event: FsEvent, _: &mut Core| {
+        println!("{:?} {}", event.kind, event.path.display());
+    };
+    Watcher::watch(&path, printer_id, print, &mut core).expect("Failed to watch the path.");
+    core.run().expect("Failed to run the event loop.");
+}
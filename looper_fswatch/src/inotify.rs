@@ -0,0 +1,94 @@
+use crate::{FsEvent, FsEventKind};
+use log::{error, warn};
+use looper::{Core, ObjectId};
+use mio::unix::EventedFd;
+use std::any::Any;
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+
+const MASK: u32 = libc::IN_CREATE
+    | libc::IN_MODIFY
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF;
+
+pub fn watch<F, T>(path: &Path, object_id: ObjectId, mut f: F, core: &mut Core) -> io::Result<()>
+where
+    F: 'static + FnMut(&mut T, FsEvent, &mut Core),
+    T: Any,
+{
+    let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut inotify = unsafe { File::from_raw_fd(fd) };
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+    if unsafe { libc::inotify_add_watch(fd, c_path.as_ptr(), MASK) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let path = path.to_path_buf();
+    let mut buffer = vec![0; 16 * 1024];
+    // the callback owns the inotify instance, which is closed when the object is removed.
+    let read = move |object: &mut T, core: &mut Core| loop {
+        let n = match inotify.read(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+            Err(e) => {
+                error!("Failed to read changes to {}: {}", path.display(), e);
+                return;
+            }
+        };
+        for event in parse(&buffer[..n], &path) {
+            f(object, event, core);
+        }
+    };
+    core.register_reader(&EventedFd(&fd), object_id, read)?;
+    Ok(())
+}
+
+fn parse(mut bytes: &[u8], path: &Path) -> Vec<FsEvent> {
+    let header_len = mem::size_of::<libc::inotify_event>();
+    let mut events = Vec::new();
+    while bytes.len() >= header_len {
+        let header = unsafe { (bytes.as_ptr() as *const libc::inotify_event).read_unaligned() };
+        let name_len = header.len as usize;
+        let name = &bytes[header_len..header_len + name_len];
+        bytes = &bytes[header_len + name_len..];
+        if header.mask & libc::IN_Q_OVERFLOW != 0 {
+            warn!("Too many changes to {}, some were lost.", path.display());
+            continue;
+        }
+        let kind = if header.mask & libc::IN_CREATE != 0 {
+            FsEventKind::Created
+        } else if header.mask & libc::IN_MODIFY != 0 {
+            FsEventKind::Modified
+        } else if header.mask & (libc::IN_DELETE | libc::IN_DELETE_SELF) != 0 {
+            FsEventKind::Deleted
+        } else if header.mask & (libc::IN_MOVED_FROM | libc::IN_MOVE_SELF) != 0 {
+            FsEventKind::RenamedFrom
+        } else if header.mask & libc::IN_MOVED_TO != 0 {
+            FsEventKind::RenamedTo
+        } else {
+            // e.g. the watch going away along with the path.
+            continue;
+        };
+        // the name is padded with nul bytes.
+        let name_end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        let path = if name_end == 0 {
+            path.to_path_buf()
+        } else {
+            path.join(OsStr::from_bytes(&name[..name_end]))
+        };
+        events.push(FsEvent { kind, path });
+    }
+    events
+}
@@ -0,0 +1,247 @@
+use crate::{FsEvent, FsEventKind};
+use log::error;
+use looper::{Core, ObjectId};
+use mio::unix::EventedFd;
+use std::any::Any;
+use std::collections::HashMap;
+use std::ffi::{CString, OsString};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirEntryExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+const NOTES: u32 = libc::NOTE_WRITE | libc::NOTE_EXTEND | libc::NOTE_DELETE | libc::NOTE_RENAME;
+
+// opens files only to watch them, where the platform allows it.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const OPEN_FLAGS: libc::c_int = libc::O_EVTONLY;
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+const OPEN_FLAGS: libc::c_int = libc::O_RDONLY;
+
+pub fn watch<F, T>(path: &Path, object_id: ObjectId, mut f: F, core: &mut Core) -> io::Result<()>
+where
+    F: 'static + FnMut(&mut T, FsEvent, &mut Core),
+    T: Any,
+{
+    let fd = unsafe { libc::kqueue() };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let kqueue = unsafe { File::from_raw_fd(fd) };
+    let root = open(path, 0)?;
+    add_watch(fd, &root)?;
+    let is_dir = root.metadata()?.is_dir();
+    let mut watch = KqueueWatch {
+        kqueue,
+        path: path.to_path_buf(),
+        root,
+        entries: if is_dir { Some(HashMap::new()) } else { None },
+    };
+    // the entries there already are only taken note of.
+    watch.rescan(&mut Vec::new());
+    // the callback owns the kqueue, which is closed when the object is removed.
+    let read = move |object: &mut T, core: &mut Core| {
+        for event in watch.read_events() {
+            f(object, event, core);
+        }
+    };
+    core.register_reader(&EventedFd(&fd), object_id, read)?;
+    Ok(())
+}
+
+// an entry of a watched directory. kqueue only tells that the directory changed, so
+// the entries are compared with a new listing to find out how, with renames
+// recognized by the inode number.
+struct Entry {
+    ino: u64,
+    // kept open to be told about writes to the file, unless it isn't a regular file
+    // or couldn't be opened.
+    file: Option<File>,
+}
+
+struct KqueueWatch {
+    kqueue: File,
+    path: PathBuf,
+    root: File,
+    // the entries of the watched path, if it is a directory.
+    entries: Option<HashMap<OsString, Entry>>,
+}
+
+impl KqueueWatch {
+    fn read_events(&mut self) -> Vec<FsEvent> {
+        let mut events = Vec::new();
+        let mut kevents: [libc::kevent; 32] = unsafe { mem::zeroed() };
+        let timeout: libc::timespec = unsafe { mem::zeroed() };
+        loop {
+            let n = unsafe {
+                libc::kevent(
+                    self.kqueue.as_raw_fd(),
+                    ptr::null(),
+                    0,
+                    kevents.as_mut_ptr(),
+                    kevents.len() as _,
+                    &timeout,
+                )
+            };
+            if n == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("Failed to read changes to {}: {}", self.path.display(), err);
+                break;
+            }
+            let n = n as usize;
+            let mut rescan = false;
+            for kevent in &kevents[..n] {
+                let fd = kevent.ident as RawFd;
+                let fflags = kevent.fflags;
+                if fd == self.root.as_raw_fd() {
+                    let kind = if fflags & libc::NOTE_DELETE != 0 {
+                        FsEventKind::Deleted
+                    } else if fflags & libc::NOTE_RENAME != 0 {
+                        FsEventKind::RenamedFrom
+                    } else if self.entries.is_some() {
+                        // an entry was added, removed or renamed.
+                        rescan = true;
+                        continue;
+                    } else {
+                        FsEventKind::Modified
+                    };
+                    events.push(FsEvent {
+                        kind,
+                        path: self.path.clone(),
+                    });
+                } else if fflags & (libc::NOTE_WRITE | libc::NOTE_EXTEND) != 0 {
+                    // entries going away are found when rescanning the directory.
+                    if let Some(name) = self.entry_name(fd) {
+                        events.push(FsEvent {
+                            kind: FsEventKind::Modified,
+                            path: self.path.join(name),
+                        });
+                    }
+                }
+            }
+            if rescan {
+                self.rescan(&mut events);
+            }
+            if n < kevents.len() {
+                break;
+            }
+        }
+        events
+    }
+
+    fn entry_name(&self, fd: RawFd) -> Option<&OsString> {
+        let entries = self.entries.as_ref()?;
+        entries
+            .iter()
+            .find(|(_, entry)| entry.file.as_ref().map(File::as_raw_fd) == Some(fd))
+            .map(|(name, _)| name)
+    }
+
+    // compares the entries of the directory with what they were, reporting what
+    // changed.
+    fn rescan(&mut self, events: &mut Vec<FsEvent>) {
+        let entries = match &mut self.entries {
+            Some(entries) => entries,
+            None => return,
+        };
+        let listing = match fs::read_dir(&self.path) {
+            Ok(listing) => listing,
+            Err(err) => {
+                error!("Failed to list {}: {}", self.path.display(), err);
+                return;
+            }
+        };
+        let current: HashMap<OsString, u64> = listing
+            .filter_map(Result::ok)
+            .map(|entry| (entry.file_name(), entry.ino()))
+            .collect();
+        let mut gone = Vec::new();
+        for (name, entry) in mem::take(entries) {
+            if current.get(&name) == Some(&entry.ino) {
+                entries.insert(name, entry);
+            } else {
+                gone.push((name, entry));
+            }
+        }
+        let mut renamed = Vec::new();
+        let mut created = Vec::new();
+        for (name, ino) in current {
+            if entries.contains_key(&name) {
+                continue;
+            }
+            match gone.iter().position(|(_, entry)| entry.ino == ino) {
+                Some(pos) => renamed.push((gone.remove(pos), name)),
+                None => created.push((name, ino)),
+            }
+        }
+        for (name, _) in gone {
+            events.push(FsEvent {
+                kind: FsEventKind::Deleted,
+                path: self.path.join(name),
+            });
+        }
+        for ((old_name, entry), name) in renamed {
+            events.push(FsEvent {
+                kind: FsEventKind::RenamedFrom,
+                path: self.path.join(old_name),
+            });
+            events.push(FsEvent {
+                kind: FsEventKind::RenamedTo,
+                path: self.path.join(&name),
+            });
+            entries.insert(name, entry);
+        }
+        for (name, ino) in created {
+            let path = self.path.join(&name);
+            let file = watch_entry(self.kqueue.as_raw_fd(), &path);
+            events.push(FsEvent {
+                kind: FsEventKind::Created,
+                path,
+            });
+            entries.insert(name, Entry { ino, file });
+        }
+    }
+}
+
+// opens an entry of a watched directory to be told about writes to it, if it is a
+// regular file.
+fn watch_entry(kqueue: RawFd, path: &Path) -> Option<File> {
+    let is_file = fs::symlink_metadata(path).ok()?.is_file();
+    if !is_file {
+        return None;
+    }
+    let file = open(path, libc::O_NOFOLLOW).ok()?;
+    add_watch(kqueue, &file).ok()?;
+    Some(file)
+}
+
+fn open(path: &Path, flags: libc::c_int) -> io::Result<File> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+    let flags = OPEN_FLAGS | libc::O_CLOEXEC | libc::O_NONBLOCK | flags;
+    let fd = unsafe { libc::open(c_path.as_ptr(), flags) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+// the watch goes away by itself once the file is closed.
+fn add_watch(kqueue: RawFd, file: &File) -> io::Result<()> {
+    let mut change: libc::kevent = unsafe { mem::zeroed() };
+    change.ident = file.as_raw_fd() as _;
+    change.filter = libc::EVFILT_VNODE as _;
+    change.flags = (libc::EV_ADD | libc::EV_CLEAR) as _;
+    change.fflags = NOTES as _;
+    if unsafe { libc::kevent(kqueue, &change, 1, ptr::null_mut(), 0, ptr::null()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
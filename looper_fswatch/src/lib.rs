@@ -0,0 +1,103 @@
+use looper::{Core, ObjectId};
+use std::any::Any;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod inotify;
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+mod kqueue;
+#[cfg(windows)]
+mod windows;
+
+/// What happened to a watched file or directory, or to an entry in a watched
+/// directory.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FsEventKind {
+    Created,
+    Modified,
+    Deleted,
+    /// The path was renamed or moved away. If it was moved within a watched
+    /// directory, this is directly followed by `RenamedTo` with the new path.
+    RenamedFrom,
+    /// Something was renamed to the path, or moved to it from elsewhere.
+    RenamedTo,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FsEvent {
+    pub kind: FsEventKind,
+    pub path: PathBuf,
+}
+
+/// Watches files and directories for changes.
+pub struct Watcher;
+
+impl Watcher {
+    /// Calls `f` on the object with the given id with every change to `path`.
+    ///
+    /// If `path` is a directory, changes to the entries in it are reported as well,
+    /// but not changes deeper down. Watching goes on until the object is removed.
+    ///
+    /// inotify is used on Linux and kqueue on macOS and the BSDs. kqueue only tells that
+    /// a directory changed, so the entries are listed again to find out how, with
+    /// renames recognized by the inode number, and each regular file in the directory is
+    /// kept open to be told about writes to it. On Windows, ReadDirectoryChangesW is
+    /// used on a thread of its own, which ends with the next change after the object is
+    /// removed at the latest. Elsewhere this fails with `ErrorKind::Unsupported`.
+    pub fn watch<F, T>(
+        path: impl AsRef<Path>,
+        object_id: ObjectId,
+        f: F,
+        core: &mut Core,
+    ) -> io::Result<()>
+    where
+        F: 'static + FnMut(&mut T, FsEvent, &mut Core),
+        T: Any,
+    {
+        imp_watch(path.as_ref(), object_id, f, core)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use inotify::watch as imp_watch;
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+use kqueue::watch as imp_watch;
+#[cfg(windows)]
+use windows::watch as imp_watch;
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly",
+    windows
+)))]
+fn imp_watch<F, T>(_path: &Path, _object_id: ObjectId, _f: F, _core: &mut Core) -> io::Result<()>
+where
+    F: 'static + FnMut(&mut T, FsEvent, &mut Core),
+    T: Any,
+{
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Watching files is not supported on this platform.",
+    ))
+}
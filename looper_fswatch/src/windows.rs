@@ -0,0 +1,229 @@
+use crate::{FsEvent, FsEventKind};
+use log::{error, warn};
+use looper::{Core, ObjectId};
+use mio::{Ready, Registration, SetReadiness};
+use std::any::Any;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, ErrorKind};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use winapi::shared::minwindef::{DWORD, FALSE};
+use winapi::um::fileapi::{CreateFileW, OPEN_EXISTING};
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::ioapiset::CancelSynchronousIo;
+use winapi::um::winbase::{ReadDirectoryChangesW, FILE_FLAG_BACKUP_SEMANTICS};
+use winapi::um::winnt::{
+    FILE_ACTION_ADDED, FILE_ACTION_MODIFIED, FILE_ACTION_REMOVED, FILE_ACTION_RENAMED_NEW_NAME,
+    FILE_ACTION_RENAMED_OLD_NAME, FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_DIR_NAME,
+    FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_NOTIFY_CHANGE_SIZE,
+    FILE_NOTIFY_INFORMATION, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, HANDLE,
+};
+
+const NOTIFY_FILTER: DWORD = FILE_NOTIFY_CHANGE_FILE_NAME
+    | FILE_NOTIFY_CHANGE_DIR_NAME
+    | FILE_NOTIFY_CHANGE_SIZE
+    | FILE_NOTIFY_CHANGE_LAST_WRITE;
+
+pub fn watch<F, T>(path: &Path, object_id: ObjectId, mut f: F, core: &mut Core) -> io::Result<()>
+where
+    F: 'static + FnMut(&mut T, FsEvent, &mut Core),
+    T: Any,
+{
+    // only directories can be watched, so files are watched through the directory
+    // they are in.
+    let (dir, file_name) = if path.is_dir() {
+        (path.to_path_buf(), None)
+    } else {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Not a file or directory."))?;
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        (dir, Some(file_name.to_os_string()))
+    };
+    let directory = open_directory(&dir)?;
+    let (registration, set_readiness) = Registration::new2();
+    let (sender, receiver) = mpsc::channel();
+    let reader = ChangeReader {
+        directory,
+        path: path.to_path_buf(),
+        file_name,
+        sender,
+        set_readiness: set_readiness.clone(),
+        stop: Arc::new(AtomicBool::new(false)),
+    };
+    let stop = reader.stop.clone();
+    let thread = thread::Builder::new()
+        .name("looper-fswatch".to_string())
+        .spawn(move || reader.run())?;
+    let stop = StopThread { thread, stop };
+    // the callback keeps the registration alive, as dropping it stops the wakeups, and
+    // stops the thread once the object is removed.
+    let registration = Rc::new(registration);
+    let kept = registration.clone();
+    core.register_reader(&*registration, object_id, move |object: &mut T, core| {
+        let _ = (&kept, &stop);
+        // clear the readiness first, so changes sent from now on wake the loop again.
+        if let Err(err) = set_readiness.set_readiness(Ready::empty()) {
+            error!("Failed to reset readiness for file changes: {}", err);
+        }
+        while let Ok(event) = receiver.try_recv() {
+            f(object, event, core);
+        }
+    })?;
+    Ok(())
+}
+
+// waits for changes to a directory on a thread of its own, as ReadDirectoryChangesW
+// can't be polled.
+struct ChangeReader {
+    directory: File,
+    // the watched path, which is the directory or a file in it.
+    path: PathBuf,
+    // the name of the watched file, if a file is watched.
+    file_name: Option<OsString>,
+    sender: Sender<FsEvent>,
+    set_readiness: SetReadiness,
+    stop: Arc<AtomicBool>,
+}
+
+impl ChangeReader {
+    fn run(self) {
+        // aligned to DWORDs, as ReadDirectoryChangesW requires.
+        let mut buffer = vec![0u32; 16 * 1024];
+        while !self.stop.load(Ordering::SeqCst) {
+            let mut len: DWORD = 0;
+            let rc = unsafe {
+                ReadDirectoryChangesW(
+                    self.directory.as_raw_handle() as HANDLE,
+                    buffer.as_mut_ptr() as *mut _,
+                    (buffer.len() * 4) as DWORD,
+                    FALSE,
+                    NOTIFY_FILTER,
+                    &mut len,
+                    ptr::null_mut(),
+                    None,
+                )
+            };
+            if rc == 0 {
+                let err = io::Error::last_os_error();
+                if self.stop.load(Ordering::SeqCst) {
+                    return;
+                }
+                if self.file_name.is_none() && !self.path.exists() {
+                    // the watched directory itself went away.
+                    self.send(vec![FsEvent {
+                        kind: FsEventKind::Deleted,
+                        path: self.path.clone(),
+                    }]);
+                } else {
+                    error!("Failed to read changes to {}: {}", self.path.display(), err);
+                }
+                return;
+            }
+            if len == 0 {
+                warn!(
+                    "Too many changes to {}, some were lost.",
+                    self.path.display()
+                );
+                continue;
+            }
+            let events = self.parse(&buffer, len as usize);
+            if !events.is_empty() && !self.send(events) {
+                return;
+            }
+        }
+    }
+
+    // returns false if the object has been removed.
+    fn send(&self, events: Vec<FsEvent>) -> bool {
+        for event in events {
+            if self.sender.send(event).is_err() {
+                return false;
+            }
+        }
+        if let Err(err) = self.set_readiness.set_readiness(Ready::readable()) {
+            error!("Failed to wake up the event loop for file changes: {}", err);
+        }
+        true
+    }
+
+    fn parse(&self, buffer: &[u32], len: usize) -> Vec<FsEvent> {
+        let bytes = buffer.as_ptr() as *const u8;
+        let mut events = Vec::new();
+        let mut offset = 0;
+        while offset < len {
+            let info = unsafe { &*(bytes.add(offset) as *const FILE_NOTIFY_INFORMATION) };
+            let name = unsafe {
+                slice::from_raw_parts(info.FileName.as_ptr(), info.FileNameLength as usize / 2)
+            };
+            let name = OsString::from_wide(name);
+            let kind = match info.Action {
+                FILE_ACTION_ADDED => Some(FsEventKind::Created),
+                FILE_ACTION_REMOVED => Some(FsEventKind::Deleted),
+                FILE_ACTION_MODIFIED => Some(FsEventKind::Modified),
+                FILE_ACTION_RENAMED_OLD_NAME => Some(FsEventKind::RenamedFrom),
+                FILE_ACTION_RENAMED_NEW_NAME => Some(FsEventKind::RenamedTo),
+                _ => None,
+            };
+            let path = match &self.file_name {
+                Some(file_name) if *file_name == name => Some(self.path.clone()),
+                Some(_) => None,
+                None => Some(self.path.join(&name)),
+            };
+            if let (Some(kind), Some(path)) = (kind, path) {
+                events.push(FsEvent { kind, path });
+            }
+            if info.NextEntryOffset == 0 {
+                break;
+            }
+            offset += info.NextEntryOffset as usize;
+        }
+        events
+    }
+}
+
+// stops the thread of a watch once the watch is dropped.
+struct StopThread {
+    thread: JoinHandle<()>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for StopThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // wakes the thread up if it is waiting for changes. If it is just about to
+        // wait, it stops with the next change instead, once sending it fails.
+        unsafe { CancelSynchronousIo(self.thread.as_raw_handle() as HANDLE) };
+    }
+}
+
+fn open_directory(dir: &Path) -> io::Result<File> {
+    let wide: Vec<u16> = dir.as_os_str().encode_wide().chain(Some(0)).collect();
+    let handle = unsafe {
+        CreateFileW(
+            wide.as_ptr(),
+            FILE_LIST_DIRECTORY,
+            FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+            ptr::null_mut(),
+            OPEN_EXISTING,
+            FILE_FLAG_BACKUP_SEMANTICS,
+            ptr::null_mut(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_handle(handle as RawHandle) })
+}
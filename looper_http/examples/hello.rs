@@ -0,0 +1,34 @@
+use looper::Core;
+use looper_http::{HttpServer, Request, RequestHandler, Response};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+struct Hello;
+
+impl RequestHandler for Hello {
+    fn handle_request(&mut self, request: Request, _core: &mut Core) -> Response {
+        match request.path.as_str() {
+            "/" => Response::new(200, "Hello there!\n").with_header("Content-Type", "text/plain"),
+            "/count" => {
+                let mut n = 0;
+                Response::streaming(200, move |_core| {
+                    n += 1;
+                    if n <= 10 {
+                        Some(format!("{}\n", n).into_bytes())
+                    } else {
+                        None
+                    }
+                })
+            }
+            _ => Response::new(404, "Not found.\n"),
+        }
+    }
+}
+
+fn main() {
+    let mut core = Core::new();
+    let address = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 17774));
+
+    HttpServer::start(address, || Hello, &mut core).expect("Port 17774 expected to be available.");
+
+    core.run().expect("Failed to run the event loop.");
+}
@@ -0,0 +1,450 @@
+use log::{error, info};
+use looper::{Core, ObjectId};
+use mio::net::{TcpListener, TcpStream};
+use std::io::{self, ErrorKind, Read, Result, Write};
+use std::net::SocketAddr;
+
+// limits on what a client may send, to keep a misbehaving client from making the
+// read buffer grow without bounds.
+const MAX_HEAD_LEN: usize = 64 * 1024;
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+const MAX_HEADERS: usize = 64;
+
+/// A request read from a connection.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// The minor version of HTTP/1.x the client speaks.
+    pub version: u8,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Vec<u8>,
+    /// The id of the connection the request arrived on, e.g. for resuming a streamed
+    /// response with `HttpConnection::resume`.
+    pub connection_id: ObjectId,
+}
+
+impl Request {
+    /// Returns the value of the first header with the given name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+}
+
+type BodyStream = Box<dyn FnMut(&mut Core) -> Option<Vec<u8>>>;
+
+enum Body {
+    Full(Vec<u8>),
+    Streaming(BodyStream),
+}
+
+/// A response to send back for a request.
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Body,
+}
+
+impl Response {
+    /// Creates a response with the given status and body.
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Body::Full(body.into()),
+        }
+    }
+
+    /// Creates a response whose body is produced a chunk at a time.
+    ///
+    /// `next_chunk` is called whenever everything produced so far has been written to
+    /// the socket, so a slow client slows down the producer rather than making data
+    /// pile up. It returns `None` at the end of the body. An empty chunk means there
+    /// is nothing to send yet, and the connection waits until it is woken up with
+    /// `HttpConnection::resume`. The body is sent with chunked transfer encoding, or
+    /// to HTTP/1.0 clients by closing the connection at its end.
+    pub fn streaming<F>(status: u16, next_chunk: F) -> Response
+    where
+        F: 'static + FnMut(&mut Core) -> Option<Vec<u8>>,
+    {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Body::Streaming(Box::new(next_chunk)),
+        }
+    }
+
+    /// Adds a header to the response.
+    ///
+    /// The headers for the length of the body and for closing the connection are
+    /// added by the server.
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+pub trait RequestHandler {
+    fn acceptable(&mut self, _from_address: SocketAddr) -> bool {
+        true
+    }
+
+    /// Handles a request, returning the response to send back.
+    ///
+    /// Requests on a connection are handled one at a time, in the order they arrive.
+    fn handle_request(&mut self, request: Request, core: &mut Core) -> Response;
+}
+
+impl<H: RequestHandler + ?Sized> RequestHandler for Box<H> {
+    fn acceptable(&mut self, from_address: SocketAddr) -> bool {
+        (**self).acceptable(from_address)
+    }
+
+    fn handle_request(&mut self, request: Request, core: &mut Core) -> Response {
+        (**self).handle_request(request, core)
+    }
+}
+
+type Factory = Box<dyn Fn() -> Box<dyn RequestHandler>>;
+
+pub struct HttpServer {
+    tcp_listener: TcpListener,
+    factory: Factory,
+    object_id: ObjectId,
+    connections: Vec<ObjectId>,
+}
+
+impl HttpServer {
+    /// Starts a server accepting connections on the given address.
+    ///
+    /// `factory` creates a handler for every accepted connection.
+    pub fn start<H, F>(socket_address: SocketAddr, factory: F, core: &mut Core) -> Result<ObjectId>
+    where
+        H: 'static + RequestHandler,
+        F: 'static + Fn() -> H,
+    {
+        let tcp_listener = TcpListener::bind(&socket_address)?;
+        let object_id = core.next_id();
+        core.register_reader(&tcp_listener, object_id, HttpServer::read_all)?;
+        core.add(HttpServer {
+            tcp_listener,
+            factory: Box::new(move || Box::new(factory())),
+            object_id,
+            connections: Vec::new(),
+        });
+        Ok(object_id)
+    }
+
+    /// Returns the ids of the connections of this server.
+    pub fn connections(&self) -> &[ObjectId] {
+        &self.connections
+    }
+
+    fn read_all(&mut self, core: &mut Core) {
+        // forget about connections that have been closed since the last time.
+        self.connections
+            .retain(|id| core.get::<HttpConnection>(*id).is_some());
+        loop {
+            let (tcp_stream, address) = match self.tcp_listener.accept() {
+                Ok((t, a)) => (t, a),
+                Err(ref e) => {
+                    if e.kind() != ErrorKind::WouldBlock {
+                        error!("Error while trying to accept an incoming connection: {}", e);
+                        core.report_error(self.object_id, e);
+                        core.remove(self.object_id);
+                    }
+                    return;
+                }
+            };
+            let mut handler = (self.factory)();
+            if !handler.acceptable(address) {
+                info!(
+                    "Connection from {} found unacceptable. Dropping it.",
+                    address
+                );
+                continue; // just drop the tcp stream
+            }
+            let object_id = core.next_id();
+            let result = core.register_reader_writer(
+                &tcp_stream,
+                object_id,
+                HttpConnection::read_all,
+                HttpConnection::write_all,
+            );
+            if let Err(err) = result {
+                error!("Failed to register a new connection: {}", err);
+                continue;
+            }
+            core.add(HttpConnection {
+                tcp_stream,
+                handler,
+                object_id,
+                incoming: Vec::new(),
+                outgoing: Vec::new(),
+                written: 0,
+                stream: None,
+                eof: false,
+                closing: false,
+            });
+            self.connections.push(object_id);
+        }
+    }
+}
+
+/// A connection accepted by an `HttpServer`.
+pub struct HttpConnection {
+    tcp_stream: TcpStream,
+    handler: Box<dyn RequestHandler>,
+    object_id: ObjectId,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    // how much of `outgoing` has been written already.
+    written: usize,
+    // the body being streamed, and whether it is sent in chunks.
+    stream: Option<(BodyStream, bool)>,
+    // whether the client has closed its side of the connection.
+    eof: bool,
+    // whether to close the connection once the current response has been sent.
+    closing: bool,
+}
+
+impl HttpConnection {
+    /// Carries on with the streamed response of the connection with the given id,
+    /// once its producer has more to send after returning an empty chunk.
+    ///
+    /// This can be called from anywhere with access to the core, including the
+    /// producer itself. Returns false if there is no such connection.
+    pub fn resume(connection_id: ObjectId, core: &mut Core) -> bool {
+        if !core.contains(connection_id) {
+            return false;
+        }
+        core.post(connection_id, |connection: &mut HttpConnection, core| {
+            connection.drive(core)
+        });
+        true
+    }
+
+    fn read_all(&mut self, core: &mut Core) {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.tcp_stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(n) => self.incoming.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    self.fail(e, core);
+                    return;
+                }
+            }
+            // requests are only taken out of the buffer one at a time, so a client
+            // sending while a response is streamed could make it grow forever.
+            if self.incoming.len() > MAX_HEAD_LEN + MAX_BODY_LEN {
+                let err = io::Error::new(ErrorKind::InvalidData, "Too much data buffered.");
+                self.fail(err, core);
+                return;
+            }
+        }
+        self.drive(core);
+    }
+
+    fn write_all(&mut self, core: &mut Core) {
+        self.drive(core);
+    }
+
+    // Writes what has been queued, produces more of the current response and handles
+    // the next request, until the socket would block or there is nothing left to do.
+    fn drive(&mut self, core: &mut Core) {
+        loop {
+            if let Err(err) = self.flush() {
+                self.fail(err, core);
+                return;
+            }
+            if self.written < self.outgoing.len() {
+                return; // wait for the socket to become writable again
+            }
+            if let Some((next_chunk, chunked)) = &mut self.stream {
+                match next_chunk(core) {
+                    // wait for the producer to resume the connection.
+                    Some(chunk) if chunk.is_empty() => return,
+                    Some(chunk) if *chunked => {
+                        self.outgoing
+                            .extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+                        self.outgoing.extend_from_slice(&chunk);
+                        self.outgoing.extend_from_slice(b"\r\n");
+                    }
+                    Some(chunk) => self.outgoing.extend_from_slice(&chunk),
+                    None => {
+                        if *chunked {
+                            self.outgoing.extend_from_slice(b"0\r\n\r\n");
+                        }
+                        self.stream = None;
+                    }
+                }
+                continue;
+            }
+            if self.closing {
+                core.remove(self.object_id);
+                return;
+            }
+            match self.next_request() {
+                Ok(Some((request, keep_alive))) => {
+                    let head = request.method == "HEAD";
+                    let version = request.version;
+                    let response = self.handler.handle_request(request, core);
+                    self.start_response(response, head, version, keep_alive);
+                }
+                Ok(None) if self.eof => self.closing = true,
+                Ok(None) => return,
+                Err(status) => self.start_response(Response::new(status, ""), false, 1, false),
+            }
+        }
+    }
+
+    // Takes the next complete request out of the incoming buffer, along with whether
+    // the connection should be kept open after responding to it. Fails with the
+    // status to respond with if the request is invalid.
+    fn next_request(&mut self) -> std::result::Result<Option<(Request, bool)>, u16> {
+        let (mut request, head_len) = {
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut parsed = httparse::Request::new(&mut headers);
+            let head_len = match parsed.parse(&self.incoming) {
+                Ok(httparse::Status::Complete(len)) => len,
+                Ok(httparse::Status::Partial) if self.incoming.len() > MAX_HEAD_LEN => {
+                    return Err(431)
+                }
+                Ok(httparse::Status::Partial) => return Ok(None),
+                Err(httparse::Error::TooManyHeaders) => return Err(431),
+                Err(_) => return Err(400),
+            };
+            let request = Request {
+                method: parsed.method.unwrap_or_default().to_string(),
+                path: parsed.path.unwrap_or_default().to_string(),
+                version: parsed.version.unwrap_or_default(),
+                headers: parsed
+                    .headers
+                    .iter()
+                    .map(|h| (h.name.to_string(), h.value.to_vec()))
+                    .collect(),
+                body: Vec::new(),
+                connection_id: self.object_id,
+            };
+            (request, head_len)
+        };
+        if request.header("Transfer-Encoding").is_some() {
+            return Err(501); // chunked request bodies aren't supported
+        }
+        let body_len = match request.header("Content-Length") {
+            Some(value) => std::str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or(400u16)?,
+            None => 0,
+        };
+        if body_len > MAX_BODY_LEN {
+            return Err(413);
+        }
+        if self.incoming.len() < head_len + body_len {
+            return Ok(None);
+        }
+        request.body = self.incoming[head_len..head_len + body_len].to_vec();
+        self.incoming.drain(..head_len + body_len);
+        let keep_alive = match request.header("Connection") {
+            Some(value) if has_token(value, "close") => false,
+            Some(value) if has_token(value, "keep-alive") => true,
+            _ => request.version >= 1,
+        };
+        Ok(Some((request, keep_alive)))
+    }
+
+    fn start_response(&mut self, response: Response, head: bool, version: u8, keep_alive: bool) {
+        let status = response.status;
+        let streaming = matches!(response.body, Body::Streaming(_));
+        let chunked = streaming && version >= 1;
+        // without chunks, the end of a streamed body is marked by closing.
+        let close = !keep_alive || (streaming && !chunked);
+        let no_body = (100..200).contains(&status) || status == 204 || status == 304;
+        let mut head_text = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+        for (name, value) in &response.headers {
+            head_text.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        match &response.body {
+            Body::Full(body) if !no_body => {
+                head_text.push_str(&format!("Content-Length: {}\r\n", body.len()));
+            }
+            Body::Streaming(_) if chunked && !no_body => {
+                head_text.push_str("Transfer-Encoding: chunked\r\n");
+            }
+            _ => {}
+        }
+        if close {
+            head_text.push_str("Connection: close\r\n");
+        }
+        head_text.push_str("\r\n");
+        self.outgoing.extend_from_slice(head_text.as_bytes());
+        if !head && !no_body {
+            match response.body {
+                Body::Full(body) => self.outgoing.extend_from_slice(&body),
+                Body::Streaming(next_chunk) => self.stream = Some((next_chunk, chunked)),
+            }
+        }
+        self.closing = close;
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        while self.written < self.outgoing.len() {
+            match self.tcp_stream.write(&self.outgoing[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        if self.written == self.outgoing.len() {
+            self.outgoing.clear();
+            self.written = 0;
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, err: io::Error, core: &mut Core) {
+        error!("IO error on http connection: {}", err);
+        core.report_error(self.object_id, &err);
+        core.remove(self.object_id);
+    }
+}
+
+// whether a comma separated header value contains the given token.
+fn has_token(value: &[u8], token: &str) -> bool {
+    value
+        .split(|&b| b == b',')
+        .any(|t| t.trim_ascii().eq_ignore_ascii_case(token.as_bytes()))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
@@ -0,0 +1,35 @@
+use looper::Core;
+use looper_httpclient::HttpRequest;
+use std::time::Duration;
+
+// Fetches the URL given as argument, e.g. `http://127.0.0.1:17774/count` with the
+// hello example of looper_http running, and prints the response.
+
+struct Printer;
+
+fn main() {
+    let url = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("http://127.0.0.1:17774/"));
+    let mut core = Core::new();
+    let printer_id = core.add(Printer);
+    HttpRequest::get(&url)
+        .with_timeout(Duration::from_secs(10))
+        .send(
+            &mut core,
+            printer_id,
+            |_: &mut Printer, response, _core| match response {
+                Ok(response) => {
+                    println!("{} {}", response.status, response.reason);
+                    for (name, value) in &response.headers {
+                        println!("{}: {}", name, String::from_utf8_lossy(value));
+                    }
+                    println!();
+                    print!("{}", String::from_utf8_lossy(&response.body));
+                }
+                Err(err) => println!("Request failed: {}", err),
+            },
+        )
+        .expect("Invalid URL.");
+    core.run().expect("Failed to run the event loop.");
+}
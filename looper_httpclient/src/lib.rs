@@ -0,0 +1,632 @@
+use log::{debug, error};
+use looper::{Core, ObjectId};
+use mio::net::TcpStream;
+use std::any::Any;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use url::{Host, Position, Url};
+
+// limits on what a server may send, to keep a misbehaving server from making the
+// read buffer grow without bounds.
+const MAX_HEAD_LEN: usize = 64 * 1024;
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+const MAX_HEADERS: usize = 64;
+
+type Deliver = Box<dyn FnOnce(io::Result<HttpResponse>, &mut Core)>;
+
+/// A request to send to an HTTP server, see `send`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    timeout: Option<Duration>,
+}
+
+impl HttpRequest {
+    /// Creates a request with the given method for the given `http://` URL.
+    pub fn new(method: &str, url: &str) -> HttpRequest {
+        HttpRequest {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    pub fn get(url: &str) -> HttpRequest {
+        HttpRequest::new("GET", url)
+    }
+
+    pub fn post(url: &str, body: impl Into<Vec<u8>>) -> HttpRequest {
+        HttpRequest::new("POST", url).with_body(body)
+    }
+
+    /// Adds a header to the request.
+    ///
+    /// The headers for the host, the length of the body and for closing the
+    /// connection are added by the client.
+    pub fn with_header(mut self, name: &str, value: &str) -> HttpRequest {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> HttpRequest {
+        self.body = body.into();
+        self
+    }
+
+    /// Fails the request with `ErrorKind::TimedOut` if the response isn't complete
+    /// within `timeout` of sending it, which includes looking up the host and
+    /// connecting to it.
+    pub fn with_timeout(mut self, timeout: Duration) -> HttpRequest {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sends the request, calling `on_response` on the object with the given id with
+    /// the response or the error the request failed with.
+    ///
+    /// The host is looked up and connected to without blocking the event loop, trying
+    /// each of its addresses in turn, and the connection is closed once the response
+    /// is in. Responses with an error status are passed on like any other. Bodies of
+    /// up to 16 MiB are read, whether their length is given, they are sent in chunks
+    /// or they end with the connection. Fails right away if the URL isn't a valid
+    /// `http://` one.
+    pub fn send<F, T>(self, core: &mut Core, object_id: ObjectId, on_response: F) -> io::Result<()>
+    where
+        F: 'static + FnOnce(&mut T, io::Result<HttpResponse>, &mut Core),
+        T: Any,
+    {
+        let url = Url::parse(&self.url)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err.to_string()))?;
+        if url.scheme() != "http" {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Only http:// URLs are supported.",
+            ));
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addresses = match url.host() {
+            Some(Host::Domain(_)) => Vec::new(),
+            Some(Host::Ipv4(ip)) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+            Some(Host::Ipv6(ip)) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+            None => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "The URL has no host.",
+                ))
+            }
+        };
+        let exchange_id = core.next_id();
+        let deliver = move |result, core: &mut Core| {
+            core.post(object_id, move |object: &mut T, core| {
+                on_response(object, result, core)
+            });
+        };
+        let exchange = Exchange {
+            object_id: exchange_id,
+            url: self.url.clone(),
+            port,
+            addresses,
+            stream: None,
+            outgoing: self.serialize(&url),
+            written: 0,
+            incoming: Vec::new(),
+            head_only: self.method == "HEAD",
+            response: None,
+            eof: false,
+            deliver: Some(Box::new(deliver)),
+        };
+        // looking up the host and connecting add objects of their own, so the
+        // exchange has to be added first to keep its id.
+        core.add(exchange);
+        match url.domain() {
+            Some(domain) => {
+                let result = looper_dns::resolve(domain, exchange_id, Exchange::resolved, core);
+                if let Err(err) = result {
+                    core.remove(exchange_id);
+                    return Err(err);
+                }
+            }
+            None => core.post(exchange_id, |exchange: &mut Exchange, core| {
+                if let Err(err) = exchange.connect_next(core) {
+                    exchange.finish(Err(err), core);
+                }
+            }),
+        }
+        if let Some(timeout) = self.timeout {
+            // dropped along with the exchange if the response is in first.
+            core.set_timeout(timeout, exchange_id, |exchange: &mut Exchange, core| {
+                let err = io::Error::new(ErrorKind::TimedOut, "Timed out waiting for a response.");
+                exchange.finish(Err(err), core);
+            });
+        }
+        Ok(())
+    }
+
+    fn serialize(&self, url: &Url) -> Vec<u8> {
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\n",
+            self.method,
+            &url[Position::BeforePath..Position::AfterQuery],
+            &url[Position::BeforeHost..Position::AfterPort]
+        );
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !self.body.is_empty() || self.method == "POST" || self.method == "PUT" {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("Connection: close\r\n\r\n");
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+/// A response read from a server.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub reason: String,
+    /// The minor version of HTTP/1.x the server speaks.
+    pub version: u8,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Returns the value of the first header with the given name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+}
+
+// how the end of the body of a response is found.
+#[derive(Clone, Copy, Debug)]
+enum BodyLength {
+    Known(usize),
+    Chunked,
+    UntilClose,
+}
+
+// a request being sent with `HttpRequest::send`, living in the core as an object of
+// its own until the response is in.
+struct Exchange {
+    object_id: ObjectId,
+    url: String,
+    port: u16,
+    // the addresses left to try connecting to.
+    addresses: Vec<SocketAddr>,
+    stream: Option<TcpStream>,
+    outgoing: Vec<u8>,
+    // how much of `outgoing` has been written already.
+    written: usize,
+    incoming: Vec<u8>,
+    // whether the response has no body, whatever its headers say.
+    head_only: bool,
+    // the response once its head has been read, with the body read so far.
+    response: Option<(HttpResponse, BodyLength)>,
+    // whether the server has closed its side of the connection.
+    eof: bool,
+    // taken once the exchange is done, either way.
+    deliver: Option<Deliver>,
+}
+
+impl Exchange {
+    fn resolved(&mut self, addresses: io::Result<Vec<IpAddr>>, core: &mut Core) {
+        match addresses {
+            Ok(addresses) => {
+                let port = self.port;
+                self.addresses = addresses
+                    .into_iter()
+                    .map(|ip| SocketAddr::new(ip, port))
+                    .collect();
+                if let Err(err) = self.connect_next(core) {
+                    self.finish(Err(err), core);
+                }
+            }
+            Err(err) => self.finish(Err(err), core),
+        }
+    }
+
+    // starts connecting to the next address, failing once there are none left.
+    fn connect_next(&mut self, core: &mut Core) -> io::Result<()> {
+        loop {
+            if self.addresses.is_empty() {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    "The host has no addresses.",
+                ));
+            }
+            let address = self.addresses.remove(0);
+            let result = core.connect_tcp(
+                address,
+                None,
+                self.object_id,
+                Exchange::connected,
+                Exchange::connect_failed,
+            );
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) if self.addresses.is_empty() => return Err(err),
+                Err(err) => debug!("Failed to connect to {}: {}", address, err),
+            }
+        }
+    }
+
+    fn connect_failed(&mut self, err: io::Error, core: &mut Core) {
+        debug!("Failed to connect for {}: {}", self.url, err);
+        if self.addresses.is_empty() {
+            self.finish(Err(err), core);
+        } else if let Err(err) = self.connect_next(core) {
+            self.finish(Err(err), core);
+        }
+    }
+
+    fn connected(&mut self, stream: TcpStream, core: &mut Core) {
+        let result = core.register_reader_writer(
+            &stream,
+            self.object_id,
+            Exchange::read_all,
+            Exchange::write_all,
+        );
+        if let Err(err) = result {
+            self.finish(Err(err), core);
+            return;
+        }
+        self.stream = Some(stream);
+        self.write_all(core);
+    }
+
+    fn write_all(&mut self, core: &mut Core) {
+        while self.written < self.outgoing.len() {
+            let result = match &mut self.stream {
+                Some(stream) => stream.write(&self.outgoing[self.written..]),
+                None => return,
+            };
+            match result {
+                Ok(0) => {
+                    self.finish(Err(ErrorKind::WriteZero.into()), core);
+                    return;
+                }
+                Ok(n) => self.written += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    self.finish(Err(e), core);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn read_all(&mut self, core: &mut Core) {
+        let mut chunk = [0; 4096];
+        loop {
+            let result = match &mut self.stream {
+                Some(stream) => stream.read(&mut chunk),
+                None => return,
+            };
+            match result {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(n) => self.incoming.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    self.finish(Err(e), core);
+                    return;
+                }
+            }
+            if self.incoming.len() > MAX_HEAD_LEN + MAX_BODY_LEN {
+                let err = io::Error::new(ErrorKind::InvalidData, "The response is too long.");
+                self.finish(Err(err), core);
+                return;
+            }
+        }
+        match self.parse() {
+            Ok(true) => {
+                let (response, _) = self.response.take().unwrap();
+                self.finish(Ok(response), core);
+            }
+            Ok(false) if self.eof => {
+                let err = io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "The connection was closed before the response was complete.",
+                );
+                self.finish(Err(err), core);
+            }
+            Ok(false) => {}
+            Err(err) => self.finish(Err(err), core),
+        }
+    }
+
+    // reads what has arrived so far, returning whether the response is complete.
+    fn parse(&mut self) -> io::Result<bool> {
+        while self.response.is_none() {
+            if !self.parse_head()? {
+                return Ok(false);
+            }
+        }
+        let (response, length) = self.response.as_mut().unwrap();
+        match *length {
+            BodyLength::Known(len) => {
+                if self.incoming.len() < len {
+                    return Ok(false);
+                }
+                response.body = self.incoming[..len].to_vec();
+                Ok(true)
+            }
+            BodyLength::Chunked => take_chunks(&mut self.incoming, &mut response.body),
+            BodyLength::UntilClose => {
+                if self.incoming.len() > MAX_BODY_LEN {
+                    return Err(too_long());
+                }
+                if self.eof {
+                    response.body = std::mem::take(&mut self.incoming);
+                }
+                Ok(self.eof)
+            }
+        }
+    }
+
+    // takes the head of the response out of the incoming buffer, returning whether
+    // it was complete. The heads of informational responses are skipped.
+    fn parse_head(&mut self) -> io::Result<bool> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut parsed = httparse::Response::new(&mut headers);
+        let head_len = match parsed.parse(&self.incoming) {
+            Ok(httparse::Status::Complete(len)) => len,
+            Ok(httparse::Status::Partial) if self.incoming.len() > MAX_HEAD_LEN => {
+                return Err(too_long())
+            }
+            Ok(httparse::Status::Partial) => return Ok(false),
+            Err(err) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid response: {}", err),
+                ))
+            }
+        };
+        let response = HttpResponse {
+            status: parsed.code.unwrap_or_default(),
+            reason: parsed.reason.unwrap_or_default().to_string(),
+            version: parsed.version.unwrap_or_default(),
+            headers: parsed
+                .headers
+                .iter()
+                .map(|h| (h.name.to_string(), h.value.to_vec()))
+                .collect(),
+            body: Vec::new(),
+        };
+        self.incoming.drain(..head_len);
+        let status = response.status;
+        if (100..200).contains(&status) {
+            debug!(
+                "Skipping informational response {} for {}.",
+                status, self.url
+            );
+            return Ok(true);
+        }
+        let length = if self.head_only || status == 204 || status == 304 {
+            BodyLength::Known(0)
+        } else if response
+            .header("Transfer-Encoding")
+            .is_some_and(|value| has_token(value, "chunked"))
+        {
+            BodyLength::Chunked
+        } else if let Some(value) = response.header("Content-Length") {
+            let len = std::str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Invalid Content-Length."))?;
+            if len > MAX_BODY_LEN {
+                return Err(too_long());
+            }
+            BodyLength::Known(len)
+        } else {
+            BodyLength::UntilClose
+        };
+        self.response = Some((response, length));
+        Ok(true)
+    }
+
+    fn finish(&mut self, result: io::Result<HttpResponse>, core: &mut Core) {
+        let deliver = match self.deliver.take() {
+            Some(deliver) => deliver,
+            None => return,
+        };
+        if let Err(err) = &result {
+            error!("Request for {} failed: {}", self.url, err);
+        }
+        // closes the connection once this callback returns.
+        core.remove(self.object_id);
+        deliver(result, core);
+    }
+}
+
+// moves the complete chunks of a chunked body from `incoming` to `body`, returning
+// whether the last one has been read.
+fn take_chunks(incoming: &mut Vec<u8>, body: &mut Vec<u8>) -> io::Result<bool> {
+    let invalid = || io::Error::new(ErrorKind::InvalidData, "Invalid chunked body.");
+    let mut pos = 0;
+    let done = loop {
+        let line_end = match find(&incoming[pos..], b"\r\n") {
+            Some(i) => pos + i,
+            None => break false,
+        };
+        // chunk extensions are ignored. The size is hex digits only, which
+        // `from_str_radix` alone doesn't make sure of, as it takes a sign as well.
+        let size = incoming[pos..line_end]
+            .split(|&b| b == b';')
+            .next()
+            .unwrap()
+            .trim_ascii_end();
+        if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+            return Err(invalid());
+        }
+        let size = std::str::from_utf8(size)
+            .ok()
+            .and_then(|size| usize::from_str_radix(size, 16).ok())
+            .ok_or_else(invalid)?;
+        if size == 0 {
+            // the trailers end with an empty line, and are ignored.
+            match find(&incoming[line_end..], b"\r\n\r\n") {
+                Some(i) => {
+                    pos = line_end + i + 4;
+                    break true;
+                }
+                None => break false,
+            }
+        }
+        if size > MAX_BODY_LEN - body.len() {
+            return Err(too_long());
+        }
+        let data_start = line_end + 2;
+        let data_end = data_start + size;
+        if incoming.len() < data_end + 2 {
+            break false;
+        }
+        if &incoming[data_end..data_end + 2] != b"\r\n" {
+            return Err(invalid());
+        }
+        body.extend_from_slice(&incoming[data_start..data_end]);
+        pos = data_end + 2;
+    };
+    incoming.drain(..pos);
+    Ok(done)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "The response is too long.")
+}
+
+// whether a comma separated header value contains the given token.
+fn has_token(value: &[u8], token: &str) -> bool {
+    value
+        .split(|&b| b == b',')
+        .any(|t| t.trim_ascii().eq_ignore_ascii_case(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // feeds `input` to `take_chunks` in pieces of `step` bytes, like reads would.
+    fn parse(input: &[u8], step: usize) -> io::Result<(Vec<u8>, bool, Vec<u8>)> {
+        let mut incoming = Vec::new();
+        let mut body = Vec::new();
+        let mut done = false;
+        for piece in input.chunks(step) {
+            assert!(!done, "done before the end of the input");
+            incoming.extend_from_slice(piece);
+            done = take_chunks(&mut incoming, &mut body)?;
+        }
+        Ok((body, done, incoming))
+    }
+
+    #[test]
+    fn chunks() {
+        let input = b"5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n";
+        let (body, done, rest) = parse(input, input.len()).unwrap();
+        assert_eq!(body, b"hello, world");
+        assert!(done);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn split_anywhere() {
+        let input = b"a\r\n0123456789\r\n1F\r\n0123456789abcdefghijklmnopqrstu\r\n0\r\n\r\n";
+        for step in 1..input.len() {
+            let (body, done, rest) = parse(input, step).unwrap();
+            assert_eq!(body, b"01234567890123456789abcdefghijklmnopqrstu");
+            assert!(done);
+            assert!(rest.is_empty());
+        }
+    }
+
+    #[test]
+    fn incomplete() {
+        let (body, done, rest) = parse(b"5\r\nhello\r\n5\r\nwor", 64).unwrap();
+        assert_eq!(body, b"hello");
+        assert!(!done);
+        assert_eq!(rest, b"5\r\nwor");
+        let (body, done, _) = parse(b"5\r\nhello\r\n0\r\n\r", 64).unwrap();
+        assert_eq!(body, b"hello");
+        assert!(!done);
+    }
+
+    #[test]
+    fn extensions() {
+        let input = b"5;name=value\r\nhello\r\n2 ; spaced\r\n!!\r\n0;last\r\n\r\n";
+        let (body, done, _) = parse(input, 3).unwrap();
+        assert_eq!(body, b"hello!!");
+        assert!(done);
+    }
+
+    #[test]
+    fn trailers() {
+        let input = b"5\r\nhello\r\n0\r\nExpires: never\r\nX-Check: 1\r\n\r\n";
+        for step in 1..input.len() {
+            let (body, done, rest) = parse(input, step).unwrap();
+            assert_eq!(body, b"hello");
+            assert!(done);
+            assert!(rest.is_empty());
+        }
+    }
+
+    #[test]
+    fn leaves_what_follows() {
+        let (_, done, rest) = parse(b"0\r\n\r\nHTTP/1.1", 64).unwrap();
+        assert!(done);
+        assert_eq!(rest, b"HTTP/1.1");
+    }
+
+    #[test]
+    fn malformed_sizes() {
+        let inputs: &[&[u8]] = &[
+            b"\r\nhello\r\n",
+            b"zz\r\nhello\r\n",
+            b"+5\r\nhello\r\n",
+            b" 5\r\nhello\r\n",
+            b"-5\r\nhello\r\n",
+            b"0x5\r\nhello\r\n",
+            b"5 5\r\nhello\r\n",
+            b"10000000000000000\r\nhello\r\n",
+        ];
+        for input in inputs {
+            let err = parse(input, 64).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn data_not_followed_by_crlf() {
+        let err = parse(b"5\r\nhello!\r\n0\r\n\r\n", 64).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn too_long() {
+        let err = parse(b"ffffffff\r\n", 64).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        // the limit is on the body as a whole.
+        let mut body = vec![0; MAX_BODY_LEN - 4];
+        let mut incoming = b"5\r\nhello\r\n".to_vec();
+        assert!(take_chunks(&mut incoming, &mut body).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+use looper::Core;
+use looper_serial::{SerialHandler, SerialPort, SerialSettings};
+use std::io::Write;
+
+// Prints everything that arrives on a serial port, e.g.
+// `serial_monitor /dev/ttyUSB0 115200`.
+
+struct Monitor;
+
+impl SerialHandler for Monitor {
+    fn handle_data(&mut self, data: &[u8], _core: &mut Core) -> Option<Vec<u8>> {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(data).expect("Failed to write to stdout.");
+        stdout.flush().expect("Failed to flush stdout.");
+        None
+    }
+
+    fn on_hangup(&mut self, _core: &mut Core) {
+        eprintln!("Device unplugged.");
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or_else(|| "/dev/ttyUSB0".to_string());
+    let settings = SerialSettings {
+        baud_rate: args
+            .next()
+            .map(|rate| rate.parse().expect("Baud rate must be a number."))
+            .unwrap_or(9600),
+        ..SerialSettings::default()
+    };
+    let mut core = Core::new();
+    SerialPort::open(&path, &settings, Monitor, &mut core).expect("Failed to open the port.");
+    core.run().expect("Failed to run the event loop.");
+}
@@ -0,0 +1,332 @@
+//! Serial ports for looper.
+#![cfg(unix)]
+
+use log::{error, info};
+use looper::{Core, ObjectId, RawFdSource};
+use std::fs::OpenOptions;
+use std::io::{self, ErrorKind, Read, Result, Write};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FlowControl {
+    None,
+    /// XON/XOFF characters sent in band.
+    Software,
+    /// The RTS and CTS lines.
+    Hardware,
+}
+
+/// How data is sent over a serial line. The default is 9600 baud, 8N1 without flow
+/// control.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SerialSettings {
+    /// One of the standard rates, e.g. 9600 or 115200.
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+}
+
+impl Default for SerialSettings {
+    fn default() -> Self {
+        SerialSettings {
+            baud_rate: 9600,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+pub trait SerialHandler {
+    /// Handles data read from the port, returning a reply to send back.
+    ///
+    /// Data is passed on as it arrives, so a message may be split over several calls.
+    fn handle_data(&mut self, _data: &[u8], _core: &mut Core) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Called when the device goes away, e.g. because it was unplugged, before the
+    /// port is closed.
+    fn on_hangup(&mut self, _core: &mut Core) {}
+
+    /// Called when an error ends the connection, before the port is closed.
+    fn on_error(&mut self, _error: &io::Error, _core: &mut Core) {}
+}
+
+impl<H: SerialHandler + ?Sized> SerialHandler for Box<H> {
+    fn handle_data(&mut self, data: &[u8], core: &mut Core) -> Option<Vec<u8>> {
+        (**self).handle_data(data, core)
+    }
+
+    fn on_hangup(&mut self, core: &mut Core) {
+        (**self).on_hangup(core)
+    }
+
+    fn on_error(&mut self, error: &io::Error, core: &mut Core) {
+        (**self).on_error(error, core)
+    }
+}
+
+/// An open serial port, such as `/dev/ttyUSB0`.
+///
+/// The port is set to raw mode, so bytes are passed on as they are. Outgoing data is
+/// queued until the port can take it.
+pub struct SerialPort {
+    source: RawFdSource,
+    handler: Box<dyn SerialHandler>,
+    object_id: ObjectId,
+    outgoing: Vec<u8>,
+    // how much of `outgoing` has been written already.
+    written: usize,
+}
+
+impl SerialPort {
+    /// Opens the serial device at the given path, adding the port to the core.
+    ///
+    /// The port doesn't become the controlling terminal of the process. `handler`
+    /// gets the data that arrives on it.
+    pub fn open<H>(
+        path: impl AsRef<Path>,
+        settings: &SerialSettings,
+        handler: H,
+        core: &mut Core,
+    ) -> Result<ObjectId>
+    where
+        H: 'static + SerialHandler,
+    {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+        let source = RawFdSource::from_file(file)?;
+        configure(source.as_raw_fd(), settings)?;
+        let object_id = core.next_id();
+        core.register_reader_writer(
+            &source,
+            object_id,
+            SerialPort::read_all,
+            SerialPort::write_all,
+        )?;
+        Ok(core.add(SerialPort {
+            source,
+            handler: Box::new(handler),
+            object_id,
+            outgoing: Vec::new(),
+            written: 0,
+        }))
+    }
+
+    /// Sends data on the port with the given id.
+    ///
+    /// Returns false if there is no such port. This can't be used from the port's own
+    /// handler, which should reply through `handle_data` instead.
+    pub fn send_to(port_id: ObjectId, core: &mut Core, data: &[u8]) -> bool {
+        let result = match core.get_mut::<SerialPort>(port_id) {
+            Some(port) => port.send(data),
+            None => return false,
+        };
+        if let Err(err) = result {
+            if let Some(mut port) = core.take_object::<SerialPort>(port_id) {
+                port.fail(err, core);
+            }
+        }
+        true
+    }
+
+    /// Changes the settings of the port, e.g. to switch to a higher baud rate once a
+    /// device has been told to.
+    ///
+    /// Applies once the data queued for the port has been sent.
+    pub fn set_settings(&mut self, settings: &SerialSettings) -> Result<()> {
+        configure(self.source.as_raw_fd(), settings)
+    }
+
+    /// Returns the number of bytes waiting to be written to the port.
+    pub fn queued_bytes(&self) -> usize {
+        self.outgoing.len() - self.written
+    }
+
+    fn read_all(&mut self, core: &mut Core) {
+        let mut chunk = [0; 4096];
+        loop {
+            let n = match self.source.read(&mut chunk) {
+                Ok(0) => return self.hang_up(core),
+                Ok(n) => n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                // what reading reports once the device is gone.
+                Err(ref e) if e.raw_os_error() == Some(libc::EIO) => return self.hang_up(core),
+                Err(e) => return self.fail(e, core),
+            };
+            if let Some(reply) = self.handler.handle_data(&chunk[..n], core) {
+                if let Err(err) = self.send(&reply) {
+                    return self.fail(err, core);
+                }
+            }
+        }
+    }
+
+    // Queues the data and writes as much as possible. Returns an error if the port
+    // should be closed.
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        self.outgoing.extend_from_slice(data);
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        while self.written < self.outgoing.len() {
+            match self.source.write(&self.outgoing[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        // drop what has been written once it makes up most of the queue, so the queue
+        // doesn't grow forever while the port keeps up.
+        if self.written > self.outgoing.len() / 2 {
+            self.outgoing.drain(..self.written);
+            self.written = 0;
+        }
+        Ok(())
+    }
+
+    fn hang_up(&mut self, core: &mut Core) {
+        info!("Serial device went away.");
+        self.handler.on_hangup(core);
+        core.remove(self.object_id);
+    }
+
+    // Reports an error that ends the connection and removes the port.
+    fn fail(&mut self, err: io::Error, core: &mut Core) {
+        error!("IO error on serial port: {}", err);
+        self.handler.on_error(&err, core);
+        core.report_error(self.object_id, &err);
+        core.remove(self.object_id);
+    }
+
+    fn write_all(&mut self, core: &mut Core) {
+        if let Err(err) = self.flush() {
+            self.fail(err, core);
+        }
+    }
+}
+
+fn configure(fd: libc::c_int, settings: &SerialSettings) -> Result<()> {
+    let speed = speed(settings.baud_rate)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Unsupported baud rate."))?;
+    let mut termios: libc::termios = unsafe { mem::zeroed() };
+    check(unsafe { libc::tcgetattr(fd, &mut termios) })?;
+    unsafe { libc::cfmakeraw(&mut termios) };
+    check(unsafe { libc::cfsetispeed(&mut termios, speed) })?;
+    check(unsafe { libc::cfsetospeed(&mut termios, speed) })?;
+    // ignore the modem control lines, and turn on the receiver.
+    termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+    termios.c_cflag &= !libc::CSIZE;
+    termios.c_cflag |= match settings.data_bits {
+        DataBits::Five => libc::CS5,
+        DataBits::Six => libc::CS6,
+        DataBits::Seven => libc::CS7,
+        DataBits::Eight => libc::CS8,
+    };
+    termios.c_cflag &= !(libc::PARENB | libc::PARODD);
+    termios.c_iflag &= !libc::INPCK;
+    match settings.parity {
+        Parity::None => {}
+        Parity::Odd => {
+            termios.c_cflag |= libc::PARENB | libc::PARODD;
+            termios.c_iflag |= libc::INPCK;
+        }
+        Parity::Even => {
+            termios.c_cflag |= libc::PARENB;
+            termios.c_iflag |= libc::INPCK;
+        }
+    }
+    match settings.stop_bits {
+        StopBits::One => termios.c_cflag &= !libc::CSTOPB,
+        StopBits::Two => termios.c_cflag |= libc::CSTOPB,
+    }
+    termios.c_cflag &= !libc::CRTSCTS;
+    termios.c_iflag &= !(libc::IXON | libc::IXOFF);
+    match settings.flow_control {
+        FlowControl::None => {}
+        FlowControl::Software => termios.c_iflag |= libc::IXON | libc::IXOFF,
+        FlowControl::Hardware => termios.c_cflag |= libc::CRTSCTS,
+    }
+    check(unsafe { libc::tcsetattr(fd, libc::TCSADRAIN, &termios) })
+}
+
+fn speed(baud_rate: u32) -> Option<libc::speed_t> {
+    let speed = match baud_rate {
+        50 => libc::B50,
+        75 => libc::B75,
+        110 => libc::B110,
+        134 => libc::B134,
+        150 => libc::B150,
+        200 => libc::B200,
+        300 => libc::B300,
+        600 => libc::B600,
+        1200 => libc::B1200,
+        1800 => libc::B1800,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        460800 => libc::B460800,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        921600 => libc::B921600,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        1000000 => libc::B1000000,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        1500000 => libc::B1500000,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        2000000 => libc::B2000000,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        3000000 => libc::B3000000,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        4000000 => libc::B4000000,
+        _ => return None,
+    };
+    Some(speed)
+}
+
+fn check(result: libc::c_int) -> Result<()> {
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
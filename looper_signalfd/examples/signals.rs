@@ -0,0 +1,24 @@
+use looper::Core;
+use looper_signalfd::{SignalInfo, SignalWatcher};
+
+// Prints the signals sent to it, e.g. with `kill -USR1 <pid>`, until it gets SIGTERM.
+
+struct Printer;
+
+fn main() {
+    let mut core = Core::new();
+    let printer_id = core.add(Printer);
+    let print = |_: &mut Printer, info: SignalInfo, core: &mut Core| {
+        println!(
+            "signal {} from pid {} (uid {}), value {}",
+            info.signal, info.pid, info.uid, info.value
+        );
+        if info.signal == libc::SIGTERM {
+            core.exit();
+        }
+    };
+    let signals = [libc::SIGUSR1, libc::SIGUSR2, libc::SIGTERM];
+    SignalWatcher::watch(&signals, printer_id, print, &mut core).expect("Failed to watch signals.");
+    println!("pid {}", std::process::id());
+    core.run().expect("Failed to run the event loop.");
+}
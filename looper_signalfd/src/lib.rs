@@ -0,0 +1,110 @@
+use looper::{Core, ObjectId};
+use std::any::Any;
+use std::io;
+
+#[cfg(unix)]
+mod pipe;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod signalfd;
+
+/// A signal that was received, along with what is known about where it came from.
+///
+/// For signals sent with `kill` or `sigqueue`, `pid` and `uid` are those of the
+/// sender. For `SIGCHLD` they are those of the child, and `status` is its exit code
+/// or the signal that ended or stopped it, depending on `code`. For other signals
+/// they have no meaning.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[repr(C)]
+pub struct SignalInfo {
+    pub signal: i32,
+    /// Why the signal was sent, e.g. `libc::SI_QUEUE` for `sigqueue` or
+    /// `libc::CLD_EXITED` for a child that exited.
+    pub code: i32,
+    pub pid: u32,
+    pub uid: u32,
+    pub status: i32,
+    /// The integer sent along with the signal by `sigqueue`.
+    pub value: i32,
+}
+
+/// Watches for signals sent to the process.
+pub struct SignalWatcher;
+
+impl SignalWatcher {
+    /// Calls `f` on the object with the given id with every one of `signals` received.
+    ///
+    /// The signals are caught by a handler that passes them on to the loop through a
+    /// pipe, and that runs alongside any other handlers for the same signals, such as
+    /// the one the core reaps children with. Like with any signal handler, a signal
+    /// that arrives again before the first one was handled may only be reported once.
+    /// Watching goes on until the object is removed. Fails with
+    /// `ErrorKind::InvalidInput` for signals that can't be caught, such as `SIGKILL`,
+    /// and with `ErrorKind::Unsupported` on Windows.
+    pub fn watch<F, T>(
+        signals: &[i32],
+        object_id: ObjectId,
+        f: F,
+        core: &mut Core,
+    ) -> io::Result<()>
+    where
+        F: 'static + FnMut(&mut T, SignalInfo, &mut Core),
+        T: Any,
+    {
+        imp_watch(signals, object_id, f, core)
+    }
+
+    /// Like `watch`, but blocks the signals and reads them from a signalfd instead of
+    /// catching them.
+    ///
+    /// This needs no signal handler, and signals are queued by the kernel until they
+    /// are read. The signals are only blocked in the calling thread and the threads
+    /// it starts from now on, so this should be called before any other threads are
+    /// started, or those may still receive the signals. They stay blocked once the
+    /// object is removed. `SIGCHLD` can't be watched this way, as the core needs it to
+    /// reap children. Only supported on Linux.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn watch_blocked<F, T>(
+        signals: &[i32],
+        object_id: ObjectId,
+        f: F,
+        core: &mut Core,
+    ) -> io::Result<()>
+    where
+        F: 'static + FnMut(&mut T, SignalInfo, &mut Core),
+        T: Any,
+    {
+        signalfd::watch(signals, object_id, f, core)
+    }
+}
+
+// fails for the signals that can't or mustn't be caught.
+#[cfg(unix)]
+fn check_signal(signal: i32) -> io::Result<()> {
+    if signal_hook_registry::FORBIDDEN.contains(&signal) || signal <= 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Signal {} can't be watched.", signal),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+use pipe::watch as imp_watch;
+
+#[cfg(not(unix))]
+fn imp_watch<F, T>(
+    _signals: &[i32],
+    _object_id: ObjectId,
+    _f: F,
+    _core: &mut Core,
+) -> io::Result<()>
+where
+    F: 'static + FnMut(&mut T, SignalInfo, &mut Core),
+    T: Any,
+{
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Watching signals is not supported on this platform.",
+    ))
+}
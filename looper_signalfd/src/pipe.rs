@@ -0,0 +1,114 @@
+use crate::{check_signal, SignalInfo};
+use log::error;
+use looper::{Core, ObjectId};
+use mio::unix::EventedFd;
+use signal_hook_registry::SigId;
+use std::any::Any;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+const INFO_LEN: usize = mem::size_of::<SignalInfo>();
+
+// the handlers and the pipe they write to, which are taken down together.
+struct Handlers {
+    ids: Vec<SigId>,
+    reader: File,
+    // only written to by the handlers.
+    _writer: File,
+}
+
+impl Drop for Handlers {
+    fn drop(&mut self) {
+        // the handlers are gone once this returns, before the pipe is closed.
+        for id in self.ids.drain(..) {
+            signal_hook_registry::unregister(id);
+        }
+    }
+}
+
+pub fn watch<F, T>(
+    signals: &[i32],
+    object_id: ObjectId,
+    mut f: F,
+    core: &mut Core,
+) -> io::Result<()>
+where
+    F: 'static + FnMut(&mut T, SignalInfo, &mut Core),
+    T: Any,
+{
+    for signal in signals {
+        check_signal(*signal)?;
+    }
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut handlers = Handlers {
+        ids: Vec::new(),
+        reader: unsafe { File::from_raw_fd(fds[0]) },
+        _writer: unsafe { File::from_raw_fd(fds[1]) },
+    };
+    for fd in &fds {
+        unsafe {
+            let flags = libc::fcntl(*fd, libc::F_GETFL);
+            if flags == -1 || libc::fcntl(*fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    let writer = fds[1];
+    for signal in signals {
+        // only async-signal-safe calls may be made here. A write of less than
+        // `PIPE_BUF` bytes is atomic, so the info is never split up. If the pipe is full
+        // the signal is dropped, as there is nowhere else for it to go.
+        let action = move |info: &libc::siginfo_t| {
+            let info = unsafe { SignalInfo::from_siginfo(info) };
+            unsafe { libc::write(writer, &info as *const SignalInfo as *const _, INFO_LEN) };
+        };
+        let id = unsafe { signal_hook_registry::register_sigaction(*signal, action)? };
+        handlers.ids.push(id);
+    }
+    let reader = handlers.reader.as_raw_fd();
+    let mut bytes = Vec::new();
+    let mut buffer = [0; 64 * INFO_LEN];
+    // the callback owns the handlers, which are taken down when the object is removed.
+    let read = move |object: &mut T, core: &mut Core| loop {
+        match handlers.reader.read(&mut buffer) {
+            Ok(n) => bytes.extend_from_slice(&buffer[..n]),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+            Err(e) => {
+                error!("Failed to read received signals: {}", e);
+                return;
+            }
+        }
+        let complete = bytes.len() - bytes.len() % INFO_LEN;
+        for chunk in bytes[..complete].chunks(INFO_LEN) {
+            let info = unsafe { (chunk.as_ptr() as *const SignalInfo).read_unaligned() };
+            f(object, info, core);
+        }
+        bytes.drain(..complete);
+    };
+    core.register_reader(&EventedFd(&reader), object_id, read)?;
+    Ok(())
+}
+
+impl SignalInfo {
+    unsafe fn from_siginfo(info: &libc::siginfo_t) -> SignalInfo {
+        let value = info.si_value();
+        SignalInfo {
+            signal: info.si_signo,
+            code: info.si_code,
+            pid: info.si_pid() as u32,
+            uid: info.si_uid(),
+            status: info.si_status(),
+            // `sival_int` shares its first bytes with `sival_ptr`.
+            value: (&value as *const libc::sigval as *const libc::c_int).read(),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{check_signal, SignalInfo};
+use log::error;
+use looper::{Core, ObjectId};
+use mio::unix::EventedFd;
+use std::any::Any;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::mem;
+use std::os::unix::io::FromRawFd;
+
+const SIGINFO_LEN: usize = mem::size_of::<libc::signalfd_siginfo>();
+
+pub fn watch<F, T>(
+    signals: &[i32],
+    object_id: ObjectId,
+    mut f: F,
+    core: &mut Core,
+) -> io::Result<()>
+where
+    F: 'static + FnMut(&mut T, SignalInfo, &mut Core),
+    T: Any,
+{
+    let mut set: libc::sigset_t = unsafe { mem::zeroed() };
+    unsafe { libc::sigemptyset(&mut set) };
+    for signal in signals {
+        check_signal(*signal)?;
+        if *signal == libc::SIGCHLD {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "SIGCHLD can't be blocked, the core reaps children with it.",
+            ));
+        }
+        unsafe { libc::sigaddset(&mut set, *signal) };
+    }
+    let fd = unsafe { libc::signalfd(-1, &set, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut signalfd = unsafe { File::from_raw_fd(fd) };
+    // signals that aren't blocked are still handled as usual instead of being queued.
+    let err = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+    if err != 0 {
+        return Err(io::Error::from_raw_os_error(err));
+    }
+    let mut buffer = [0; 16 * SIGINFO_LEN];
+    // the callback owns the signalfd, which is closed when the object is removed.
+    let read = move |object: &mut T, core: &mut Core| loop {
+        // reads only ever return whole records.
+        let n = match signalfd.read(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+            Err(e) => {
+                error!("Failed to read received signals: {}", e);
+                return;
+            }
+        };
+        for chunk in buffer[..n].chunks_exact(SIGINFO_LEN) {
+            let info =
+                unsafe { (chunk.as_ptr() as *const libc::signalfd_siginfo).read_unaligned() };
+            let info = SignalInfo {
+                signal: info.ssi_signo as i32,
+                code: info.ssi_code,
+                pid: info.ssi_pid,
+                uid: info.ssi_uid,
+                status: info.ssi_status,
+                value: info.ssi_int,
+            };
+            f(object, info, core);
+        }
+    };
+    core.register_reader(&EventedFd(&fd), object_id, read)?;
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use looper::Core;
+use looper_tcp::{Framing, TcpHandler, TcpServer};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+struct Client;
+
+impl TcpHandler for Client {
+    fn welcome_message(&mut self, _core: &mut Core) -> Option<Vec<u8>> {
+        Some(b"Hello there!".to_vec())
+    }
+
+    fn handle_message(&mut self, message: Vec<u8>, _core: &mut Core) -> Option<Vec<u8>> {
+        let mut reply = b"I heard you say: ".to_vec();
+        reply.extend(message);
+        Some(reply)
+    }
+}
+
+fn main() {
+    let mut core = Core::new();
+    let address = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 17772));
+
+    let server_id = TcpServer::start(address, || Client, &mut core)
+        .expect("Port 17772 expected to be available.");
+    core.get_mut::<TcpServer>(server_id)
+        .unwrap()
+        .set_framing(Framing::Lines);
+
+    core.run().expect("Failed to run the event loop.");
+}
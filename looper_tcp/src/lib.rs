@@ -0,0 +1,327 @@
+use log::{error, info};
+use looper::{Core, ObjectId};
+use mio::net::{TcpListener, TcpStream};
+use std::io::{self, ErrorKind, Read, Result, Write};
+use std::net::SocketAddr;
+
+// the largest message accepted from a client, to keep a misbehaving client from
+// making the read buffer grow without bounds.
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+pub trait TcpHandler {
+    fn acceptable(&mut self, _from_address: SocketAddr) -> bool {
+        true
+    }
+
+    fn welcome_message(&mut self, _core: &mut Core) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Handles a message read from the connection, returning a reply to send back.
+    ///
+    /// What a message is depends on the framing of the connection.
+    fn handle_message(&mut self, _message: Vec<u8>, _core: &mut Core) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Called when an error ends the connection, before the connection is closed.
+    fn on_error(&mut self, _error: &io::Error, _core: &mut Core) {}
+}
+
+impl<H: TcpHandler + ?Sized> TcpHandler for Box<H> {
+    fn acceptable(&mut self, from_address: SocketAddr) -> bool {
+        (**self).acceptable(from_address)
+    }
+
+    fn welcome_message(&mut self, core: &mut Core) -> Option<Vec<u8>> {
+        (**self).welcome_message(core)
+    }
+
+    fn handle_message(&mut self, message: Vec<u8>, core: &mut Core) -> Option<Vec<u8>> {
+        (**self).handle_message(message, core)
+    }
+
+    fn on_error(&mut self, error: &io::Error, core: &mut Core) {
+        (**self).on_error(error, core)
+    }
+}
+
+/// How the byte stream of a connection is split into messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Framing {
+    /// Every read is passed on as it is, and messages are sent as they are.
+    Raw,
+    /// Messages are lines ending in `\n`, which is stripped from incoming messages and
+    /// added to outgoing ones.
+    Lines,
+    /// Messages are prefixed with their length as a big-endian `u32`.
+    LengthPrefixed,
+}
+
+type Factory = Box<dyn Fn() -> Box<dyn TcpHandler>>;
+
+pub struct TcpServer {
+    tcp_listener: TcpListener,
+    factory: Factory,
+    object_id: ObjectId,
+    connections: Vec<ObjectId>,
+    framing: Framing,
+}
+
+impl TcpServer {
+    /// Starts a server accepting connections on the given address.
+    ///
+    /// `factory` creates a handler for every accepted connection. Connections use raw
+    /// framing until `set_framing` is called.
+    pub fn start<H, F>(socket_address: SocketAddr, factory: F, core: &mut Core) -> Result<ObjectId>
+    where
+        H: 'static + TcpHandler,
+        F: 'static + Fn() -> H,
+    {
+        let tcp_listener = TcpListener::bind(&socket_address)?;
+        let object_id = core.next_id();
+        core.register_reader(&tcp_listener, object_id, TcpServer::read_all)?;
+        core.add(TcpServer {
+            tcp_listener,
+            factory: Box::new(move || Box::new(factory())),
+            object_id,
+            connections: Vec::new(),
+            framing: Framing::Raw,
+        });
+        Ok(object_id)
+    }
+
+    /// Sets the framing of connections accepted from now on.
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    /// Returns the ids of the connections of this server.
+    pub fn connections(&self) -> &[ObjectId] {
+        &self.connections
+    }
+
+    fn read_all(&mut self, core: &mut Core) {
+        // forget about connections that have been closed since the last time.
+        self.connections
+            .retain(|id| core.get::<TcpConnection>(*id).is_some());
+        loop {
+            let (tcp_stream, address) = match self.tcp_listener.accept() {
+                Ok((t, a)) => (t, a),
+                Err(ref e) => {
+                    if e.kind() != ErrorKind::WouldBlock {
+                        error!("Error while trying to accept an incoming connection: {}", e);
+                        core.report_error(self.object_id, e);
+                        core.remove(self.object_id);
+                    }
+                    return;
+                }
+            };
+            let mut handler = (self.factory)();
+            if !handler.acceptable(address) {
+                info!(
+                    "Connection from {} found unacceptable. Dropping it.",
+                    address
+                );
+                continue; // just drop the tcp stream
+            }
+            let welcome_message = handler.welcome_message(core);
+            let object_id = core.next_id();
+            let mut connection = TcpConnection {
+                tcp_stream,
+                handler,
+                object_id,
+                framing: self.framing,
+                incoming: Vec::new(),
+                outgoing: Vec::new(),
+                written: 0,
+            };
+            if let Some(message) = welcome_message {
+                if let Err(err) = connection.send(&message) {
+                    connection.fail(err, core);
+                    continue;
+                }
+            }
+            let result = core.register_reader_writer(
+                &connection.tcp_stream,
+                object_id,
+                TcpConnection::read_all,
+                TcpConnection::write_all,
+            );
+            if let Err(err) = result {
+                error!("Failed to register a new connection: {}", err);
+                continue;
+            }
+            core.add(connection);
+            self.connections.push(object_id);
+        }
+    }
+}
+
+/// A connection accepted by a `TcpServer`.
+///
+/// Incoming data is buffered until a full message has been read, and outgoing data is
+/// queued until the socket can take it.
+pub struct TcpConnection {
+    tcp_stream: TcpStream,
+    handler: Box<dyn TcpHandler>,
+    object_id: ObjectId,
+    framing: Framing,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    // how much of `outgoing` has been written already.
+    written: usize,
+}
+
+impl TcpConnection {
+    /// Sends a message on the connection with the given id.
+    ///
+    /// The message is framed according to the framing of the connection. Returns
+    /// false if there is no such connection. This can't be used from the connection's
+    /// own handler, which should reply through `handle_message` instead.
+    pub fn send_to(connection_id: ObjectId, core: &mut Core, message: &[u8]) -> bool {
+        let result = match core.get_mut::<TcpConnection>(connection_id) {
+            Some(connection) => connection.send(message),
+            None => return false,
+        };
+        if let Err(err) = result {
+            if let Some(mut connection) = core.take_object::<TcpConnection>(connection_id) {
+                connection.fail(err, core);
+            }
+        }
+        true
+    }
+
+    /// Returns the number of bytes waiting to be written to the socket.
+    pub fn queued_bytes(&self) -> usize {
+        self.outgoing.len() - self.written
+    }
+
+    fn read_all(&mut self, core: &mut Core) {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.tcp_stream.read(&mut chunk) {
+                Ok(0) => {
+                    info!("Connection closed.");
+                    core.remove(self.object_id);
+                    return;
+                }
+                Ok(n) => self.incoming.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    self.fail(e, core);
+                    return;
+                }
+            }
+            loop {
+                let message = match self.next_message() {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    Err(err) => {
+                        self.fail(err, core);
+                        return;
+                    }
+                };
+                if let Some(reply) = self.handler.handle_message(message, core) {
+                    if let Err(err) = self.send(&reply) {
+                        self.fail(err, core);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    // Takes the next complete message out of the incoming buffer.
+    fn next_message(&mut self) -> Result<Option<Vec<u8>>> {
+        let len = match self.framing {
+            Framing::Raw if self.incoming.is_empty() => return Ok(None),
+            Framing::Raw => return Ok(Some(std::mem::take(&mut self.incoming))),
+            Framing::Lines => match self.incoming.iter().position(|&b| b == b'\n') {
+                Some(pos) => {
+                    let mut line: Vec<u8> = self.incoming.drain(..=pos).collect();
+                    line.pop();
+                    return Ok(Some(line));
+                }
+                None => self.incoming.len(),
+            },
+            Framing::LengthPrefixed if self.incoming.len() < 4 => return Ok(None),
+            Framing::LengthPrefixed => {
+                let mut prefix = [0; 4];
+                prefix.copy_from_slice(&self.incoming[..4]);
+                let len = u32::from_be_bytes(prefix) as usize;
+                if self.incoming.len() >= 4 + len {
+                    let message = self.incoming[4..4 + len].to_vec();
+                    self.incoming.drain(..4 + len);
+                    return Ok(Some(message));
+                }
+                len
+            }
+        };
+        if len > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Incoming message is too long.",
+            ));
+        }
+        Ok(None)
+    }
+
+    // Queues the message and writes as much as possible. Returns an error if the
+    // connection should be dropped.
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        match self.framing {
+            Framing::Raw => self.outgoing.extend_from_slice(message),
+            Framing::Lines => {
+                self.outgoing.extend_from_slice(message);
+                self.outgoing.push(b'\n');
+            }
+            Framing::LengthPrefixed => {
+                let len = message.len();
+                if len > u32::MAX as usize {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "Outgoing message is too long.",
+                    ));
+                }
+                self.outgoing.extend_from_slice(&(len as u32).to_be_bytes());
+                self.outgoing.extend_from_slice(message);
+            }
+        }
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        while self.written < self.outgoing.len() {
+            match self.tcp_stream.write(&self.outgoing[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        // drop what has been written once it makes up most of the queue, so the queue
+        // doesn't grow forever while the socket keeps up.
+        if self.written > self.outgoing.len() / 2 {
+            self.outgoing.drain(..self.written);
+            self.written = 0;
+        }
+        Ok(())
+    }
+
+    // Reports an error that ends the connection and removes the connection.
+    fn fail(&mut self, err: io::Error, core: &mut Core) {
+        error!("IO error on tcp connection: {}", err);
+        self.handler.on_error(&err, core);
+        core.report_error(self.object_id, &err);
+        core.remove(self.object_id);
+    }
+
+    fn write_all(&mut self, core: &mut Core) {
+        if let Err(err) = self.flush() {
+            self.fail(err, core);
+        }
+    }
+}
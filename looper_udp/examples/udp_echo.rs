@@ -0,0 +1,31 @@
+use looper::Core;
+use looper_udp::UdpEndpoint;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+struct Echo {
+    endpoint: UdpEndpoint,
+}
+
+impl Echo {
+    fn endpoint(echo: &mut Echo) -> &mut UdpEndpoint {
+        &mut echo.endpoint
+    }
+
+    fn reply(&mut self, datagram: &[u8], from: SocketAddr, core: &mut Core) {
+        if let Err(err) = self.endpoint.send_to(datagram, from, core) {
+            eprintln!("Failed to reply to {}: {}", from, err);
+        }
+    }
+}
+
+fn main() {
+    let mut core = Core::new();
+    let address = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 17773));
+
+    let id = core.next_id();
+    let endpoint = UdpEndpoint::bind(address, id, Echo::endpoint, Echo::reply, &mut core)
+        .expect("Port 17773 expected to be available.");
+    core.add(Echo { endpoint });
+
+    core.run().expect("Failed to run the event loop.");
+}
@@ -0,0 +1,136 @@
+use log::error;
+use looper::{Core, ObjectId};
+use mio::net::UdpSocket;
+use mio::Token;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Result};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// the largest payload a UDP datagram can carry.
+const MAX_DATAGRAM_LEN: usize = 65535;
+
+/// A UDP socket that is part of an object in a core.
+///
+/// The endpoint is kept in the object it was bound for, and reads every pending
+/// datagram when the socket becomes readable, passing each one to that object.
+/// Datagrams that can't be sent right away are queued and sent as the socket becomes
+/// writable again.
+pub struct UdpEndpoint {
+    socket: UdpSocket,
+    token: Token,
+    outgoing: VecDeque<(Vec<u8>, SocketAddr)>,
+    write_interest: bool,
+}
+
+impl UdpEndpoint {
+    /// Binds a socket to the given address for the object with the given id.
+    ///
+    /// `access` returns the endpoint from the object, which it must be added to, and
+    /// `on_datagram` is called on the object with every datagram received and the
+    /// address it came from.
+    pub fn bind<T, A, F>(
+        socket_address: SocketAddr,
+        object_id: ObjectId,
+        access: A,
+        mut on_datagram: F,
+        core: &mut Core,
+    ) -> Result<UdpEndpoint>
+    where
+        T: Any,
+        A: 'static + Copy + Fn(&mut T) -> &mut UdpEndpoint,
+        F: 'static + FnMut(&mut T, &[u8], SocketAddr, &mut Core),
+    {
+        let socket = UdpSocket::bind(&socket_address)?;
+        let mut buffer = vec![0; MAX_DATAGRAM_LEN];
+        let read = move |object: &mut T, core: &mut Core| loop {
+            match access(object).socket.recv_from(&mut buffer) {
+                Ok((n, from)) => on_datagram(object, &buffer[..n], from, core),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                // errors for earlier datagrams, e.g. a port being unreachable, are
+                // reported once and don't stop the socket from working.
+                Err(e) => error!("Error while receiving a datagram: {}", e),
+            }
+        };
+        let write = move |object: &mut T, core: &mut Core| {
+            if let Err(err) = access(object).flush(core) {
+                error!("Error while sending a datagram: {}", err);
+            }
+        };
+        let token = core.register_reader_writer(&socket, object_id, read, write)?;
+        Ok(UdpEndpoint {
+            socket,
+            token,
+            outgoing: VecDeque::new(),
+            write_interest: true,
+        })
+    }
+
+    /// Sends a datagram to the given address.
+    ///
+    /// If the socket can't take the datagram right away it is queued, and sent once
+    /// the datagrams queued before it have been sent. Fails if sending fails for any
+    /// other reason, in which case the datagram that couldn't be sent is dropped.
+    pub fn send_to(&mut self, datagram: &[u8], target: SocketAddr, core: &mut Core) -> Result<()> {
+        self.outgoing.push_back((datagram.to_vec(), target));
+        self.flush(core)
+    }
+
+    /// Returns the number of datagrams waiting to be sent.
+    pub fn queued(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn join_multicast_v4(&self, group: Ipv4Addr, interface: Ipv4Addr) -> Result<()> {
+        self.socket.join_multicast_v4(&group, &interface)
+    }
+
+    pub fn leave_multicast_v4(&self, group: Ipv4Addr, interface: Ipv4Addr) -> Result<()> {
+        self.socket.leave_multicast_v4(&group, &interface)
+    }
+
+    pub fn join_multicast_v6(&self, group: Ipv6Addr, interface: u32) -> Result<()> {
+        self.socket.join_multicast_v6(&group, interface)
+    }
+
+    pub fn leave_multicast_v6(&self, group: Ipv6Addr, interface: u32) -> Result<()> {
+        self.socket.leave_multicast_v6(&group, interface)
+    }
+
+    pub fn set_multicast_loop_v4(&self, enabled: bool) -> Result<()> {
+        self.socket.set_multicast_loop_v4(enabled)
+    }
+
+    pub fn set_multicast_ttl_v4(&self, ttl: u32) -> Result<()> {
+        self.socket.set_multicast_ttl_v4(ttl)
+    }
+
+    pub fn set_broadcast(&self, enabled: bool) -> Result<()> {
+        self.socket.set_broadcast(enabled)
+    }
+
+    fn flush(&mut self, core: &mut Core) -> Result<()> {
+        let mut result = Ok(());
+        while let Some((datagram, target)) = self.outgoing.front() {
+            match self.socket.send_to(datagram, target) {
+                Ok(_) => {}
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => result = Err(e),
+            }
+            self.outgoing.pop_front();
+        }
+        // only wake up for writability while there is something to send.
+        let pending = !self.outgoing.is_empty();
+        if pending != self.write_interest {
+            core.set_write_interest(&self.socket, self.token, pending)?;
+            self.write_interest = pending;
+        }
+        result
+    }
+}
@@ -0,0 +1,30 @@
+use looper::Core;
+use looper_uds::{Framing, PeerCredentials, UdsHandler, UdsServer};
+
+struct Client;
+
+impl UdsHandler for Client {
+    fn acceptable(&mut self, credentials: &PeerCredentials) -> bool {
+        // only talk to processes running as the same user.
+        credentials.uid == unsafe { libc::getuid() }
+    }
+
+    fn handle_message(&mut self, message: Vec<u8>, _core: &mut Core) -> Option<Vec<u8>> {
+        let mut reply = b"I heard you say: ".to_vec();
+        reply.extend(message);
+        Some(reply)
+    }
+}
+
+fn main() {
+    let mut core = Core::new();
+    let path = std::env::temp_dir().join("looper_uds_echo.sock");
+
+    let server_id = UdsServer::start(&path, || Client, &mut core)
+        .expect("Socket path expected to be available.");
+    core.get_mut::<UdsServer>(server_id)
+        .unwrap()
+        .set_framing(Framing::Lines);
+
+    core.run().expect("Failed to run the event loop.");
+}
@@ -0,0 +1,461 @@
+//! Unix domain socket servers and clients for looper.
+#![cfg(unix)]
+
+use log::{error, info};
+use looper::{Core, ObjectId};
+use mio::unix::EventedFd;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Result, Write};
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+// the largest message accepted from a peer, to keep a misbehaving peer from making
+// the read buffer grow without bounds.
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// The credentials of the process at the other end of a connection, as they were
+/// when it connected.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PeerCredentials {
+    /// Only known on Linux and Android.
+    pub pid: Option<u32>,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl PeerCredentials {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn of(stream: &UnixStream) -> Result<PeerCredentials> {
+        let mut credentials = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut credentials as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            pid: Some(credentials.pid as u32),
+            uid: credentials.uid,
+            gid: credentials.gid,
+        })
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn of(stream: &UnixStream) -> Result<PeerCredentials> {
+        let mut uid = 0;
+        let mut gid = 0;
+        if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            pid: None,
+            uid,
+            gid,
+        })
+    }
+}
+
+pub trait UdsHandler {
+    /// Decides whether to keep a connection, e.g. by checking that the peer runs as
+    /// the same user.
+    fn acceptable(&mut self, _credentials: &PeerCredentials) -> bool {
+        true
+    }
+
+    fn welcome_message(&mut self, _core: &mut Core) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Handles a message read from the connection, returning a reply to send back.
+    ///
+    /// What a message is depends on the framing of the connection.
+    fn handle_message(&mut self, _message: Vec<u8>, _core: &mut Core) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Called when an error ends the connection, before the connection is closed.
+    fn on_error(&mut self, _error: &io::Error, _core: &mut Core) {}
+}
+
+impl<H: UdsHandler + ?Sized> UdsHandler for Box<H> {
+    fn acceptable(&mut self, credentials: &PeerCredentials) -> bool {
+        (**self).acceptable(credentials)
+    }
+
+    fn welcome_message(&mut self, core: &mut Core) -> Option<Vec<u8>> {
+        (**self).welcome_message(core)
+    }
+
+    fn handle_message(&mut self, message: Vec<u8>, core: &mut Core) -> Option<Vec<u8>> {
+        (**self).handle_message(message, core)
+    }
+
+    fn on_error(&mut self, error: &io::Error, core: &mut Core) {
+        (**self).on_error(error, core)
+    }
+}
+
+/// How the byte stream of a connection is split into messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Framing {
+    /// Every read is passed on as it is, and messages are sent as they are.
+    Raw,
+    /// Messages are lines ending in `\n`, which is stripped from incoming messages and
+    /// added to outgoing ones.
+    Lines,
+    /// Messages are prefixed with their length as a big-endian `u32`.
+    LengthPrefixed,
+}
+
+type Factory = Box<dyn Fn() -> Box<dyn UdsHandler>>;
+
+/// A server accepting connections on a unix domain socket.
+///
+/// The socket file is removed again when the server is dropped.
+pub struct UdsServer {
+    listener: UnixListener,
+    path: PathBuf,
+    factory: Factory,
+    object_id: ObjectId,
+    connections: Vec<ObjectId>,
+    framing: Framing,
+}
+
+impl UdsServer {
+    /// Starts a server accepting connections on a socket at the given path.
+    ///
+    /// Fails if the path already exists. `factory` creates a handler for every
+    /// accepted connection. Connections use raw framing until `set_framing` is called.
+    pub fn start<H, F>(path: impl AsRef<Path>, factory: F, core: &mut Core) -> Result<ObjectId>
+    where
+        H: 'static + UdsHandler,
+        F: 'static + Fn() -> H,
+    {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        let object_id = core.next_id();
+        let server = UdsServer {
+            listener,
+            path,
+            factory: Box::new(move || Box::new(factory())),
+            object_id,
+            connections: Vec::new(),
+            framing: Framing::Raw,
+        };
+        let evented = EventedFd(&server.listener.as_raw_fd());
+        core.register_reader(&evented, object_id, UdsServer::read_all)?;
+        core.add(server);
+        Ok(object_id)
+    }
+
+    /// Sets the framing of connections accepted from now on.
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    /// Returns the ids of the connections of this server.
+    pub fn connections(&self) -> &[ObjectId] {
+        &self.connections
+    }
+
+    fn read_all(&mut self, core: &mut Core) {
+        // forget about connections that have been closed since the last time.
+        self.connections
+            .retain(|id| core.get::<UdsConnection>(*id).is_some());
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) => {
+                    if e.kind() != ErrorKind::WouldBlock {
+                        error!("Error while trying to accept an incoming connection: {}", e);
+                        core.report_error(self.object_id, e);
+                        core.remove(self.object_id);
+                    }
+                    return;
+                }
+            };
+            let credentials = match PeerCredentials::of(&stream) {
+                Ok(credentials) => credentials,
+                Err(err) => {
+                    error!("Failed to get the credentials of a new connection: {}", err);
+                    continue;
+                }
+            };
+            let mut handler = (self.factory)();
+            if !handler.acceptable(&credentials) {
+                info!(
+                    "Connection from {:?} found unacceptable. Dropping it.",
+                    credentials
+                );
+                continue;
+            }
+            match UdsConnection::add(stream, credentials, handler, self.framing, core) {
+                Ok(Some(object_id)) => self.connections.push(object_id),
+                Ok(None) => {}
+                Err(err) => error!("Failed to register a new connection: {}", err),
+            }
+        }
+    }
+}
+
+impl Drop for UdsServer {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            error!("Failed to remove socket {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// A connection on a unix domain socket, accepted by a `UdsServer` or made with
+/// `connect`.
+///
+/// Incoming data is buffered until a full message has been read, and outgoing data is
+/// queued until the socket can take it.
+pub struct UdsConnection {
+    stream: UnixStream,
+    credentials: PeerCredentials,
+    handler: Box<dyn UdsHandler>,
+    object_id: ObjectId,
+    framing: Framing,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    // how much of `outgoing` has been written already.
+    written: usize,
+}
+
+impl UdsConnection {
+    /// Connects to the socket at the given path, adding the connection to the core.
+    ///
+    /// The connection is handled like the ones a `UdsServer` accepts, with `handler`
+    /// getting the messages that arrive on it. `acceptable` is not called, as the
+    /// peer was chosen by connecting to it.
+    pub fn connect<H>(
+        path: impl AsRef<Path>,
+        handler: H,
+        framing: Framing,
+        core: &mut Core,
+    ) -> Result<ObjectId>
+    where
+        H: 'static + UdsHandler,
+    {
+        let stream = UnixStream::connect(path)?;
+        let credentials = PeerCredentials::of(&stream)?;
+        UdsConnection::add(stream, credentials, Box::new(handler), framing, core)?.ok_or_else(
+            || {
+                io::Error::new(
+                    ErrorKind::ConnectionAborted,
+                    "Failed to send welcome message.",
+                )
+            },
+        )
+    }
+
+    /// Sends a message on the connection with the given id.
+    ///
+    /// The message is framed according to the framing of the connection. Returns
+    /// false if there is no such connection. This can't be used from the connection's
+    /// own handler, which should reply through `handle_message` instead.
+    pub fn send_to(connection_id: ObjectId, core: &mut Core, message: &[u8]) -> bool {
+        let result = match core.get_mut::<UdsConnection>(connection_id) {
+            Some(connection) => connection.send(message),
+            None => return false,
+        };
+        if let Err(err) = result {
+            if let Some(mut connection) = core.take_object::<UdsConnection>(connection_id) {
+                connection.fail(err, core);
+            }
+        }
+        true
+    }
+
+    /// Returns the credentials of the process at the other end.
+    pub fn peer_credentials(&self) -> PeerCredentials {
+        self.credentials
+    }
+
+    /// Returns the number of bytes waiting to be written to the socket.
+    pub fn queued_bytes(&self) -> usize {
+        self.outgoing.len() - self.written
+    }
+
+    // Sets up a connection and adds it to the core. Returns `None` if sending the
+    // welcome message failed, which has been reported to the handler.
+    fn add(
+        stream: UnixStream,
+        credentials: PeerCredentials,
+        mut handler: Box<dyn UdsHandler>,
+        framing: Framing,
+        core: &mut Core,
+    ) -> Result<Option<ObjectId>> {
+        stream.set_nonblocking(true)?;
+        let welcome_message = handler.welcome_message(core);
+        let object_id = core.next_id();
+        let mut connection = UdsConnection {
+            stream,
+            credentials,
+            handler,
+            object_id,
+            framing,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            written: 0,
+        };
+        if let Some(message) = welcome_message {
+            if let Err(err) = connection.send(&message) {
+                connection.fail(err, core);
+                return Ok(None);
+            }
+        }
+        core.register_reader_writer(
+            &EventedFd(&connection.stream.as_raw_fd()),
+            object_id,
+            UdsConnection::read_all,
+            UdsConnection::write_all,
+        )?;
+        core.add(connection);
+        Ok(Some(object_id))
+    }
+
+    fn read_all(&mut self, core: &mut Core) {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    info!("Connection closed.");
+                    core.remove(self.object_id);
+                    return;
+                }
+                Ok(n) => self.incoming.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    self.fail(e, core);
+                    return;
+                }
+            }
+            loop {
+                let message = match self.next_message() {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    Err(err) => {
+                        self.fail(err, core);
+                        return;
+                    }
+                };
+                if let Some(reply) = self.handler.handle_message(message, core) {
+                    if let Err(err) = self.send(&reply) {
+                        self.fail(err, core);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    // Takes the next complete message out of the incoming buffer.
+    fn next_message(&mut self) -> Result<Option<Vec<u8>>> {
+        let len = match self.framing {
+            Framing::Raw if self.incoming.is_empty() => return Ok(None),
+            Framing::Raw => return Ok(Some(mem::take(&mut self.incoming))),
+            Framing::Lines => match self.incoming.iter().position(|&b| b == b'\n') {
+                Some(pos) => {
+                    let mut line: Vec<u8> = self.incoming.drain(..=pos).collect();
+                    line.pop();
+                    return Ok(Some(line));
+                }
+                None => self.incoming.len(),
+            },
+            Framing::LengthPrefixed if self.incoming.len() < 4 => return Ok(None),
+            Framing::LengthPrefixed => {
+                let mut prefix = [0; 4];
+                prefix.copy_from_slice(&self.incoming[..4]);
+                let len = u32::from_be_bytes(prefix) as usize;
+                if self.incoming.len() >= 4 + len {
+                    let message = self.incoming[4..4 + len].to_vec();
+                    self.incoming.drain(..4 + len);
+                    return Ok(Some(message));
+                }
+                len
+            }
+        };
+        if len > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Incoming message is too long.",
+            ));
+        }
+        Ok(None)
+    }
+
+    // Queues the message and writes as much as possible. Returns an error if the
+    // connection should be dropped.
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        match self.framing {
+            Framing::Raw => self.outgoing.extend_from_slice(message),
+            Framing::Lines => {
+                self.outgoing.extend_from_slice(message);
+                self.outgoing.push(b'\n');
+            }
+            Framing::LengthPrefixed => {
+                let len = message.len();
+                if len > u32::MAX as usize {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "Outgoing message is too long.",
+                    ));
+                }
+                self.outgoing.extend_from_slice(&(len as u32).to_be_bytes());
+                self.outgoing.extend_from_slice(message);
+            }
+        }
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        while self.written < self.outgoing.len() {
+            match self.stream.write(&self.outgoing[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        // drop what has been written once it makes up most of the queue, so the queue
+        // doesn't grow forever while the socket keeps up.
+        if self.written > self.outgoing.len() / 2 {
+            self.outgoing.drain(..self.written);
+            self.written = 0;
+        }
+        Ok(())
+    }
+
+    // Reports an error that ends the connection and removes the connection.
+    fn fail(&mut self, err: io::Error, core: &mut Core) {
+        error!("IO error on unix socket connection: {}", err);
+        self.handler.on_error(&err, core);
+        core.report_error(self.object_id, &err);
+        core.remove(self.object_id);
+    }
+
+    fn write_all(&mut self, core: &mut Core) {
+        if let Err(err) = self.flush() {
+            self.fail(err, core);
+        }
+    }
+}
@@ -1,16 +1,23 @@
 use looper::Core;
-use looper_websocket::{WebSocketHandler, WebSocketServer};
+use looper_websocket::{Message, WebSocketHandler, WebSocketServer};
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 
 struct Client;
 
 impl WebSocketHandler for Client {
-    fn welcome_message(&mut self, _core: &mut Core) -> Option<String> {
-        Some(String::from("Hello there!"))
+    fn welcome_message(&mut self, _core: &mut Core) -> Option<Message> {
+        Some(Message::Text(String::from("Hello there!")))
     }
 
-    fn handle_message(&mut self, message: String, _core: &mut Core) -> Option<String> {
-        Some(format!("I heard you say: {}", message))
+    fn handle_message(&mut self, message: String, _core: &mut Core) -> Option<Message> {
+        Some(Message::Text(format!("I heard you say: {}", message)))
+    }
+
+    fn handle_binary(&mut self, message: Vec<u8>, _core: &mut Core) -> Option<Message> {
+        Some(Message::Text(format!(
+            "I heard you send {} bytes",
+            message.len()
+        )))
     }
 }
 
@@ -22,5 +29,5 @@ fn main() {
     let _server_id = WebSocketServer::start(web_socket_address, || Client, &mut core)
         .expect("Port 17771 expected to be available.");
 
-    core.run();
+    core.run().expect("Failed to run the event loop.");
 }
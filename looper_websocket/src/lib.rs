@@ -1,72 +1,565 @@
 use log::{debug, error, info, warn};
-use looper::{Core, ObjectId};
+use looper::{Core, LoopObject, ObjectId, TimerId};
 use mio::net::{TcpListener, TcpStream};
-use std::io::{ErrorKind, Result};
-use std::net::SocketAddr;
-use tungstenite::{server, Error as InnerSocketError, Message, WebSocket as InnerSocket};
+use mio::Token;
+use native_tls::{HandshakeError as TlsHandshakeError, MidHandshakeTlsStream, TlsStream};
+use net2::TcpBuilder;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Result};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tungstenite::handshake::server::NoCallback;
+use tungstenite::handshake::MidHandshake;
+use tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tungstenite::stream::Stream as InnerStream;
+use tungstenite::{
+    ClientHandshake, Error as InnerSocketError, HandshakeError, ServerHandshake,
+    WebSocket as InnerSocket,
+};
+use url::{Host, Url};
 
-pub trait WebSocketHandler {
+pub use native_tls::{Identity, TlsAcceptor};
+pub use tungstenite::protocol::frame::coding::CloseCode;
+pub use tungstenite::Error as WebSocketError;
+pub use tungstenite::Message;
+
+/// Identifies a connection, see `WebSocketHandler::on_open`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ConnectionInfo {
+    /// The id of the connection in the core, for use with `WebSocketControl`.
+    pub object_id: ObjectId,
+    pub peer_addr: SocketAddr,
+}
+
+/// Handles the messages of a websocket connection.
+///
+/// Handlers are kept by their connections, and can be reached from elsewhere through
+/// `WebSocketServer::with_handler`.
+pub trait WebSocketHandler: Any {
     fn acceptable(&mut self, _from_address: SocketAddr) -> bool {
         true
     }
 
-    fn welcome_message(&mut self, _core: &mut Core) -> Option<String> {
+    /// Called once the handshake is done and the connection is open, right before
+    /// `welcome_message`.
+    ///
+    /// This lets the handler learn the id of its connection, e.g. to register it
+    /// with the rest of the application so messages can be sent to it later on.
+    fn on_open(&mut self, _info: ConnectionInfo, _core: &mut Core) {}
+
+    /// Returns a message to send as soon as the connection is open.
+    fn welcome_message(&mut self, _core: &mut Core) -> Option<Message> {
         None
     }
 
-    fn handle_message(&mut self, _message: String, _core: &mut Core) -> Option<String> {
+    fn handle_message(&mut self, _message: String, _core: &mut Core) -> Option<Message> {
         None
     }
+
+    /// Handles a binary message, returning a reply to send back.
+    ///
+    /// Binary messages are ignored by default.
+    fn handle_binary(&mut self, _message: Vec<u8>, _core: &mut Core) -> Option<Message> {
+        None
+    }
+
+    /// Returns a handler that should take over the connection, if any.
+    ///
+    /// This is checked after every handled message, so a protocol can for example
+    /// switch to a different handler once an initial authentication handshake is
+    /// done. The new handler is type-erased, so it only needs to implement
+    /// `WebSocketHandler` and may itself be replaced later on.
+    fn replacement(&mut self) -> Option<Box<dyn WebSocketHandler>> {
+        None
+    }
+
+    /// Called when an error occurs on the connection.
+    ///
+    /// Errors are logged either way, this lets the handler react to them as well,
+    /// e.g. by counting them or cleaning up state kept elsewhere in the core. If the
+    /// error is fatal to the connection, the connection is closed after this returns.
+    fn on_error(&mut self, _error: &WebSocketError, _core: &mut Core) {}
+
+    /// Called when the connection has been closed with a closing handshake, by either
+    /// end.
+    ///
+    /// `code` and `reason` are what the other end sent in its close frame, with
+    /// `CloseCode::Status` if it didn't give a code. Connections that end because of
+    /// an error are reported to `on_error` instead.
+    fn on_close(&mut self, _code: CloseCode, _reason: &str, _core: &mut Core) {}
+
+    /// Called when all the messages that had to be queued for the connection have
+    /// been handed to the socket.
+    ///
+    /// Messages are queued when the other end doesn't read them as fast as they are
+    /// sent. A handler producing a lot of messages can hold back once
+    /// `WebSocket::queued_messages` grows, and carry on from here.
+    fn on_drained(&mut self, _core: &mut Core) {}
 }
 
-pub struct WebSocketServer<F> {
-    tcp_listener: TcpListener,
-    factory: F,
+impl<H: WebSocketHandler + ?Sized> WebSocketHandler for Box<H> {
+    fn acceptable(&mut self, from_address: SocketAddr) -> bool {
+        (**self).acceptable(from_address)
+    }
+
+    fn on_open(&mut self, info: ConnectionInfo, core: &mut Core) {
+        (**self).on_open(info, core)
+    }
+
+    fn welcome_message(&mut self, core: &mut Core) -> Option<Message> {
+        (**self).welcome_message(core)
+    }
+
+    fn handle_message(&mut self, message: String, core: &mut Core) -> Option<Message> {
+        (**self).handle_message(message, core)
+    }
+
+    fn handle_binary(&mut self, message: Vec<u8>, core: &mut Core) -> Option<Message> {
+        (**self).handle_binary(message, core)
+    }
+
+    fn replacement(&mut self) -> Option<Box<dyn WebSocketHandler>> {
+        (**self).replacement()
+    }
+
+    fn on_error(&mut self, error: &WebSocketError, core: &mut Core) {
+        (**self).on_error(error, core)
+    }
+
+    fn on_close(&mut self, code: CloseCode, reason: &str, core: &mut Core) {
+        (**self).on_close(code, reason, core)
+    }
+
+    fn on_drained(&mut self, core: &mut Core) {
+        (**self).on_drained(core)
+    }
+}
+
+/// What to do with a connection whose outgoing queue is full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued message to make room for the new one.
+    DropOldest,
+    /// Drop the new message.
+    DropNewest,
+    /// Close the connection.
+    Disconnect,
+}
+
+// pings sent to check that the other end is still there.
+#[derive(Clone, Copy, Debug)]
+struct Keepalive {
+    interval: Duration,
+    max_missed: u32,
+    // pings sent since anything was last heard from the other end.
+    missed: u32,
+    timer: Option<TimerId>,
+}
+
+impl Keepalive {
+    fn new(interval: Duration, max_missed: u32) -> Keepalive {
+        Keepalive {
+            interval,
+            max_missed,
+            missed: 0,
+            timer: None,
+        }
+    }
+}
+
+/// A limit that made a server stop accepting connections for a while, see
+/// `WebSocketServer::on_accept_limit`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AcceptLimit {
+    /// The server has as many connections as `set_max_connections` allows. Accepting
+    /// continues once one of them closes.
+    Connections,
+    /// The server has accepted as many connections as `set_accept_rate` allows for
+    /// now. Accepting continues once the current period is over.
+    Rate,
+}
+
+// the connections accepted in the current period, see `WebSocketServer::set_accept_rate`.
+#[derive(Clone, Copy, Debug)]
+struct AcceptRate {
+    max_accepts: usize,
+    period: Duration,
+    period_start: Instant,
+    accepted: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct QueueLimit {
+    max_messages: usize,
+    policy: OverflowPolicy,
+}
+
+type Factory = Box<dyn Fn() -> Box<dyn WebSocketHandler>>;
+
+type AcceptLimitCallback = Box<dyn FnMut(AcceptLimit, &mut Core)>;
+
+// a connection, encrypted or not.
+type Stream = InnerStream<TcpStream, TlsStream<TcpStream>>;
+
+pub struct WebSocketServer {
+    listeners: Vec<(TcpListener, Token)>,
+    // where accepting continues after yielding to the event loop, so one busy
+    // listener can't keep the others from being served.
+    next_listener: usize,
+    factory: Factory,
     object_id: ObjectId,
     sockets: Vec<ObjectId>,
+    tls_acceptor: Option<TlsAcceptor>,
+    queue_limit: Option<QueueLimit>,
+    keepalive: Option<Keepalive>,
+    config: WebSocketConfig,
+    max_accepts: Option<usize>,
+    // set when accepting stopped at the limit, to continue once other events are handled.
+    resume_timer: Option<TimerId>,
+    max_connections: Option<usize>,
+    accept_rate: Option<AcceptRate>,
+    on_accept_limit: Option<AcceptLimitCallback>,
+    // whether the listener's read interest is off because a limit was reached.
+    paused: bool,
+    // set while paused by the accept rate, to continue once the period is over.
+    rate_timer: Option<TimerId>,
 }
 
-impl<W, F> WebSocketServer<F>
-where
-    W: 'static + WebSocketHandler,
-    F: 'static + Fn() -> W,
-{
-    pub fn start(socket_address: SocketAddr, factory: F, core: &mut Core) -> Result<ObjectId> {
+impl WebSocketServer {
+    pub fn start<W, F>(socket_address: SocketAddr, factory: F, core: &mut Core) -> Result<ObjectId>
+    where
+        W: 'static + WebSocketHandler,
+        F: 'static + Fn() -> W,
+    {
         let tcp_listener = TcpListener::bind(&socket_address)?;
+        WebSocketServer::listen(vec![tcp_listener], None, factory, core)
+    }
+
+    /// Starts a server accepting connections on all of the given addresses, e.g. on
+    /// both `0.0.0.0` and `::` to serve IPv4 as well as IPv6 clients.
+    ///
+    /// The connections from all addresses are created by the same factory and are
+    /// handled as one, e.g. by `connections` and `broadcast_by_id`. Listeners on IPv6
+    /// addresses only accept IPv6 connections, so the same port can be used for both.
+    pub fn start_multi<W, F>(
+        socket_addresses: &[SocketAddr],
+        factory: F,
+        core: &mut Core,
+    ) -> Result<ObjectId>
+    where
+        W: 'static + WebSocketHandler,
+        F: 'static + Fn() -> W,
+    {
+        if socket_addresses.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "No addresses to listen on.",
+            ));
+        }
+        let tcp_listeners = socket_addresses
+            .iter()
+            .map(bind)
+            .collect::<Result<Vec<_>>>()?;
+        WebSocketServer::listen(tcp_listeners, None, factory, core)
+    }
+
+    /// Starts a server accepting `wss://` connections on the given address.
+    ///
+    /// The TLS handshake is done with `tls_acceptor` before the websocket handshake,
+    /// without blocking the event loop. From then on, connections work the same as
+    /// for `start`.
+    pub fn start_tls<W, F>(
+        socket_address: SocketAddr,
+        tls_acceptor: TlsAcceptor,
+        factory: F,
+        core: &mut Core,
+    ) -> Result<ObjectId>
+    where
+        W: 'static + WebSocketHandler,
+        F: 'static + Fn() -> W,
+    {
+        let tcp_listener = TcpListener::bind(&socket_address)?;
+        WebSocketServer::listen(vec![tcp_listener], Some(tls_acceptor), factory, core)
+    }
+
+    /// Starts a server accepting connections on an already bound listener.
+    ///
+    /// This allows the listener to be created elsewhere, e.g. passed in through
+    /// systemd socket activation or set up with special socket options. The listener
+    /// is switched to non-blocking mode.
+    pub fn from_listener<W, F>(
+        listener: std::net::TcpListener,
+        factory: F,
+        core: &mut Core,
+    ) -> Result<ObjectId>
+    where
+        W: 'static + WebSocketHandler,
+        F: 'static + Fn() -> W,
+    {
+        let tcp_listener = TcpListener::from_std(listener)?;
+        WebSocketServer::listen(vec![tcp_listener], None, factory, core)
+    }
+
+    fn listen<W, F>(
+        tcp_listeners: Vec<TcpListener>,
+        tls_acceptor: Option<TlsAcceptor>,
+        factory: F,
+        core: &mut Core,
+    ) -> Result<ObjectId>
+    where
+        W: 'static + WebSocketHandler,
+        F: 'static + Fn() -> W,
+    {
         let object_id = core.next_id();
-        core.register_reader(&tcp_listener, object_id, WebSocketServer::<F>::read_all);
+        let mut listeners = Vec::new();
+        for tcp_listener in tcp_listeners {
+            match core.register_reader(&tcp_listener, object_id, WebSocketServer::read_all) {
+                Ok(token) => listeners.push((tcp_listener, token)),
+                Err(err) => {
+                    core.unregister_object(object_id);
+                    return Err(err);
+                }
+            }
+        }
+        core.on_shutdown(object_id, WebSocketServer::stop);
         core.add(WebSocketServer {
-            tcp_listener,
-            factory,
+            listeners,
+            next_listener: 0,
+            factory: Box::new(move || Box::new(factory())),
             object_id,
             sockets: Vec::new(),
+            tls_acceptor,
+            queue_limit: None,
+            keepalive: None,
+            config: WebSocketConfig::default(),
+            max_accepts: None,
+            resume_timer: None,
+            max_connections: None,
+            accept_rate: None,
+            on_accept_limit: None,
+            paused: false,
+            rate_timer: None,
         });
         Ok(object_id)
     }
 
-    pub fn broadcast(&self, core: &mut Core, message: String) {
-        for id in &self.sockets {
-            if let Some(socket) = core.get_mut::<WebSocket<W>>(*id) {
-                socket
-                    .inner_socket
-                    .write_message(Message::Text(message.clone()))
-                    .unwrap();
+    /// Limits the number of messages queued up for sending on each connection.
+    ///
+    /// Messages are queued when a client doesn't read them as fast as they are sent,
+    /// and without a limit a slow client can make the queue grow without bounds.
+    /// `policy` decides what happens once `max_messages` are queued. The limit
+    /// applies to connections accepted after it is set.
+    pub fn set_queue_limit(&mut self, max_messages: usize, policy: OverflowPolicy) {
+        self.queue_limit = Some(QueueLimit {
+            max_messages,
+            policy,
+        });
+    }
+
+    /// Pings every connection after each `interval`, closing the ones that haven't
+    /// been heard from for `max_missed` pings in a row.
+    ///
+    /// This gets rid of connections whose other end went away without closing them,
+    /// e.g. because the network went down. A connection that is closed this way fails
+    /// with an `ErrorKind::TimedOut` error, which is passed to the handler's
+    /// `on_error`. Applies to connections accepted after it is set, see
+    /// `WebSocket::set_keepalive` for single connections.
+    pub fn set_keepalive(&mut self, interval: Duration, max_missed: u32) {
+        self.keepalive = Some(Keepalive::new(interval, max_missed));
+    }
+
+    /// Limits the size of the messages received on each connection.
+    ///
+    /// A connection that receives a larger message sends a close frame with
+    /// `CloseCode::Size` and is dropped, with the error passed to the handler's
+    /// `on_error`. `None` lifts the limit, which
+    /// is 64 MiB by default. Applies to connections accepted after it is set, see
+    /// `WebSocket::set_max_message_size` for single connections.
+    pub fn set_max_message_size(&mut self, max_size: Option<usize>) {
+        set_max_message_size(&mut self.config, max_size);
+    }
+
+    /// Limits the number of connections accepted each time the listener wakes up.
+    ///
+    /// By default connections are accepted until there are no more waiting, which
+    /// during a burst of connection attempts can hold up the IO of existing
+    /// connections for a long time. With a limit, the server goes back to the event
+    /// loop after accepting `max_accepts` connections and continues accepting once the
+    /// other pending events have been handled. A limit of zero is treated as one.
+    pub fn set_max_accepts_per_wakeup(&mut self, max_accepts: usize) {
+        self.max_accepts = Some(max_accepts.max(1));
+    }
+
+    /// Limits the number of connections the server has open at the same time.
+    ///
+    /// Once the limit is reached the server stops listening for new connections, which
+    /// wait in the listener's backlog until one of the open connections closes,
+    /// instead of using up file descriptors. `None` lifts the limit, which is the
+    /// default.
+    pub fn set_max_connections(&mut self, max_connections: Option<usize>) {
+        self.max_connections = max_connections;
+    }
+
+    /// Limits the server to accepting `max_accepts` connections per `period`.
+    ///
+    /// Once the limit is reached the server stops listening for new connections until
+    /// the current period is over, so a flood of connection attempts is spread out
+    /// over time. Connections found unacceptable by the handler count as well. A
+    /// limit of zero is treated as one.
+    pub fn set_accept_rate(&mut self, max_accepts: usize, period: Duration) {
+        self.accept_rate = Some(AcceptRate {
+            max_accepts: max_accepts.max(1),
+            period,
+            period_start: Instant::now(),
+            accepted: 0,
+        });
+    }
+
+    /// Makes `f` be called whenever the server stops accepting connections because
+    /// of one of its limits, e.g. to log it or to shed load elsewhere.
+    pub fn on_accept_limit<F>(&mut self, f: F)
+    where
+        F: 'static + FnMut(AcceptLimit, &mut Core),
+    {
+        self.on_accept_limit = Some(Box::new(f));
+    }
+
+    /// Returns the ids of the connections of this server.
+    pub fn connections(&self) -> &[ObjectId] {
+        &self.sockets
+    }
+
+    /// Stops the server with the given id, without affecting the rest of the core.
+    ///
+    /// The listeners are closed so no more connections are accepted, and all of the
+    /// server's connections are closed with `CloseCode::Away` once the messages
+    /// queued for them have been sent. This works from the handlers of the server's
+    /// connections too.
+    pub fn shutdown(server_id: ObjectId, core: &mut Core) {
+        match core.take_object::<WebSocketServer>(server_id) {
+            Some(mut server) => server.stop(core),
+            // the server is borrowed by its own callback.
+            None if core.contains(server_id) => core.post(server_id, WebSocketServer::stop),
+            None => {}
+        }
+    }
+
+    /// Sends a message to all connections of the server with the given id.
+    ///
+    /// This can be called from anywhere with access to the core, including the
+    /// handlers of the server's connections. The connection whose handler is running
+    /// is borrowed by it and skipped, but the handler can reply through its return
+    /// value. Connections that have closed are forgotten. Returns false if there is no
+    /// such server.
+    pub fn broadcast_by_id(server_id: ObjectId, core: &mut Core, message: Message) -> bool {
+        let mut sockets = match core.get_mut::<WebSocketServer>(server_id) {
+            Some(server) => std::mem::take(&mut server.sockets),
+            None => return false,
+        };
+        sockets.retain(|id| core.contains(*id));
+        for id in &sockets {
+            let result = match core.get_mut::<WebSocket>(*id) {
+                Some(socket) => socket.send(message.clone()),
+                None => continue,
+            };
+            if let Err(err) = result {
+                if let Some(mut socket) = core.take_object::<WebSocket>(*id) {
+                    socket.fail(err, core);
+                }
             }
         }
+        if let Some(server) = core.get_mut::<WebSocketServer>(server_id) {
+            server.sockets = sockets;
+        }
+        true
+    }
+
+    /// Calls `f` with the handler of the connection with the given id, so state kept
+    /// in the handler can be looked at or changed from other objects in the core.
+    ///
+    /// `W` has to be the type of the handler, which is that of the replacement if the
+    /// handler has been replaced. Returns `None` if there is no such connection, if
+    /// its handler is of another type, or while the handler is being called. This
+    /// works for connections made with `WebSocketClient` as well.
+    pub fn with_handler<W, R, F>(connection_id: ObjectId, core: &mut Core, f: F) -> Option<R>
+    where
+        W: WebSocketHandler,
+        F: FnOnce(&mut W) -> R,
+    {
+        let socket = core.get_mut::<WebSocket>(connection_id)?;
+        let handler: &mut dyn Any = &mut *socket.handler;
+        handler.downcast_mut::<W>().map(f)
+    }
+
+    // stops accepting connections when the core shuts down, and closes the open ones
+    // once the messages queued for them have been sent.
+    fn stop(&mut self, core: &mut Core) {
+        info!("Shutting down websocket server.");
+        for id in mem::take(&mut self.sockets) {
+            WebSocketControl::new(id).close(core, CloseCode::Away, "Server shutting down.");
+        }
+        core.remove(self.object_id);
     }
 
     fn read_all(&mut self, core: &mut Core) {
+        // forget about connections that have been closed since the last time.
+        self.sockets
+            .retain(|id| core.get::<WebSocket>(*id).is_some());
+        if let Some(timer_id) = self.resume_timer.take() {
+            core.cancel_timer(timer_id);
+        }
+        // every listener is tried, as it isn't known which of them woke us up.
+        let mut accepted = 0;
+        for i in 0..self.listeners.len() {
+            let index = (self.next_listener + i) % self.listeners.len();
+            if !self.accept_all(index, &mut accepted, core) {
+                return;
+            }
+        }
+    }
+
+    // accepts the connections waiting on the listener with the given index. Returns
+    // false if the server stopped accepting for now.
+    fn accept_all(&mut self, index: usize, accepted: &mut usize, core: &mut Core) -> bool {
         loop {
-            let (tcp_stream, address) = match self.tcp_listener.accept() {
+            if let Some(limit) = self.accept_limit() {
+                self.pause(limit, core);
+                return false;
+            }
+            if self.max_accepts == Some(*accepted) {
+                // the listener is edge-triggered, so it won't wake us up again for the
+                // connections that are still waiting.
+                debug!(
+                    "Accepted {} connections, yielding to the event loop.",
+                    accepted
+                );
+                let timer_id = core.set_timeout(
+                    Duration::from_secs(0),
+                    self.object_id,
+                    WebSocketServer::read_all,
+                );
+                self.resume_timer = Some(timer_id);
+                self.next_listener = index;
+                return false;
+            }
+            let (tcp_stream, address) = match self.listeners[index].0.accept() {
                 Ok((t, a)) => (t, a),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return true,
                 Err(ref e) => {
-                    if e.kind() != ErrorKind::WouldBlock {
-                        error!("Error while trying to accept an incoming connection: {}", e);
-                        core.remove(self.object_id);
-                    }
-                    return;
+                    error!("Error while trying to accept an incoming connection: {}", e);
+                    core.report_error(self.object_id, e);
+                    core.remove(self.object_id);
+                    return false;
                 }
             };
+            // only connections count, so listeners with nothing waiting don't make
+            // the server yield for no reason.
+            *accepted += 1;
+            if let Some(rate) = &mut self.accept_rate {
+                rate.accepted += 1;
+            }
             let mut handler = (self.factory)();
             if !handler.acceptable(address) {
                 info!(
@@ -75,56 +568,445 @@ where
                 );
                 continue; // just drop the tcp stream
             }
-            let mut inner_socket = match server::accept(tcp_stream) {
-                Ok(inner_socket) => inner_socket,
-                Err(err) => {
-                    error!("Failed to open a new websocket: {}", err);
-                    continue;
-                }
-            };
-            if let Some(message) = handler.welcome_message(core) {
-                inner_socket.write_message(Message::Text(message)).unwrap();
-            }
             let object_id = core.next_id();
-            core.register_reader_writer(
-                inner_socket.get_ref(),
+            let result = core.register_reader_writer(
+                &tcp_stream,
                 object_id,
-                WebSocket::<W>::read_all,
-                WebSocket::<W>::write_all,
+                WebSocket::read_all,
+                WebSocket::write_all,
             );
-            core.add(WebSocket {
-                inner_socket,
+            if let Err(err) = result {
+                error!("Failed to register a new websocket: {}", err);
+                continue;
+            }
+            let state = match &self.tls_acceptor {
+                Some(tls_acceptor) => State::TlsStarting(tls_acceptor.clone(), tcp_stream),
+                None => State::Accepting(ServerHandshake::start(
+                    InnerStream::Plain(tcp_stream),
+                    NoCallback,
+                    Some(self.config),
+                )),
+            };
+            core.add_loop_object(WebSocket {
+                state,
                 handler,
                 object_id,
+                server_id: Some(self.object_id),
+                peer_addr: address,
+                outgoing: VecDeque::new(),
+                queue_limit: self.queue_limit,
+                keepalive: self.keepalive,
+                config: self.config,
+                dropped_messages: 0,
+                pending_close: None,
             });
             self.sockets.push(object_id);
         }
     }
+
+    // returns the limit that keeps the server from accepting another connection now.
+    fn accept_limit(&mut self) -> Option<AcceptLimit> {
+        if let Some(max_connections) = self.max_connections {
+            if self.sockets.len() >= max_connections {
+                return Some(AcceptLimit::Connections);
+            }
+        }
+        let rate = self.accept_rate.as_mut()?;
+        let now = Instant::now();
+        if now >= rate.period_start + rate.period {
+            rate.period_start = now;
+            rate.accepted = 0;
+        }
+        if rate.accepted >= rate.max_accepts {
+            return Some(AcceptLimit::Rate);
+        }
+        None
+    }
+
+    fn pause(&mut self, limit: AcceptLimit, core: &mut Core) {
+        if !self.paused {
+            debug!(
+                "Reached {:?} limit, no longer accepting connections.",
+                limit
+            );
+            if let Err(err) = self.set_listening(false, core) {
+                error!("Failed to stop listening for connections: {}", err);
+                return;
+            }
+            self.paused = true;
+        }
+        if let (AcceptLimit::Rate, None) = (limit, self.rate_timer) {
+            let rate = self.accept_rate.unwrap();
+            let timeout =
+                (rate.period_start + rate.period).saturating_duration_since(Instant::now());
+            let timer_id = core.set_timeout(timeout, self.object_id, |server: &mut Self, core| {
+                server.rate_timer = None;
+                server.resume(core);
+            });
+            self.rate_timer = Some(timer_id);
+        }
+        if let Some(f) = &mut self.on_accept_limit {
+            f(limit, core);
+        }
+    }
+
+    // starts accepting connections again, unless a limit is still reached.
+    fn resume(&mut self, core: &mut Core) {
+        if !self.paused {
+            return;
+        }
+        if let Err(err) = self.set_listening(true, core) {
+            error!("Failed to listen for connections again: {}", err);
+            return;
+        }
+        self.paused = false;
+        debug!("Accepting connections again.");
+        // connections may have been waiting all along, which edge-triggered
+        // notifications don't tell about.
+        self.read_all(core);
+    }
+
+    fn set_listening(&self, enabled: bool, core: &mut Core) -> Result<()> {
+        for (tcp_listener, token) in &self.listeners {
+            core.set_read_interest(tcp_listener, *token, enabled)?;
+        }
+        Ok(())
+    }
+
+    fn connection_closed(&mut self, connection_id: ObjectId, core: &mut Core) {
+        self.sockets.retain(|id| *id != connection_id);
+        if self.rate_timer.is_none() {
+            self.resume(core);
+        }
+    }
 }
 
-struct WebSocket<W> {
-    inner_socket: InnerSocket<TcpStream>,
-    handler: W,
+// binds a listener like `TcpListener::bind`, except that IPv6 listeners leave IPv4 to
+// others.
+fn bind(socket_address: &SocketAddr) -> Result<TcpListener> {
+    let builder = match socket_address {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => {
+            let builder = TcpBuilder::new_v6()?;
+            builder.only_v6(true)?;
+            builder
+        }
+    };
+    if cfg!(unix) {
+        builder.reuse_address(true)?;
+    }
+    let listener = builder.bind(socket_address)?.listen(1024)?;
+    TcpListener::from_std(listener)
+}
+
+fn set_max_message_size(config: &mut WebSocketConfig, max_size: Option<usize>) {
+    config.max_message_size = max_size;
+    // a single frame can't be larger than the message it is part of.
+    config.max_frame_size = max_size;
+}
+
+/// Connects to websocket servers.
+pub struct WebSocketClient;
+
+impl WebSocketClient {
+    /// Connects to the websocket server at `url`, e.g. `ws://localhost:8080/chat`.
+    ///
+    /// The connection is added to the core right away and its id returned, while
+    /// connecting and the handshake happen in the background. Once the connection is
+    /// open, the handler's `welcome_message` is sent to the server, and errors from
+    /// then on are reported to `on_error` like for server connections. Host names are
+    /// looked up with `looper_dns::resolve`, so nothing blocks. Only `ws://` URLs are
+    /// supported, `wss://` ones fail with `ErrorKind::Unsupported`.
+    pub fn connect<W>(url: &str, handler: W, core: &mut Core) -> Result<ObjectId>
+    where
+        W: 'static + WebSocketHandler,
+    {
+        let url = Url::parse(url).map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+        match url.scheme() {
+            "ws" => {}
+            "wss" => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "wss:// URLs are not supported.",
+                ))
+            }
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "Not a websocket URL.",
+                ))
+            }
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        let (ip, domain) = match url.host() {
+            Some(Host::Domain(domain)) => (None, domain.to_string()),
+            Some(Host::Ipv4(ip)) => (Some(IpAddr::V4(ip)), String::new()),
+            Some(Host::Ipv6(ip)) => (Some(IpAddr::V6(ip)), String::new()),
+            None => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "The URL has no host.",
+                ))
+            }
+        };
+        let object_id = core.next_id();
+        let mut socket = WebSocket {
+            state: State::Resolving(url),
+            handler: Box::new(handler),
+            object_id,
+            server_id: None,
+            peer_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+            outgoing: VecDeque::new(),
+            queue_limit: None,
+            keepalive: None,
+            config: WebSocketConfig::default(),
+            dropped_messages: 0,
+            pending_close: None,
+        };
+        if let Some(ip) = ip {
+            socket.start_connecting(SocketAddr::new(ip, port), core)?;
+            return Ok(core.add(socket));
+        }
+        // the lookup reports back to the connection, so it has to be added first.
+        core.add(socket);
+        if let Err(err) = looper_dns::resolve(&domain, object_id, WebSocket::resolved, core) {
+            core.remove(object_id);
+            return Err(err);
+        }
+        Ok(object_id)
+    }
+}
+
+/// Controls the websocket connection with a given id, from anywhere with access to the
+/// core.
+///
+/// Unlike going through the connection directly, this works from the connection's own
+/// handler as well: while the handler runs, what it asks for is done once it returns.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct WebSocketControl(ObjectId);
+
+impl WebSocketControl {
+    pub fn new(connection_id: ObjectId) -> WebSocketControl {
+        WebSocketControl(connection_id)
+    }
+
+    pub fn id(self) -> ObjectId {
+        self.0
+    }
+
+    /// Queues a message to be sent on the connection.
+    ///
+    /// Returns false if there is no such connection.
+    pub fn send(self, core: &mut Core, message: Message) -> bool {
+        self.apply(core, move |socket| socket.send(message))
+    }
+
+    /// Starts closing the connection, sending a close frame with the given code and
+    /// reason once the messages queued before it have been sent.
+    ///
+    /// The connection is removed once the other end has replied, at which point the
+    /// handler's `on_close` is called. Returns false if there is no such connection.
+    pub fn close(self, core: &mut Core, code: CloseCode, reason: &str) -> bool {
+        let frame = CloseFrame {
+            code,
+            reason: reason.to_owned().into(),
+        };
+        self.apply(core, move |socket| socket.start_close(frame))
+    }
+
+    /// Returns the number of messages queued for sending on the connection.
+    ///
+    /// Returns `None` if there is no such connection, or while its handler is being
+    /// called.
+    pub fn queued_messages(self, core: &Core) -> Option<usize> {
+        core.get::<WebSocket>(self.0)
+            .map(WebSocket::queued_messages)
+    }
+
+    fn apply<F>(self, core: &mut Core, f: F) -> bool
+    where
+        F: 'static + FnOnce(&mut WebSocket) -> std::result::Result<(), InnerSocketError>,
+    {
+        if core.get::<WebSocket>(self.0).is_none() {
+            if !core.contains(self.0) {
+                return false;
+            }
+            // the connection is borrowed by its own handler.
+            core.post(self.0, move |socket: &mut WebSocket, core| {
+                if let Err(err) = f(socket) {
+                    socket.fail(err, core);
+                }
+            });
+            return true;
+        }
+        let result = f(core.get_mut::<WebSocket>(self.0).unwrap());
+        if let Err(err) = result {
+            if let Some(mut socket) = core.take_object::<WebSocket>(self.0) {
+                socket.fail(err, core);
+            }
+        }
+        true
+    }
+}
+
+enum State {
+    // the TLS handshake of a server connection hasn't been started yet.
+    TlsStarting(TlsAcceptor, TcpStream),
+    TlsAccepting(MidHandshakeTlsStream<TcpStream>),
+    Accepting(MidHandshake<ServerHandshake<Stream, NoCallback>>),
+    // the host of a client connection is being looked up.
+    Resolving(Url),
+    Connecting(MidHandshake<ClientHandshake<Stream>>),
+    Open(InnerSocket<Stream>),
+    // left behind by a failed handshake.
+    Failed,
+    // the connection has ended and is about to be dropped.
+    Closed,
+}
+
+/// A websocket connection, accepted by a `WebSocketServer` or made with
+/// `WebSocketClient`.
+///
+/// Connections are objects in the core, and can be looked up by the ids listed by
+/// `WebSocketServer::connections`. Messages sent before the handshake is done are
+/// queued until it is.
+pub struct WebSocket {
+    state: State,
+    handler: Box<dyn WebSocketHandler>,
     object_id: ObjectId,
+    // the server that accepted the connection, if any.
+    server_id: Option<ObjectId>,
+    peer_addr: SocketAddr,
+    // messages waiting for the socket to become writable.
+    outgoing: VecDeque<Message>,
+    queue_limit: Option<QueueLimit>,
+    keepalive: Option<Keepalive>,
+    config: WebSocketConfig,
+    dropped_messages: u64,
+    // a close frame to send once the outgoing queue is empty.
+    pending_close: Option<CloseFrame<'static>>,
 }
 
-impl<W> WebSocket<W>
-where
-    W: 'static + WebSocketHandler,
-{
+impl WebSocket {
+    /// Returns the address of the other end of the connection.
+    ///
+    /// For a client connection, the address is unspecified until the host name has
+    /// been looked up.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Returns the number of messages waiting for the socket to become writable.
+    pub fn queued_messages(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    /// Pings the other end after each `interval`, closing the connection if it hasn't
+    /// been heard from for `max_missed` pings in a row.
+    ///
+    /// Replaces any keepalive set before, e.g. by `WebSocketServer::set_keepalive`.
+    /// Pinging starts once the handshake is done.
+    pub fn set_keepalive(&mut self, interval: Duration, max_missed: u32, core: &mut Core) {
+        if let Some(timer_id) = self.keepalive.and_then(|k| k.timer) {
+            core.cancel_timer(timer_id);
+        }
+        self.keepalive = Some(Keepalive::new(interval, max_missed));
+        if let State::Open(_) = self.state {
+            self.start_keepalive(core);
+        }
+    }
+
+    /// Limits the size of the messages received on this connection.
+    ///
+    /// Replaces the limit set by `WebSocketServer::set_max_message_size`, see there.
+    pub fn set_max_message_size(&mut self, max_size: Option<usize>) {
+        set_max_message_size(&mut self.config, max_size);
+        if let State::Open(socket) = &mut self.state {
+            let config = self.config;
+            socket.set_config(|c| *c = config);
+        }
+    }
+
+    /// Returns how many messages have been dropped because the outgoing queue of
+    /// this connection was full.
+    pub fn dropped_messages(&self) -> u64 {
+        self.dropped_messages
+    }
+
+    fn resolved(&mut self, addresses: Result<Vec<IpAddr>>, core: &mut Core) {
+        let result = addresses.and_then(|addresses| {
+            let ip = addresses
+                .into_iter()
+                .next()
+                .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "The host has no addresses."))?;
+            let address = SocketAddr::new(ip, self.peer_addr.port());
+            self.start_connecting(address, core)
+        });
+        if let Err(err) = result {
+            self.fail(InnerSocketError::Io(err), core);
+        }
+    }
+
+    // connects to the server of a client connection whose host is known, starting the
+    // handshake once the connection is writable.
+    fn start_connecting(&mut self, address: SocketAddr, core: &mut Core) -> Result<()> {
+        let url = match &self.state {
+            State::Resolving(url) => url.clone(),
+            _ => return Ok(()),
+        };
+        let tcp_stream = TcpStream::connect(&address)?;
+        core.register_reader_writer(
+            &tcp_stream,
+            self.object_id,
+            WebSocket::read_all,
+            WebSocket::write_all,
+        )?;
+        let stream = InnerStream::Plain(tcp_stream);
+        let handshake = ClientHandshake::start(stream, url.into(), Some(self.config));
+        self.state = State::Connecting(handshake);
+        self.peer_addr = address;
+        Ok(())
+    }
+
     fn read_all(&mut self, core: &mut Core) {
+        match self.handshake(core) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(err) => {
+                self.fail(err, core);
+                return;
+            }
+        }
         loop {
-            match self.inner_socket.read_message() {
-                Err(InnerSocketError::ConnectionClosed(_)) => {
-                    info!("Connection closed.");
-                    core.remove(self.object_id);
+            let result = match &mut self.state {
+                State::Open(socket) => socket.read_message(),
+                _ => return,
+            };
+            match result {
+                Err(InnerSocketError::Io(ref err)) if err.kind() == ErrorKind::WouldBlock => {
+                    return;
+                }
+                Err(err @ InnerSocketError::ConnectionClosed(_))
+                | Err(err @ InnerSocketError::Io(_)) => {
+                    self.fail(err, core);
                     return;
                 }
-                Err(InnerSocketError::Io(err)) => {
-                    if err.kind() != ErrorKind::WouldBlock {
-                        error!("IO error while trying to read incoming message: {}", err);
-                        core.remove(self.object_id);
+                Err(err @ InnerSocketError::Capacity(_)) => {
+                    // the rest of the message can't be skipped, so the connection
+                    // can't be read from anymore.
+                    if let State::Open(socket) = &mut self.state {
+                        let frame = CloseFrame {
+                            code: CloseCode::Size,
+                            reason: "Message too big.".into(),
+                        };
+                        let result = socket
+                            .close(Some(frame))
+                            .and_then(|()| socket.write_pending());
+                        if let Err(err) = result {
+                            debug!("Failed to send close frame: {}", err);
+                        }
                     }
+                    self.fail(err, core);
                     return;
                 }
                 Err(err) => {
@@ -132,33 +1014,252 @@ where
                         "Non-fatal error while trying to read an incoming message: {}",
                         err
                     );
+                    self.handler.on_error(&err, core);
                 }
-                Ok(Message::Text(message)) => {
-                    if let Some(reply) = self.handler.handle_message(message, core) {
-                        self.inner_socket
-                            .write_message(Message::Text(reply))
-                            .unwrap();
+                Ok(message) => {
+                    if let Some(keepalive) = &mut self.keepalive {
+                        keepalive.missed = 0;
+                    }
+                    let reply = match message {
+                        Message::Text(message) => self.handler.handle_message(message, core),
+                        Message::Binary(message) => self.handler.handle_binary(message, core),
+                        // pings and pongs are taken care of by tungstenite.
+                        _ => continue,
+                    };
+                    if let Some(reply) = reply {
+                        if let Err(err) = self.send(reply) {
+                            self.fail(err, core);
+                            return;
+                        }
+                    }
+                    if let Some(replacement) = self.handler.replacement() {
+                        debug!("Replacing the handler of connection {:?}.", self.object_id);
+                        self.handler = replacement;
                     }
                 }
-                Ok(_other) => warn!("Received and ignored message because it was not text-type."),
             }
         }
     }
 
-    fn write_all(&mut self, core: &mut Core) {
-        match self.inner_socket.write_pending() {
-            Err(InnerSocketError::Io(err)) => {
-                if err.kind() != ErrorKind::WouldBlock {
-                    error!("Error while trying to write outgoing message: {}", err);
-                    core.remove(self.object_id);
+    // Continues the handshake if it isn't done yet, sending the welcome message once it
+    // is. Returns whether the connection is open.
+    fn handshake(&mut self, core: &mut Core) -> std::result::Result<bool, InnerSocketError> {
+        let socket = loop {
+            let tls_result = match mem::replace(&mut self.state, State::Failed) {
+                State::TlsStarting(tls_acceptor, tcp_stream) => tls_acceptor.accept(tcp_stream),
+                State::TlsAccepting(handshake) => handshake.handshake(),
+                State::Accepting(handshake) => match handshake.handshake() {
+                    Ok(socket) => break socket,
+                    Err(HandshakeError::Interrupted(handshake)) => {
+                        self.state = State::Accepting(handshake);
+                        return Ok(false);
+                    }
+                    Err(HandshakeError::Failure(err)) => return Err(err),
+                },
+                State::Connecting(handshake) => match handshake.handshake() {
+                    Ok((socket, _response)) => break socket,
+                    Err(HandshakeError::Interrupted(handshake)) => {
+                        self.state = State::Connecting(handshake);
+                        return Ok(false);
+                    }
+                    Err(HandshakeError::Failure(err)) => return Err(err),
+                },
+                state => {
+                    self.state = state;
+                    return Ok(matches!(self.state, State::Open(_)));
+                }
+            };
+            // the websocket handshake follows right after the TLS one.
+            match tls_result {
+                Ok(tls_stream) => {
+                    let stream = InnerStream::Tls(tls_stream);
+                    let config = Some(self.config);
+                    self.state =
+                        State::Accepting(ServerHandshake::start(stream, NoCallback, config));
+                }
+                Err(TlsHandshakeError::WouldBlock(handshake)) => {
+                    self.state = State::TlsAccepting(handshake);
+                    return Ok(false);
+                }
+                Err(TlsHandshakeError::Failure(err)) => return Err(err.into()),
+            }
+        };
+        debug!(
+            "Websocket handshake done for connection {:?}.",
+            self.object_id
+        );
+        self.state = State::Open(socket);
+        let info = ConnectionInfo {
+            object_id: self.object_id,
+            peer_addr: self.peer_addr,
+        };
+        self.handler.on_open(info, core);
+        self.start_keepalive(core);
+        if let Some(message) = self.handler.welcome_message(core) {
+            self.outgoing.push_front(message);
+        }
+        self.flush()?;
+        Ok(true)
+    }
+
+    fn start_keepalive(&mut self, core: &mut Core) {
+        let object_id = self.object_id;
+        if let Some(keepalive) = &mut self.keepalive {
+            // dropped along with the connection.
+            let timer_id = core.set_interval(keepalive.interval, object_id, WebSocket::ping);
+            keepalive.timer = Some(timer_id);
+        }
+    }
+
+    fn ping(&mut self, core: &mut Core) {
+        let keepalive = match &mut self.keepalive {
+            Some(keepalive) => keepalive,
+            None => return,
+        };
+        if keepalive.missed >= keepalive.max_missed {
+            info!(
+                "Connection {:?} missed {} pings, closing it.",
+                self.object_id, keepalive.missed
+            );
+            let err = io::Error::new(ErrorKind::TimedOut, "The other end stopped responding.");
+            self.fail(InnerSocketError::Io(err), core);
+            return;
+        }
+        keepalive.missed += 1;
+        if let Err(err) = self.send(Message::Ping(Vec::new())) {
+            self.fail(err, core);
+        }
+    }
+
+    // Queues the message and sends as much as possible. Returns an error if the
+    // connection should be dropped.
+    fn send(&mut self, message: Message) -> std::result::Result<(), InnerSocketError> {
+        self.outgoing.push_back(message);
+        self.flush()?;
+        if let Some(limit) = self.queue_limit {
+            if self.outgoing.len() > limit.max_messages {
+                self.dropped_messages += 1;
+                match limit.policy {
+                    OverflowPolicy::DropOldest => {
+                        self.outgoing.pop_front();
+                    }
+                    OverflowPolicy::DropNewest => {
+                        self.outgoing.pop_back();
+                    }
+                    OverflowPolicy::Disconnect => {
+                        let message = self.outgoing.pop_back().unwrap();
+                        return Err(InnerSocketError::SendQueueFull(message));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Hands queued messages to the socket until it would block. The socket keeps at
+    // most one message buffered itself, the rest stay in our own queue where they
+    // are subject to the queue limit. Returns an error if the connection should be
+    // dropped, errors that aren't fatal to the connection are only logged.
+    fn flush(&mut self) -> std::result::Result<(), InnerSocketError> {
+        let socket = match &mut self.state {
+            State::Open(socket) => socket,
+            _ => return Ok(()),
+        };
+        loop {
+            let mut result = socket.write_pending();
+            if result.is_ok() {
+                match self.outgoing.pop_front() {
+                    Some(message) => result = socket.write_message(message),
+                    None => match self.pending_close.take() {
+                        Some(frame) => result = socket.close(Some(frame)),
+                        None => return Ok(()),
+                    },
                 }
             }
-            Err(InnerSocketError::ConnectionClosed(_)) => {
+            match result {
+                Ok(()) => {}
+                Err(InnerSocketError::Io(ref err)) if err.kind() == ErrorKind::WouldBlock => {
+                    return Ok(());
+                }
+                Err(err @ InnerSocketError::ConnectionClosed(_))
+                | Err(err @ InnerSocketError::Io(_)) => return Err(err),
+                Err(err) => {
+                    error!("Error while trying to write an outgoing message: {}", err);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Queues a close frame and sends as much as possible. Returns an error if the
+    // connection should be dropped.
+    fn start_close(
+        &mut self,
+        frame: CloseFrame<'static>,
+    ) -> std::result::Result<(), InnerSocketError> {
+        self.pending_close = Some(frame);
+        self.flush()
+    }
+
+    // Reports an error that ends the connection and removes the connection.
+    fn fail(&mut self, err: InnerSocketError, core: &mut Core) {
+        // both the read and the write callback may run into the end of the connection.
+        if let State::Closed = self.state {
+            return;
+        }
+        self.state = State::Closed;
+        match err {
+            InnerSocketError::ConnectionClosed(frame) => {
                 info!("Connection closed.");
-                core.remove(self.object_id);
+                let (code, reason) = match frame {
+                    Some(frame) => (frame.code, frame.reason.into_owned()),
+                    None => (CloseCode::Status, String::new()),
+                };
+                self.handler.on_close(code, &reason, core);
+            }
+            InnerSocketError::SendQueueFull(_) => {
+                warn!("Outgoing queue full, closing connection.");
+                self.handler.on_error(&err, core);
+                core.report_error(self.object_id, &err);
+            }
+            err => {
+                error!("IO error on websocket connection: {}", err);
+                self.handler.on_error(&err, core);
+                core.report_error(self.object_id, &err);
+            }
+        }
+        core.remove(self.object_id);
+    }
+
+    fn write_all(&mut self, core: &mut Core) {
+        match self.handshake(core) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(err) => {
+                self.fail(err, core);
+                return;
+            }
+        }
+        let had_queued = !self.outgoing.is_empty();
+        match self.flush() {
+            Err(err) => self.fail(err, core),
+            Ok(()) if had_queued && self.outgoing.is_empty() => {
+                debug!("Successfully flushed pending messages to send.");
+                self.handler.on_drained(core);
             }
-            Err(err) => error!("Error while trying to write an outgoing message: {}", err),
-            Ok(()) => debug!("Successfully flushed pending messages to send."),
+            Ok(()) => {}
+        }
+    }
+}
+
+impl LoopObject for WebSocket {
+    fn on_remove(&mut self, core: &mut Core) {
+        // lets the server accept another connection if it is at its limit.
+        if let Some(server_id) = self.server_id {
+            let connection_id = self.object_id;
+            core.post(server_id, move |server: &mut WebSocketServer, core| {
+                server.connection_closed(connection_id, core)
+            });
         }
     }
 }